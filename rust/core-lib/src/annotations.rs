@@ -27,6 +27,8 @@ use crate::xi_rope::{Interval, Rope};
 pub enum AnnotationType {
     Selection,
     Find,
+    Conflict,
+    Bracket,
     Other(String),
 }
 
@@ -35,6 +37,8 @@ impl AnnotationType {
         match self {
             AnnotationType::Find => "find",
             AnnotationType::Selection => "selection",
+            AnnotationType::Conflict => "conflict",
+            AnnotationType::Bracket => "bracket",
             AnnotationType::Other(ref s) => s,
         }
     }
@@ -0,0 +1,197 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Editing files inside zip archives.
+//!
+//! A URI such as `zip:///home/me/archive.zip/src/main.rs` names the entry
+//! `src/main.rs` of the archive at `/home/me/archive.zip`. Saving an entry
+//! rewrites the whole archive, copying the other entries as they are.
+
+use std::fs::File;
+use std::io::{self, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+use zip::result::ZipError;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::vfs::{self, FileSystem, LocalFileSystem, Metadata};
+
+/// The scheme of URIs that refer to entries in zip archives.
+pub const ZIP_SCHEME: &str = "zip";
+
+const ARCHIVE_EXTENSION: &str = ".zip/";
+
+/// Zip archives on the local disk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ZipFileSystem;
+
+impl ZipFileSystem {
+    /// Splits a `zip://` URI into the path of the archive and the name of
+    /// the entry in it.
+    fn split(path: &Path) -> io::Result<(PathBuf, String)> {
+        let invalid =
+            || io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} is not a zip URI", path));
+        let uri = path.to_str().ok_or_else(invalid)?;
+        if vfs::scheme(path) != Some(ZIP_SCHEME) {
+            return Err(invalid());
+        }
+        let rest = &uri[ZIP_SCHEME.len() + 3..];
+        // the extension is ASCII, so this finds it at a char boundary
+        let end = rest.to_ascii_lowercase().find(ARCHIVE_EXTENSION).ok_or_else(invalid)?
+            + ARCHIVE_EXTENSION.len();
+        let (archive, entry) = (&rest[..end - 1], &rest[end..]);
+        if entry.is_empty() {
+            return Err(invalid());
+        }
+        Ok((PathBuf::from(archive), entry.to_owned()))
+    }
+
+    fn open(archive: &Path) -> io::Result<ZipArchive<File>> {
+        Ok(ZipArchive::new(File::open(archive)?)?)
+    }
+}
+
+impl FileSystem for ZipFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let (archive, entry) = ZipFileSystem::split(path)?;
+        let mut archive = ZipFileSystem::open(&archive)?;
+        let mut file = archive.by_name(&entry).map_err(|e| entry_error(e, path))?;
+        let mut buf = Vec::with_capacity(file.size() as usize);
+        file.read_to_end(&mut buf)?;
+        Ok(Box::new(Cursor::new(buf)))
+    }
+
+    /// The archive is created if it doesn't exist. The entry keeps its
+    /// place, compression method and permissions; a new entry is added at
+    /// the end, deflated.
+    fn write(
+        &self,
+        path: &Path,
+        contents: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let (archive_path, entry) = ZipFileSystem::split(path)?;
+        let mut buf = Vec::new();
+        contents(&mut buf)?;
+
+        let mut archive = match ZipFileSystem::open(&archive_path) {
+            Ok(archive) => Some(archive),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e),
+        };
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let mut new_options = Some(FileOptions::default());
+        if let Some(archive) = archive.as_mut() {
+            writer.set_raw_comment(archive.comment().to_vec());
+            for i in 0..archive.len() {
+                let file = archive.by_index_raw(i)?;
+                if file.name() != entry {
+                    writer.raw_copy_file(file)?;
+                    continue;
+                }
+                let mut options = FileOptions::default().compression_method(file.compression());
+                if let Some(mode) = file.unix_mode() {
+                    options = options.unix_permissions(mode);
+                }
+                writer.start_file(entry.as_str(), options)?;
+                writer.write_all(&buf)?;
+                new_options = None;
+            }
+        }
+        if let Some(options) = new_options {
+            writer.start_file(
+                entry.as_str(),
+                options.compression_method(CompressionMethod::Deflated),
+            )?;
+            writer.write_all(&buf)?;
+        }
+        let bytes = writer.finish()?.into_inner();
+        LocalFileSystem.write(&archive_path, &mut |w| w.write_all(&bytes))
+    }
+
+    /// The modification time and permissions are those of the archive.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let (archive_path, entry) = ZipFileSystem::split(path)?;
+        let archive_metadata = LocalFileSystem.metadata(&archive_path)?;
+        let mut archive = ZipFileSystem::open(&archive_path)?;
+        let file = archive.by_name(&entry).map_err(|e| entry_error(e, path))?;
+        Ok(Metadata {
+            len: file.size(),
+            mod_time: archive_metadata.mod_time,
+            read_only: archive_metadata.read_only,
+            permissions: None,
+        })
+    }
+}
+
+fn entry_error(error: ZipError, path: &Path) -> io::Error {
+    match error {
+        ZipError::FileNotFound => {
+            io::Error::new(io::ErrorKind::NotFound, format!("no such file: {:?}", path))
+        }
+        e => e.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn read_entry(archive: &Path, name: &str) -> String {
+        let mut archive = ZipFileSystem::open(archive).unwrap();
+        let mut contents = String::new();
+        archive.by_name(name).unwrap().read_to_string(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn split_uris() {
+        let split = |uri: &str| ZipFileSystem::split(Path::new(uri)).ok();
+        let expected = |archive: &str, entry: &str| Some((archive.into(), entry.to_owned()));
+        assert_eq!(split("zip:///tmp/a.zip/b/c.txt"), expected("/tmp/a.zip", "b/c.txt"));
+        assert_eq!(split("zip://a.ZIP/b.zip/c"), expected("a.ZIP", "b.zip/c"));
+        assert_eq!(split("zip:///tmp/a.zip/"), None);
+        assert_eq!(split("zip:///tmp/a.tar/b"), None);
+        assert_eq!(split("/tmp/a.zip/b"), None);
+    }
+
+    #[test]
+    fn read_and_write_entries() {
+        let tmp = TempDir::new("xi-test-zip").unwrap();
+        let archive = tmp.path().join("archive.zip");
+        let uri = |name: &str| PathBuf::from(format!("zip://{}/{}", archive.display(), name));
+        let fs = ZipFileSystem;
+        let write = |name: &str, text: &str| {
+            fs.write(&uri(name), &mut |w| w.write_all(text.as_bytes())).unwrap();
+        };
+
+        assert_eq!(fs.read(&uri("a.txt")).err().map(|e| e.kind()), Some(io::ErrorKind::NotFound));
+        write("a.txt", "first");
+        write("dir/b.txt", "second");
+        write("a.txt", "changed");
+
+        let mut contents = String::new();
+        fs.read(&uri("a.txt")).unwrap().read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "changed");
+        assert_eq!(read_entry(&archive, "dir/b.txt"), "second");
+        let mut zip = ZipFileSystem::open(&archive).unwrap();
+        let names = (0..zip.len()).map(|i| zip.by_index(i).unwrap().name().to_owned());
+        assert_eq!(names.collect::<Vec<_>>(), vec!["a.txt", "dir/b.txt"]);
+
+        assert_eq!(fs.metadata(&uri("dir/b.txt")).unwrap().len, 6);
+        let missing = fs.metadata(&uri("c.txt")).err().map(|e| e.kind());
+        assert_eq!(missing, Some(io::ErrorKind::NotFound));
+    }
+}
@@ -0,0 +1,176 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Auto-closing of brackets and quotes.
+//!
+//! When the opener of one of the `auto_close_pairs` is typed at a caret, its
+//! closer is inserted after the caret. Typing the closer then moves over the
+//! inserted one instead of adding another, for as long as it stays where it
+//! was inserted.
+
+use xi_rope::engine::RevId;
+use xi_rope::{Cursor, Interval, Rope, RopeDelta, Transformer};
+
+/// The number of inserted closers that are remembered.
+const MAX_AUTO_CLOSERS: usize = 64;
+
+/// The closers inserted by auto-closing that haven't been typed over.
+#[derive(Debug, Default)]
+pub(crate) struct AutoClosers {
+    /// The revision the closers' positions are in. If the buffer is changed
+    /// in another way, such as by an undo, they are forgotten.
+    rev: Option<RevId>,
+    closers: Vec<Interval>,
+}
+
+impl AutoClosers {
+    /// Updates the positions of the closers after `delta`, which made
+    /// revision `after` from `before`. Closers it changes are forgotten.
+    pub(crate) fn apply_delta(&mut self, before: RevId, after: RevId, delta: &RopeDelta) {
+        if self.rev != Some(before) {
+            self.closers.clear();
+        }
+        let mut transformer = Transformer::new(delta);
+        self.closers = self
+            .closers
+            .iter()
+            .filter_map(|iv| {
+                let start = transformer.transform(iv.start(), true);
+                let end = transformer.transform(iv.end(), false);
+                match end.saturating_sub(start) == iv.size() {
+                    true => Some(Interval::new(start, end)),
+                    false => None,
+                }
+            })
+            .collect();
+        self.rev = Some(after);
+    }
+
+    /// Remembers a closer inserted in the revision `rev`.
+    pub(crate) fn add(&mut self, rev: RevId, closer: Interval) {
+        if self.rev != Some(rev) {
+            self.closers.clear();
+            self.rev = Some(rev);
+        }
+        self.closers.push(closer);
+        if self.closers.len() > MAX_AUTO_CLOSERS {
+            self.closers.remove(0);
+        }
+    }
+
+    /// Returns `true` if `closer` was inserted by auto-closing, and is
+    /// unchanged in the revision `rev`.
+    pub(crate) fn contains(&self, rev: RevId, closer: Interval) -> bool {
+        self.rev == Some(rev) && self.closers.contains(&closer)
+    }
+
+    /// Forgets `closer`, once it has been typed over.
+    pub(crate) fn remove(&mut self, closer: Interval) {
+        self.closers.retain(|&iv| iv != closer);
+    }
+}
+
+/// Returns `true` if typing the opener of `pair` at `offset` should insert
+/// its closer too. That is the case before whitespace or a closing bracket,
+/// and, for quotes, if the opener doesn't follow a word.
+pub(crate) fn should_close(
+    text: &Rope,
+    offset: usize,
+    pair: &(String, String),
+    pairs: &[(String, String)],
+) -> bool {
+    let mut cursor = Cursor::new(text, offset);
+    let next_ok = match cursor.peek_next_codepoint() {
+        None => true,
+        Some(c) if c.is_whitespace() => true,
+        Some(c) => pairs.iter().any(|(open, close)| open != close && close.starts_with(c)),
+    };
+    let prev_ok = match (pair.0 == pair.1, cursor.prev_codepoint()) {
+        (true, Some(c)) => !(c.is_alphanumeric() || c == '_' || pair.0.starts_with(c)),
+        _ => true,
+    };
+    next_ok && prev_ok
+}
+
+/// Returns `true` if the caret at `offset` is between the opener and closer
+/// of `pair`.
+pub(crate) fn is_in_empty_pair(text: &Rope, offset: usize, pair: &(String, String)) -> bool {
+    let (open, close) = (pair.0.len(), pair.1.len());
+    offset >= open
+        && offset + close <= text.len()
+        && text.is_codepoint_boundary(offset - open)
+        && text.is_codepoint_boundary(offset + close)
+        && text.slice_to_cow(offset - open..offset) == pair.0
+        && text.slice_to_cow(offset..offset + close) == pair.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use xi_rope::engine::Engine;
+    use xi_rope::DeltaBuilder;
+
+    fn pairs() -> Vec<(String, String)> {
+        [("(", ")"), ("[", "]"), ("'", "'")]
+            .iter()
+            .map(|&(open, close)| (open.to_owned(), close.to_owned()))
+            .collect()
+    }
+
+    #[test]
+    fn when_to_close() {
+        let pairs = pairs();
+        let (paren, quote) = (&pairs[0], &pairs[2]);
+        let text = Rope::from("a b) x");
+        assert!(!should_close(&text, 0, paren, &pairs));
+        assert!(should_close(&text, 1, paren, &pairs));
+        assert!(should_close(&text, 3, paren, &pairs));
+        assert!(should_close(&text, 6, paren, &pairs));
+        assert!(!should_close(&text, 1, quote, &pairs));
+        assert!(should_close(&text, 4, quote, &pairs));
+        assert!(!should_close(&text, 6, quote, &pairs));
+
+        let text = Rope::from("f('')");
+        assert!(!is_in_empty_pair(&text, 2, paren));
+        assert!(is_in_empty_pair(&text, 3, quote));
+        assert!(!is_in_empty_pair(&text, 4, quote));
+    }
+
+    #[test]
+    fn closers_follow_edits() {
+        let mut engine = Engine::new(Rope::from("()"));
+        let rev = engine.get_head_rev_id();
+        let mut closers = AutoClosers::default();
+        closers.add(rev, Interval::new(1, 2));
+
+        // typing inside the pair moves the closer
+        let mut builder = DeltaBuilder::new(2);
+        builder.replace(Interval::new(1, 1), Rope::from("ab"));
+        let delta = builder.build();
+        engine.edit_rev(1, 1, rev.token(), delta.clone());
+        let next = engine.get_head_rev_id();
+        closers.apply_delta(rev, next, &delta);
+        assert!(closers.contains(next, Interval::new(3, 4)));
+        assert!(!closers.contains(rev, Interval::new(3, 4)));
+
+        // replacing it forgets it
+        let mut builder = DeltaBuilder::new(4);
+        builder.replace(Interval::new(3, 4), Rope::from(")"));
+        let delta = builder.build();
+        engine.edit_rev(1, 1, next.token(), delta.clone());
+        let last = engine.get_head_rev_id();
+        closers.apply_delta(next, last, &delta);
+        assert!(!closers.contains(last, Interval::new(3, 4)));
+    }
+}
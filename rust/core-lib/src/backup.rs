@@ -0,0 +1,221 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Backup copies of files, written before a save overwrites them.
+//!
+//! Backups are controlled by the `backup_on_save` setting. Backups written
+//! to the backup directory are named after the full path of the file, with
+//! path separators, `:` and `%` percent-encoded, so that files with the same
+//! name in different directories don't overwrite each other's backups.
+//! Timestamps in backup names are in UTC.
+
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::BufferItems;
+
+/// Where, if anywhere, a backup is written when a file is saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupMode {
+    /// No backup is written.
+    None,
+    /// The backup is written beside the file, as `file~`.
+    Beside,
+    /// The backup is written to the backup directory.
+    Directory,
+    /// A timestamped backup is written to the backup directory each time
+    /// the file is saved, keeping the most recent `backup_versions`.
+    Versions,
+}
+
+/// The backup settings that apply to a save.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BackupSettings {
+    pub mode: BackupMode,
+    /// The backup directory. If `None`, backups that would be written
+    /// there are written beside the file instead.
+    pub dir: Option<PathBuf>,
+    /// The number of timestamped backups kept in `Versions` mode.
+    pub versions: usize,
+}
+
+impl BackupSettings {
+    /// The settings for a buffer with the config `items`. `default_dir` is
+    /// used if the `backup_directory` setting is empty.
+    pub fn new(items: &BufferItems, default_dir: Option<PathBuf>) -> Self {
+        let dir = if items.backup_directory.is_empty() {
+            default_dir
+        } else {
+            Some(PathBuf::from(&items.backup_directory))
+        };
+        BackupSettings { mode: items.backup_on_save, dir, versions: items.backup_versions }
+    }
+
+    /// Settings that never write a backup.
+    pub fn disabled() -> Self {
+        BackupSettings { mode: BackupMode::None, dir: None, versions: 0 }
+    }
+}
+
+/// Copies the file at `path`, if it exists, to wherever `settings` says its
+/// backup belongs. Returns the path of the backup, if one was written.
+pub(crate) fn write_backup(path: &Path, settings: &BackupSettings) -> io::Result<Option<PathBuf>> {
+    if settings.mode == BackupMode::None || !path.is_file() {
+        return Ok(None);
+    }
+    let file_name = match path.file_name() {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+    let (dir, name) = match (settings.mode, &settings.dir) {
+        (BackupMode::Directory, Some(dir)) | (BackupMode::Versions, Some(dir)) => {
+            fs::create_dir_all(dir)?;
+            (dir.clone(), encode_path(&path.canonicalize()?))
+        }
+        _ => (path.parent().map(Path::to_owned).unwrap_or_default(), file_name.to_owned()),
+    };
+
+    let mut backup_name = name.clone();
+    if settings.mode == BackupMode::Versions {
+        backup_name.push(".");
+        backup_name.push(timestamp());
+    }
+    backup_name.push("~");
+    let backup_path = dir.join(backup_name);
+    fs::copy(path, &backup_path)?;
+
+    if settings.mode == BackupMode::Versions {
+        remove_old_versions(&dir, &name, settings.versions.max(1))?;
+    }
+    Ok(Some(backup_path))
+}
+
+/// Removes all but the `keep` most recent timestamped backups of the file
+/// whose backups are named after `name`.
+fn remove_old_versions(dir: &Path, name: &OsString, keep: usize) -> io::Result<()> {
+    let prefix = format!("{}.", name.to_string_lossy());
+    let mut versions = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|file_name| {
+            file_name.starts_with(&prefix)
+                && file_name.ends_with('~')
+                && is_timestamp(&file_name[prefix.len()..file_name.len() - 1])
+        })
+        .collect::<Vec<_>>();
+    // timestamps sort chronologically
+    versions.sort();
+    let n_old = versions.len().saturating_sub(keep);
+    for old in &versions[..n_old] {
+        fs::remove_file(dir.join(old))?;
+    }
+    Ok(())
+}
+
+/// Returns a name for the file at `path` that is unique to that path.
+fn encode_path(path: &Path) -> OsString {
+    let mut encoded = String::new();
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '%' | '/' | '\\' | ':' => encoded.push_str(&format!("%{:02X}", c as u32)),
+            c => encoded.push(c),
+        }
+    }
+    OsString::from(encoded)
+}
+
+/// The current time in UTC, formatted as `YYYYMMDD-HHMMSS.mmm`.
+fn timestamp() -> String {
+    let now = time::now_utc();
+    let secs = time::strftime("%Y%m%d-%H%M%S", &now).unwrap();
+    format!("{}.{:03}", secs, now.tm_nsec / 1_000_000)
+}
+
+fn is_timestamp(s: &str) -> bool {
+    s.len() == "YYYYMMDD-HHMMSS.mmm".len()
+        && s.bytes().all(|b| b.is_ascii_digit() || b == b'-' || b == b'.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::MAIN_SEPARATOR;
+    use tempdir::TempDir;
+
+    fn settings(mode: BackupMode, dir: Option<&Path>) -> BackupSettings {
+        BackupSettings { mode, dir: dir.map(Path::to_owned), versions: 2 }
+    }
+
+    #[test]
+    fn backup_beside() {
+        let tmp = TempDir::new("xi-test-backup").unwrap();
+        let path = tmp.path().join("file.txt");
+        let settings = settings(BackupMode::Beside, None);
+        assert_eq!(write_backup(&path, &settings).unwrap(), None);
+
+        fs::write(&path, "original").unwrap();
+        let backup = write_backup(&path, &settings).unwrap().unwrap();
+        assert_eq!(backup, tmp.path().join("file.txt~"));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "original");
+
+        let disabled = BackupSettings::disabled();
+        assert_eq!(write_backup(&path, &disabled).unwrap(), None);
+    }
+
+    #[test]
+    fn backup_to_directory() {
+        let tmp = TempDir::new("xi-test-backup-dir").unwrap();
+        let path = tmp.path().join("file.txt");
+        let dir = tmp.path().join("backups");
+        fs::write(&path, "original").unwrap();
+
+        let backup = write_backup(&path, &settings(BackupMode::Directory, Some(&dir))).unwrap();
+        let backup = backup.unwrap();
+        assert_eq!(backup.parent(), Some(dir.as_path()));
+        let name = backup.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.ends_with("%2Ffile.txt~") || name.ends_with("%5Cfile.txt~"));
+        assert!(!name.contains(MAIN_SEPARATOR));
+        assert_eq!(fs::read_to_string(&backup).unwrap(), "original");
+    }
+
+    #[test]
+    fn encode_paths() {
+        assert_eq!(encode_path(Path::new("/a/b.txt")), "%2Fa%2Fb.txt");
+        // a '%' in the path can't be mistaken for a separator
+        assert_eq!(encode_path(Path::new("/a/%b")), "%2Fa%2F%25b");
+        assert_eq!(encode_path(Path::new("/a%/b")), "%2Fa%25%2Fb");
+    }
+
+    #[test]
+    fn rotate_versions() {
+        let tmp = TempDir::new("xi-test-backup-versions").unwrap();
+        let path = tmp.path().join("file.txt");
+        let dir = tmp.path().join("backups");
+        let settings = settings(BackupMode::Versions, Some(&dir));
+        let mut backups = Vec::new();
+        for i in 0..4 {
+            fs::write(&path, i.to_string()).unwrap();
+            backups.push(write_backup(&path, &settings).unwrap().unwrap());
+            // make sure the timestamps differ
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        assert!(!backups[1].exists());
+        assert_eq!(fs::read_to_string(&backups[2]).unwrap(), "2");
+        assert_eq!(fs::read_to_string(&backups[3]).unwrap(), "3");
+    }
+}
@@ -0,0 +1,182 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Matching of brackets.
+//!
+//! The bracket matching the one next to a caret is found by scanning the
+//! text from it, counting the brackets of the same kind in between. When a
+//! syntax plugin provides scopes, brackets in strings and comments are only
+//! matched with each other, so that a `)` in a string doesn't close a `(`
+//! in code.
+
+use xi_rope::{Interval, Rope};
+
+use crate::annotations::{AnnotationSlice, AnnotationType, ToAnnotation};
+use crate::layers::Layers;
+use crate::view::View;
+
+/// The pairs of brackets that are matched.
+const BRACKETS: &[(u8, u8)] = &[(b'(', b')'), (b'[', b']'), (b'{', b'}')];
+
+/// How far from a bracket its match is looked for, in bytes.
+const MAX_SCAN: usize = 100_000;
+
+/// A bracket and the bracket matching it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct BracketMatch {
+    /// The offset of the bracket next to the caret.
+    pub(crate) bracket: usize,
+    /// The offset of the bracket matching it.
+    pub(crate) matching: usize,
+}
+
+impl BracketMatch {
+    /// Returns the interval from the opening bracket to the closing one,
+    /// including both.
+    pub(crate) fn outer(&self) -> Interval {
+        Interval::new(self.bracket.min(self.matching), self.bracket.max(self.matching) + 1)
+    }
+}
+
+/// The bracket matches of a view's carets, which are highlighted.
+#[derive(Debug, Default)]
+pub(crate) struct BracketMatches {
+    items: Vec<BracketMatch>,
+}
+
+impl BracketMatches {
+    /// Finds the brackets matching those next to `carets`.
+    pub(crate) fn find<I>(text: &Rope, layers: &Layers, carets: I) -> Self
+    where
+        I: IntoIterator<Item = usize>,
+    {
+        let items = carets.into_iter().filter_map(|c| match_at_caret(text, layers, c)).collect();
+        BracketMatches { items }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+impl ToAnnotation for BracketMatches {
+    fn get_annotations(&self, interval: Interval, view: &View, text: &Rope) -> AnnotationSlice {
+        let mut offsets = self
+            .items
+            .iter()
+            .flat_map(|m| vec![m.bracket, m.matching])
+            .filter(|&offset| interval.start() <= offset && offset < interval.end())
+            .collect::<Vec<_>>();
+        offsets.sort();
+        offsets.dedup();
+        let ranges = offsets
+            .into_iter()
+            .map(|offset| {
+                let (line, col) = view.offset_to_line_col(text, offset);
+                [line, col, line, col + 1]
+            })
+            .collect();
+        AnnotationSlice::new(AnnotationType::Bracket, ranges, None)
+    }
+}
+
+/// Returns the match of the bracket after the caret at `offset`, or failing
+/// that, of the bracket before it.
+pub(crate) fn match_at_caret(text: &Rope, layers: &Layers, offset: usize) -> Option<BracketMatch> {
+    let after = Some(offset).filter(|&offset| offset < text.len());
+    after.into_iter().chain(offset.checked_sub(1)).find_map(|bracket| {
+        find_matching(text, layers, bracket).map(|matching| BracketMatch { bracket, matching })
+    })
+}
+
+/// Returns the offset of the bracket matching the one at `offset`, or `None`
+/// if there isn't a bracket there or it is unmatched.
+pub(crate) fn find_matching(text: &Rope, layers: &Layers, offset: usize) -> Option<usize> {
+    let byte = text.byte_at(offset);
+    let (same, other, forward) = BRACKETS.iter().find_map(|&(open, close)| match byte {
+        b if b == open => Some((open, close, true)),
+        b if b == close => Some((close, open, false)),
+        _ => None,
+    })?;
+    let in_literal = is_in_string_or_comment(layers, offset);
+    let is_candidate = |pos: usize, b: u8| {
+        (b == same || b == other) && is_in_string_or_comment(layers, pos) == in_literal
+    };
+
+    // brackets are ASCII, so their bytes can't be part of another codepoint
+    let mut depth = 0;
+    let mut step = |(pos, b): (usize, u8)| {
+        if !is_candidate(pos, b) {
+            None
+        } else if b == same {
+            depth += 1;
+            None
+        } else if depth == 0 {
+            Some(pos)
+        } else {
+            depth -= 1;
+            None
+        }
+    };
+    if forward {
+        let start = offset + 1;
+        let end = text.at_or_prev_codepoint_boundary((start + MAX_SCAN).min(text.len()))?;
+        let window = text.slice_to_cow(start..end);
+        window.bytes().enumerate().map(|(i, b)| (start + i, b)).find_map(&mut step)
+    } else {
+        let start = text.at_or_next_codepoint_boundary(offset.saturating_sub(MAX_SCAN))?;
+        let window = text.slice_to_cow(start..offset);
+        window.bytes().enumerate().rev().map(|(i, b)| (start + i, b)).find_map(&mut step)
+    }
+}
+
+/// Returns `true` if a syntax plugin's scopes put the character at `offset`
+/// in a string or a comment.
+fn is_in_string_or_comment(layers: &Layers, offset: usize) -> bool {
+    layers.has_scope_at(offset, "string") || layers.has_scope_at(offset, "comment")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matching(text: &str, offset: usize) -> Option<usize> {
+        find_matching(&Rope::from(text), &Layers::default(), offset)
+    }
+
+    #[test]
+    fn find_matching_brackets() {
+        let text = "f(a[0], (b)) {}";
+        assert_eq!(matching(text, 1), Some(11));
+        assert_eq!(matching(text, 11), Some(1));
+        assert_eq!(matching(text, 3), Some(5));
+        assert_eq!(matching(text, 8), Some(10));
+        assert_eq!(matching(text, 13), Some(14));
+        assert_eq!(matching(text, 0), None);
+        assert_eq!(matching("(()", 0), None);
+        assert_eq!(matching("\u{4e2d}(\u{4e2d})", 3), Some(7));
+    }
+
+    #[test]
+    fn match_next_to_caret() {
+        let text = Rope::from("(a)(b)");
+        let layers = Layers::default();
+        let at = |offset| match_at_caret(&text, &layers, offset).map(|m| (m.bracket, m.matching));
+        assert_eq!(at(0), Some((0, 2)));
+        // the bracket after the caret comes first
+        assert_eq!(at(3), Some((3, 5)));
+        assert_eq!(at(6), Some((5, 3)));
+        assert_eq!(at(1), Some((0, 2)));
+    }
+}
@@ -14,12 +14,14 @@
 
 //! Requests and notifications from the core to front-ends.
 
+use std::path::Path;
 use std::time::Instant;
 
 use serde_json::{self, Value};
 use xi_rpc::{self, RpcPeer};
 
 use crate::config::Table;
+use crate::line_ending::LineEnding;
 use crate::plugins::rpc::ClientPluginInfo;
 use crate::plugins::Command;
 use crate::styles::ThemeSettings;
@@ -28,6 +30,7 @@ use crate::tabs::ViewId;
 use crate::width_cache::{WidthReq, WidthResponse};
 
 /// An interface to the frontend.
+#[derive(Clone)]
 pub struct Client(RpcPeer);
 
 impl Client {
@@ -94,6 +97,75 @@ impl Client {
         );
     }
 
+    /// Notify the client that the file opened in this view contained a mix
+    /// of line endings. They have been normalized, and will be saved as
+    /// `line_ending`.
+    pub fn mixed_line_endings(&self, view_id: ViewId, line_ending: LineEnding) {
+        self.0.send_rpc_notification(
+            "mixed_line_endings",
+            &json!({
+                "view_id": view_id,
+                "line_ending": line_ending,
+            }),
+        );
+    }
+
+    /// Notify the client of the progress of loading the file for this view.
+    /// Large files are loaded in the background; the view is not usable
+    /// until loading is finished.
+    pub fn load_progress(&self, view_id: ViewId, bytes_loaded: u64, total_bytes: u64) {
+        self.0.send_rpc_notification(
+            "load_progress",
+            &json!({
+                "view_id": view_id,
+                "bytes_loaded": bytes_loaded,
+                "total_bytes": total_bytes,
+            }),
+        );
+    }
+
+    /// Notify the client that core closed this view, because the file being
+    /// loaded for it in the background could not be read.
+    pub fn close_view(&self, view_id: ViewId) {
+        self.0.send_rpc_notification("close_view", &json!({ "view_id": view_id }));
+    }
+
+    /// Notify the client that the file opened in this view is binary, and
+    /// is shown as a hex dump with `bytes_per_line` bytes on each line.
+    pub fn hex_view(&self, view_id: ViewId, bytes_per_line: usize) {
+        self.0.send_rpc_notification(
+            "hex_view",
+            &json!({
+                "view_id": view_id,
+                "bytes_per_line": bytes_per_line,
+            }),
+        );
+    }
+
+    /// Notify the client that the file opened in this view is large enough
+    /// that some features have been disabled for it.
+    pub fn large_file_mode(&self, view_id: ViewId, disabled: &[&str]) {
+        self.0.send_rpc_notification(
+            "large_file_mode",
+            &json!({
+                "view_id": view_id,
+                "disabled": disabled,
+            }),
+        );
+    }
+
+    /// Notify the client that the buffer shown in this view has become
+    /// read-only, or writable again.
+    pub fn read_only(&self, view_id: ViewId, read_only: bool) {
+        self.0.send_rpc_notification(
+            "read_only",
+            &json!({
+                "view_id": view_id,
+                "read_only": read_only,
+            }),
+        );
+    }
+
     /// Notify the client that a plugin has started.
     pub fn plugin_started(&self, view_id: ViewId, plugin: &str) {
         self.0.send_rpc_notification(
@@ -172,6 +244,23 @@ impl Client {
         Ok(serde_json::from_value(resp).expect("failed to deserialize width response"))
     }
 
+    /// Ask front-end whether to restore unsaved changes to the file open in
+    /// this view, which were found in a recovery file. The front-end should
+    /// respond with a boolean; `callback` is called with the response.
+    pub fn offer_recovery<F>(&self, view_id: ViewId, path: &Path, callback: F)
+    where
+        F: FnOnce(Result<Value, xi_rpc::Error>) + Send + 'static,
+    {
+        self.0.send_rpc_request_async(
+            "offer_recovery",
+            &json!({
+                "view_id": view_id,
+                "path": path,
+            }),
+            Box::new(callback),
+        );
+    }
+
     pub fn alert<S: AsRef<str>>(&self, msg: S) {
         self.0.send_rpc_notification("alert", &json!({ "msg": msg.as_ref() }));
     }
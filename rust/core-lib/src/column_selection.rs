@@ -0,0 +1,289 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Column (rectangular) selections.
+//!
+//! A column selection is a block between two corners, each a line and a
+//! horizontal position. It becomes one selection region per line, covering
+//! the text between the block's left and right edges. Horizontal positions
+//! are measured with the same widths as line wrapping, so that wide
+//! characters and tabs line up the way they are drawn.
+
+use xi_rope::Rope;
+
+use crate::selection::{SelRegion, Selection};
+use crate::styles::N_RESERVED_STYLES;
+use crate::width_cache::{CodepointMono, WidthCache, WidthMeasure, WidthReq, WidthResponse};
+
+/// Used when comparing horizontal positions, which are sums of widths.
+const EPSILON: f64 = 1e-6;
+
+/// Measures the horizontal positions of text in a line.
+pub(crate) struct ColumnMeasure<'a> {
+    width_cache: &'a mut WidthCache,
+    client: &'a dyn WidthMeasure,
+    tab_size: usize,
+}
+
+/// Falls back to counting codepoints if the client can't measure text.
+struct OrCodepoints<'a>(&'a dyn WidthMeasure);
+
+impl<'a> WidthMeasure for OrCodepoints<'a> {
+    fn measure_width(&self, request: &[WidthReq]) -> Result<WidthResponse, xi_rpc::Error> {
+        self.0.measure_width(request).or_else(|e| {
+            warn!("measure_width failed, counting codepoints instead: {:?}", e);
+            CodepointMono.measure_width(request)
+        })
+    }
+}
+
+/// The horizontal positions of a line's grapheme boundaries.
+#[derive(Debug)]
+pub(crate) struct LinePositions {
+    /// `(offset, x)` pairs, starting with the start of the line at 0.
+    positions: Vec<(usize, f64)>,
+    /// The width of a space, which positions past the end of the line
+    /// are measured in.
+    space_width: f64,
+}
+
+impl<'a> ColumnMeasure<'a> {
+    pub(crate) fn new(
+        width_cache: &'a mut WidthCache,
+        client: &'a dyn WidthMeasure,
+        tab_size: usize,
+    ) -> Self {
+        ColumnMeasure { width_cache, client, tab_size }
+    }
+
+    /// Measures the line `start..end`, which doesn't include a newline.
+    pub(crate) fn line_positions(
+        &mut self,
+        text: &Rope,
+        start: usize,
+        end: usize,
+    ) -> LinePositions {
+        let mut graphemes = Vec::new();
+        let mut offset = start;
+        while offset < end {
+            let next = text.next_grapheme_offset(offset).unwrap_or(end).min(end);
+            graphemes.push((next, text.slice_to_cow(offset..next)));
+            offset = next;
+        }
+
+        let mut req = self.width_cache.batch_req();
+        let space_tok = req.request(N_RESERVED_STYLES, " ");
+        let toks = graphemes
+            .iter()
+            .map(|(_, s)| if s == "\t" { None } else { Some(req.request(N_RESERVED_STYLES, s)) })
+            .collect::<Vec<_>>();
+        req.resolve_pending(&OrCodepoints(self.client)).unwrap();
+
+        let space_width = self.width_cache.resolve(space_tok);
+        let tab_width = space_width * self.tab_size as f64;
+        let mut x = 0.0;
+        let mut positions = vec![(start, x)];
+        for ((next, _), tok) in graphemes.iter().zip(toks) {
+            x = match tok {
+                Some(tok) => x + self.width_cache.resolve(tok),
+                // a tab moves to the next tab stop
+                None if tab_width > 0.0 => {
+                    ((x + EPSILON) / tab_width).floor() * tab_width + tab_width
+                }
+                None => x,
+            };
+            positions.push((*next, x));
+        }
+        LinePositions { positions, space_width }
+    }
+}
+
+impl LinePositions {
+    fn end(&self) -> (usize, f64) {
+        *self.positions.last().unwrap()
+    }
+
+    /// Returns the position of the byte column `col` in the line. Columns
+    /// past the end of the line are taken to be spaces.
+    pub(crate) fn x_of_col(&self, col: usize) -> f64 {
+        let start = self.positions[0].0;
+        let (end, end_x) = self.end();
+        if start + col >= end {
+            return end_x + (start + col - end) as f64 * self.space_width;
+        }
+        self.positions.iter().take_while(|&&(offset, _)| offset <= start + col).last().unwrap().1
+    }
+
+    /// Returns the position of `offset`, which is in the line.
+    pub(crate) fn x_of_offset(&self, offset: usize) -> f64 {
+        self.x_of_col(offset.saturating_sub(self.positions[0].0))
+    }
+
+    /// Returns the boundary nearest to the position `x`, or the end of the
+    /// line if `x` is past it.
+    pub(crate) fn offset_at_x(&self, x: f64) -> usize {
+        let mut best = self.positions[0];
+        for &(offset, pos_x) in &self.positions {
+            if (pos_x - x).abs() + EPSILON < (best.1 - x).abs() {
+                best = (offset, pos_x);
+            }
+        }
+        best.0
+    }
+
+    /// Returns the position one grapheme (or space) left of `x`.
+    pub(crate) fn prev_x(&self, x: f64) -> f64 {
+        let (_, end_x) = self.end();
+        if x > end_x + EPSILON {
+            return (x - self.space_width).max(end_x);
+        }
+        self.positions.iter().rev().map(|p| p.1).find(|&pos_x| pos_x < x - EPSILON).unwrap_or(0.0)
+    }
+
+    /// Returns the position one grapheme (or space) right of `x`.
+    pub(crate) fn next_x(&self, x: f64) -> f64 {
+        match self.positions.iter().map(|p| p.1).find(|&pos_x| pos_x > x + EPSILON) {
+            Some(next) => next,
+            None => x + self.space_width,
+        }
+    }
+}
+
+/// A rectangular block, between two corners given as `(line, x)`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ColumnBlock {
+    /// The corner where the selection started.
+    pub(crate) anchor: (usize, f64),
+    /// The corner that moves as the selection is extended.
+    pub(crate) active: (usize, f64),
+}
+
+impl ColumnBlock {
+    /// Returns the selection regions of the block, one per line. `line_range`
+    /// gives the start and end of a line, without its newline.
+    ///
+    /// Lines that don't reach into the block are skipped, unless no line
+    /// does, in which case each line gets a caret at its end. A block without
+    /// width only needs a line to reach its left edge.
+    /// Regions are reversed if the active corner is left of the anchor.
+    pub(crate) fn regions<F>(
+        &self,
+        text: &Rope,
+        measure: &mut ColumnMeasure,
+        line_range: F,
+    ) -> Selection
+    where
+        F: Fn(usize) -> (usize, usize),
+    {
+        let first = self.anchor.0.min(self.active.0);
+        let last = self.anchor.0.max(self.active.0);
+        let left = self.anchor.1.min(self.active.1);
+        let right = self.anchor.1.max(self.active.1);
+        let reversed = self.active.1 < self.anchor.1;
+        let has_width = right > left + EPSILON;
+
+        let mut selection = Selection::new();
+        let mut short_line_ends = Vec::new();
+        for line in first..=last {
+            let (start, end) = line_range(line);
+            let positions = measure.line_positions(text, start, end);
+            let end_x = positions.end().1;
+            let reaches = if has_width { end_x > left + EPSILON } else { end_x + EPSILON >= left };
+            if !reaches {
+                short_line_ends.push(end);
+                continue;
+            }
+            let (left_offset, right_offset) =
+                (positions.offset_at_x(left), positions.offset_at_x(right));
+            let region = match reversed {
+                true => SelRegion::new(right_offset, left_offset),
+                false => SelRegion::new(left_offset, right_offset),
+            };
+            selection.add_region(region);
+        }
+        if selection.is_empty() {
+            short_line_ends.into_iter().for_each(|end| selection.add_region(SelRegion::caret(end)));
+        }
+        selection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ideographs are two columns wide, everything else one.
+    struct Cells;
+
+    impl WidthMeasure for Cells {
+        fn measure_width(&self, request: &[WidthReq]) -> Result<WidthResponse, xi_rpc::Error> {
+            let width = |s: &String| {
+                s.chars()
+                    .map(|c| if ('\u{4e00}'..='\u{9fff}').contains(&c) { 2.0 } else { 1.0 })
+                    .sum()
+            };
+            Ok(request.iter().map(|r| r.strings.iter().map(width).collect()).collect())
+        }
+    }
+
+    fn line_range(text: &Rope) -> impl Fn(usize) -> (usize, usize) + '_ {
+        move |line| {
+            let start = text.offset_of_line(line);
+            let end = text.offset_of_line(line + 1);
+            match text.slice_to_cow(start..end).ends_with('\n') {
+                true => (start, end - 1),
+                false => (start, end),
+            }
+        }
+    }
+
+    fn regions(text: &str, anchor: (usize, f64), active: (usize, f64)) -> Vec<(usize, usize)> {
+        let text = Rope::from(text);
+        let mut width_cache = WidthCache::new();
+        let mut measure = ColumnMeasure::new(&mut width_cache, &Cells, 4);
+        let block = ColumnBlock { anchor, active };
+        let sel = block.regions(&text, &mut measure, line_range(&text));
+        sel.iter().map(|r| (r.start, r.end)).collect()
+    }
+
+    #[test]
+    fn positions() {
+        let text = Rope::from("a\tb\u{4e2d}c");
+        let mut width_cache = WidthCache::new();
+        let mut measure = ColumnMeasure::new(&mut width_cache, &Cells, 4);
+        let positions = measure.line_positions(&text, 0, text.len());
+        let xs = positions.positions.iter().map(|p| p.1).collect::<Vec<_>>();
+        assert_eq!(xs, vec![0.0, 1.0, 4.0, 5.0, 7.0, 8.0]);
+        assert_eq!(positions.x_of_col(3), 5.0);
+        assert_eq!(positions.x_of_col(10), 11.0);
+        assert_eq!(positions.offset_at_x(6.1), 6);
+        assert_eq!(positions.offset_at_x(2.0), 1);
+        assert_eq!(positions.offset_at_x(20.0), text.len());
+        assert_eq!(positions.prev_x(4.0), 1.0);
+        assert_eq!(positions.prev_x(10.0), 9.0);
+        assert_eq!(positions.next_x(5.0), 7.0);
+        assert_eq!(positions.next_x(8.0), 9.0);
+    }
+
+    #[test]
+    fn block() {
+        let text = "abcdef\nab\n\u{4e2d}\u{4e2d}\u{4e2d}\n\tx";
+        // columns 1 to 4
+        assert_eq!(regions(text, (0, 1.0), (3, 4.0)), vec![(1, 4), (8, 9), (10, 16), (20, 21)]);
+        // a ragged line is skipped, and the regions follow the active corner
+        assert_eq!(regions(text, (0, 5.0), (2, 3.0)), vec![(5, 3), (16, 13)]);
+        // unless no line is long enough
+        assert_eq!(regions(text, (0, 9.0), (1, 9.0)), vec![(6, 6), (9, 9)]);
+    }
+}
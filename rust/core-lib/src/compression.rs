@@ -0,0 +1,234 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Transparent decompression of compressed files.
+//!
+//! Compressed files are detected by their magic bytes when they are loaded,
+//! and their buffers hold the decompressed contents. They are compressed
+//! again, with the same codec, when saved.
+
+use std::io::{self, Chain, Cursor, Read, Write};
+use std::path::Path;
+
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use xz2::read::XzDecoder;
+use xz2::write::XzEncoder;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+/// The compression level used for xz, which is also the `xz` default.
+const XZ_LEVEL: u32 = 6;
+
+/// A compression codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Xz,
+}
+
+impl Compression {
+    /// Returns the codec of a file starting with `sample`, if it is
+    /// compressed.
+    pub fn detect(sample: &[u8]) -> Option<Self> {
+        if sample.starts_with(GZIP_MAGIC) {
+            Some(Compression::Gzip)
+        } else if sample.starts_with(XZ_MAGIC) {
+            Some(Compression::Xz)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the codec conventionally used for files named like `path`.
+    pub fn from_extension(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "gz" => Some(Compression::Gzip),
+            "xz" => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+}
+
+type Source = Chain<Cursor<Vec<u8>>, Box<dyn Read + Send>>;
+
+/// Reads a file, decompressing it if it is compressed.
+pub(crate) struct Decompressor {
+    compression: Option<Compression>,
+    inner: DecompressorInner,
+}
+
+enum DecompressorInner {
+    Plain(CountingReader),
+    Gzip(MultiGzDecoder<CountingReader>),
+    Xz(XzDecoder<CountingReader>),
+}
+
+/// Counts the bytes read from the file itself, before decompression.
+struct CountingReader {
+    inner: Source,
+    count: u64,
+}
+
+impl Decompressor {
+    /// Wraps `reader`, which reads a file from its start, detecting its
+    /// compression from its first bytes.
+    pub(crate) fn new(mut reader: Box<dyn Read + Send>) -> io::Result<Self> {
+        let mut magic = Vec::with_capacity(XZ_MAGIC.len());
+        (&mut reader).take(XZ_MAGIC.len() as u64).read_to_end(&mut magic)?;
+        let compression = Compression::detect(&magic);
+        let source = CountingReader { inner: Cursor::new(magic).chain(reader), count: 0 };
+        let inner = match compression {
+            None => DecompressorInner::Plain(source),
+            Some(Compression::Gzip) => DecompressorInner::Gzip(MultiGzDecoder::new(source)),
+            Some(Compression::Xz) => DecompressorInner::Xz(XzDecoder::new_multi_decoder(source)),
+        };
+        Ok(Decompressor { compression, inner })
+    }
+
+    /// Wraps `reader` without decompressing it, for a file that looked
+    /// compressed but turned out not to be.
+    pub(crate) fn plain(reader: Box<dyn Read + Send>) -> Self {
+        let source = CountingReader { inner: Cursor::new(Vec::new()).chain(reader), count: 0 };
+        Decompressor { compression: None, inner: DecompressorInner::Plain(source) }
+    }
+
+    pub(crate) fn compression(&self) -> Option<Compression> {
+        self.compression
+    }
+
+    /// The number of bytes read from the file so far. For compressed
+    /// files, this is less than the number of bytes decompressed.
+    pub(crate) fn bytes_read(&self) -> u64 {
+        match &self.inner {
+            DecompressorInner::Plain(r) => r.count,
+            DecompressorInner::Gzip(r) => r.get_ref().count,
+            DecompressorInner::Xz(r) => r.get_ref().count,
+        }
+    }
+}
+
+impl Read for Decompressor {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            DecompressorInner::Plain(r) => r.read(buf),
+            DecompressorInner::Gzip(r) => r.read(buf),
+            DecompressorInner::Xz(r) => r.read(buf),
+        }
+    }
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// Writes a file, compressing it with the given codec, if any. `finish`
+/// must be called once everything has been written.
+pub(crate) enum Compressor<'a> {
+    Plain(&'a mut dyn Write),
+    Gzip(GzEncoder<&'a mut dyn Write>),
+    Xz(XzEncoder<&'a mut dyn Write>),
+}
+
+impl<'a> Compressor<'a> {
+    pub(crate) fn new(w: &'a mut dyn Write, compression: Option<Compression>) -> Self {
+        match compression {
+            None => Compressor::Plain(w),
+            Some(Compression::Gzip) => {
+                Compressor::Gzip(GzEncoder::new(w, flate2::Compression::default()))
+            }
+            Some(Compression::Xz) => Compressor::Xz(XzEncoder::new(w, XZ_LEVEL)),
+        }
+    }
+
+    /// Writes any remaining compressed data.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self {
+            Compressor::Plain(_) => Ok(()),
+            Compressor::Gzip(w) => w.finish().map(|_| ()),
+            Compressor::Xz(w) => w.finish().map(|_| ()),
+        }
+    }
+}
+
+impl<'a> Write for Compressor<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Compressor::Plain(w) => w.write(buf),
+            Compressor::Gzip(w) => w.write(buf),
+            Compressor::Xz(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Compressor::Plain(w) => w.flush(),
+            Compressor::Gzip(w) => w.flush(),
+            Compressor::Xz(w) => w.flush(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compress(data: &[u8], compression: Option<Compression>) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut w = Compressor::new(&mut out, compression);
+        w.write_all(data).unwrap();
+        w.finish().unwrap();
+        out
+    }
+
+    fn decompress(data: Vec<u8>) -> (Option<Compression>, Vec<u8>, u64) {
+        let mut r = Decompressor::new(Box::new(Cursor::new(data))).unwrap();
+        let mut out = Vec::new();
+        r.read_to_end(&mut out).unwrap();
+        (r.compression(), out, r.bytes_read())
+    }
+
+    #[test]
+    fn round_trip() {
+        let data = "compress me ".repeat(100).into_bytes();
+        for &compression in &[None, Some(Compression::Gzip), Some(Compression::Xz)] {
+            let compressed = compress(&data, compression);
+            assert_eq!(Compression::detect(&compressed), compression);
+            let (detected, out, bytes_read) = decompress(compressed.clone());
+            assert_eq!(detected, compression);
+            assert_eq!(out, data);
+            assert_eq!(bytes_read, compressed.len() as u64);
+        }
+    }
+
+    #[test]
+    fn short_files() {
+        assert_eq!(decompress(Vec::new()), (None, Vec::new(), 0));
+        assert_eq!(decompress(vec![0x1f]), (None, vec![0x1f], 1));
+    }
+
+    #[test]
+    fn extensions() {
+        assert_eq!(Compression::from_extension(Path::new("a.json.gz")), Some(Compression::Gzip));
+        assert_eq!(Compression::from_extension(Path::new("log.xz")), Some(Compression::Xz));
+        assert_eq!(Compression::from_extension(Path::new("a.json")), None);
+        assert_eq!(Compression::from_extension(Path::new("gz")), None);
+    }
+}
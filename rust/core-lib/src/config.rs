@@ -24,6 +24,7 @@ use serde::de::{self, Deserialize};
 use serde_json::{self, Value};
 use toml;
 
+use crate::backup::BackupMode;
 use crate::syntax::{LanguageId, Languages};
 use crate::tabs::{BufferId, ViewId};
 
@@ -186,7 +187,13 @@ pub struct BufferItems {
     pub word_wrap: bool,
     pub autodetect_whitespace: bool,
     pub surrounding_pairs: Vec<(String, String)>,
+    pub auto_close_pairs: Vec<(String, String)>,
     pub save_with_newline: bool,
+    pub large_file_threshold: u64,
+    pub backup_on_save: BackupMode,
+    pub backup_directory: String,
+    pub backup_versions: usize,
+    pub persistent_undo: bool,
 }
 
 pub type BufferConfig = Config<BufferItems>;
@@ -402,6 +409,11 @@ impl ConfigManager {
         self.buffer_tags.get(&id).map(LanguageTag::resolve).unwrap()
     }
 
+    /// Returns the language the user has chosen for this buffer, if any.
+    pub(crate) fn get_language_override(&self, id: BufferId) -> Option<LanguageId> {
+        self.buffer_tags.get(&id).and_then(|tag| tag.user.clone())
+    }
+
     /// Set the available `LanguageDefinition`s. Overrides any previous values.
     pub fn set_languages(&mut self, languages: Languages) {
         // remove base configs for any removed languages
@@ -548,6 +560,39 @@ impl ConfigManager {
         None
     }
 
+    /// Path to the crash recovery sub directory inside config directory.
+    /// Creates one if not present.
+    pub(crate) fn get_recovery_dir(&self) -> Option<PathBuf> {
+        let recovery_dir = self.config_dir.as_ref().map(|p| p.join("recovery"));
+
+        if let Some(p) = recovery_dir {
+            if p.exists() {
+                return Some(p);
+            }
+            if fs::DirBuilder::new().create(&p).is_ok() {
+                return Some(p);
+            }
+        }
+        None
+    }
+
+    /// Path to the default backup directory inside the config directory.
+    /// It is created when a backup is first written to it.
+    pub(crate) fn get_backup_dir(&self) -> Option<PathBuf> {
+        self.config_dir.as_ref().map(|p| p.join("backups"))
+    }
+
+    /// Path to the undo history directory inside the config directory.
+    /// It is created when a history is first written to it.
+    pub(crate) fn get_undo_dir(&self) -> Option<PathBuf> {
+        self.config_dir.as_ref().map(|p| p.join("undo"))
+    }
+
+    /// Path to the session file inside the config directory.
+    pub(crate) fn get_session_path(&self) -> Option<PathBuf> {
+        self.config_dir.as_ref().map(|p| p.join("session.json"))
+    }
+
     /// Path to plugins sub directory inside config directory.
     /// Creates one if not present.
     pub(crate) fn get_plugins_dir(&self) -> Option<PathBuf> {
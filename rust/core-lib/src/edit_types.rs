@@ -18,6 +18,8 @@
 //! This simplifies code elsewhere, and makes it easier to route events to
 //! the editor or view as appropriate.
 
+use crate::line_ending::LineEnding;
+use crate::line_ops::SortOptions;
 use crate::movement::Movement;
 use crate::rpc::{
     EditNotification, FindQuery, GestureType, LineRange, MouseAction, Position,
@@ -26,7 +28,7 @@ use crate::rpc::{
 use crate::view::Size;
 
 /// Events that only modify view state
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub(crate) enum ViewEvent {
     Move(Movement),
     ModifySelection(Movement),
@@ -49,16 +51,44 @@ pub(crate) enum ViewEvent {
     SelectionForReplace,
     SelectionIntoLines,
     CollapseSelections,
+    UndoSelection,
+    RedoSelection,
+    ColumnSelect(Movement),
+    MoveToMatchingBracket,
+    SelectToMatchingBracket,
+}
+
+impl ViewEvent {
+    /// Returns `true` if this event moves the selection somewhere else
+    /// entirely, rather than by a small step, for the selection history.
+    pub(crate) fn is_jump(&self) -> bool {
+        match self {
+            ViewEvent::Move(Movement::StartOfDocument)
+            | ViewEvent::Move(Movement::EndOfDocument)
+            | ViewEvent::SelectAll
+            | ViewEvent::Click(_)
+            | ViewEvent::GotoLine { .. }
+            | ViewEvent::FindNext { .. }
+            | ViewEvent::FindPrevious { .. }
+            | ViewEvent::FindAll
+            | ViewEvent::MoveToMatchingBracket
+            | ViewEvent::SelectToMatchingBracket => true,
+            ViewEvent::Gesture { ty, .. } => *ty != GestureType::Drag,
+            _ => false,
+        }
+    }
 }
 
 /// Events that modify the buffer
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub(crate) enum BufferEvent {
     Delete { movement: Movement, kill: bool },
     Backspace,
     Transpose,
     Undo,
     Redo,
+    UndoTreeGoto(usize),
+    UndoToTime(u64),
     Uppercase,
     Lowercase,
     Capitalize,
@@ -72,18 +102,29 @@ pub(crate) enum BufferEvent {
     ReplaceNext,
     ReplaceAll,
     DuplicateLine,
+    MoveLinesUp,
+    MoveLinesDown,
+    JoinLines,
+    SortLines(SortOptions),
+    UniqueLines,
+    ReverseLines,
+    Reflow(usize),
     IncreaseNumber,
     DecreaseNumber,
+    SetLineEnding(LineEnding),
+    KeepMine,
+    TakeTheirs,
 }
 
 /// An event that needs special handling
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub(crate) enum SpecialEvent {
     DebugRewrap,
     DebugWrapWidth,
     DebugPrintSpans,
     Resize(Size),
     RequestLines(LineRange),
+    GotoOffset(u64),
     RequestHover { request_id: usize, position: Option<Position> },
     DebugToggleComment,
     Reindent,
@@ -92,13 +133,29 @@ pub(crate) enum SpecialEvent {
     ClearRecording(String),
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub(crate) enum EventDomain {
     View(ViewEvent),
     Buffer(BufferEvent),
     Special(SpecialEvent),
 }
 
+impl EventDomain {
+    /// Returns `true` if this event may change the text of the buffer.
+    /// Resolving a conflict by keeping the buffer's text doesn't count as an
+    /// edit, since the text stays the same.
+    pub(crate) fn is_edit(&self) -> bool {
+        match self {
+            EventDomain::Buffer(BufferEvent::KeepMine) => false,
+            EventDomain::Buffer(_) => true,
+            EventDomain::Special(SpecialEvent::DebugToggleComment)
+            | EventDomain::Special(SpecialEvent::Reindent)
+            | EventDomain::Special(SpecialEvent::PlayRecording(_)) => true,
+            _ => false,
+        }
+    }
+}
+
 impl From<BufferEvent> for EventDomain {
     fn from(src: BufferEvent) -> EventDomain {
         EventDomain::Buffer(src)
@@ -219,6 +276,7 @@ impl From<EditNotification> for EventDomain {
             Scroll(range) => ViewEvent::Scroll(range).into(),
             Resize(size) => SpecialEvent::Resize(size).into(),
             GotoLine { line } => ViewEvent::GotoLine { line }.into(),
+            GotoOffset { offset } => SpecialEvent::GotoOffset(offset).into(),
             RequestLines(range) => SpecialEvent::RequestLines(range).into(),
             Yank => BufferEvent::Yank.into(),
             Transpose => BufferEvent::Transpose.into(),
@@ -261,6 +319,8 @@ impl From<EditNotification> for EventDomain {
             },
             Undo => BufferEvent::Undo.into(),
             Redo => BufferEvent::Redo.into(),
+            UndoTreeGoto { node } => BufferEvent::UndoTreeGoto(node).into(),
+            UndoToTime { seconds_ago } => BufferEvent::UndoToTime(seconds_ago).into(),
             Find { chars, case_sensitive, regex, whole_words } =>
                 ViewEvent::Find { chars, case_sensitive, regex, whole_words }.into(),
             MultiFind { queries } =>
@@ -292,12 +352,33 @@ impl From<EditNotification> for EventDomain {
                 SpecialEvent::RequestHover { request_id, position }.into(),
             SelectionIntoLines => ViewEvent::SelectionIntoLines.into(),
             DuplicateLine => BufferEvent::DuplicateLine.into(),
+            MoveLinesUp => BufferEvent::MoveLinesUp.into(),
+            MoveLinesDown => BufferEvent::MoveLinesDown.into(),
+            JoinLines => BufferEvent::JoinLines.into(),
+            SortLines { case_insensitive, natural, numeric, reverse } => {
+                let options = SortOptions { case_insensitive, natural, numeric, reverse };
+                BufferEvent::SortLines(options).into()
+            }
+            UniqueLines => BufferEvent::UniqueLines.into(),
+            ReverseLines => BufferEvent::ReverseLines.into(),
+            Reflow { width } => BufferEvent::Reflow(width).into(),
             IncreaseNumber => BufferEvent::IncreaseNumber.into(),
             DecreaseNumber => BufferEvent::DecreaseNumber.into(),
             ToggleRecording { recording_name } => SpecialEvent::ToggleRecording(recording_name).into(),
             PlayRecording { recording_name } => SpecialEvent::PlayRecording(recording_name).into(),
             ClearRecording { recording_name } => SpecialEvent::ClearRecording(recording_name).into(),
             CollapseSelections => ViewEvent::CollapseSelections.into(),
+            UndoSelection => ViewEvent::UndoSelection.into(),
+            RedoSelection => ViewEvent::RedoSelection.into(),
+            ColumnSelectUp => ViewEvent::ColumnSelect(Movement::Up).into(),
+            ColumnSelectDown => ViewEvent::ColumnSelect(Movement::Down).into(),
+            ColumnSelectLeft => ViewEvent::ColumnSelect(Movement::Left).into(),
+            ColumnSelectRight => ViewEvent::ColumnSelect(Movement::Right).into(),
+            MoveToMatchingBracket => ViewEvent::MoveToMatchingBracket.into(),
+            SelectToMatchingBracket => ViewEvent::SelectToMatchingBracket.into(),
+            SetLineEnding { line_ending } => BufferEvent::SetLineEnding(line_ending).into(),
+            KeepMine => BufferEvent::KeepMine.into(),
+            TakeTheirs => BufferEvent::TakeTheirs.into(),
         }
     }
 }
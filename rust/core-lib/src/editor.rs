@@ -15,6 +15,7 @@
 use std::borrow::{Borrow, Cow};
 use std::cmp::min;
 use std::collections::BTreeSet;
+use std::time::{Duration, SystemTime};
 
 use serde_json::Value;
 
@@ -25,16 +26,23 @@ use xi_rope::spans::SpansBuilder;
 use xi_rope::{Cursor, DeltaBuilder, Interval, LinesMetric, Rope, RopeDelta, Transformer};
 use xi_trace::{trace_block, trace_payload};
 
+use crate::auto_pairs::{self, AutoClosers};
 use crate::config::BufferItems;
 use crate::edit_types::BufferEvent;
 use crate::event_context::MAX_SIZE_LIMIT;
+use crate::hex;
 use crate::layers::Layers;
+use crate::line_ending::LineEnding;
+use crate::line_ops::{self, LineRewrite, SortOptions};
+use crate::merge::{self, Conflict, Conflicts};
 use crate::movement::{region_movement, Movement};
 use crate::plugins::rpc::{GetDataResponse, PluginEdit, ScopeSpan, TextUnit};
 use crate::plugins::PluginId;
+use crate::reflow;
 use crate::rpc::SelectionModifier;
 use crate::selection::{InsertDrift, SelRegion, Selection};
 use crate::styles::ThemeStyleMap;
+use crate::undo_tree::{UndoTree, UndoTreeInfo};
 use crate::view::{Replace, View};
 use crate::word_boundaries::WordCursor;
 
@@ -48,6 +56,35 @@ use fuchsia::sync::SyncStore;
 // better to keep it low to expose bugs in the GC during casual testing.
 const MAX_UNDOS: usize = 20;
 
+/// An `Editor`'s undo history: its engine, and the state of its undo
+/// groups. This is what the `persistent_undo` setting keeps.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct UndoHistory<E = Engine> {
+    engine: E,
+    undo_group_id: usize,
+    live_undos: Vec<usize>,
+    cur_undo: usize,
+    undos: BTreeSet<usize>,
+    gc_undos: BTreeSet<usize>,
+    undo_tree: UndoTree,
+}
+
+impl UndoHistory {
+    /// The text of the most recent revision.
+    pub(crate) fn get_text(&self) -> &Rope {
+        self.engine.get_head()
+    }
+}
+
+/// The recorded selections that views should restore after an undo or redo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum UndoSelection {
+    /// The selections from before the edits of an undo group.
+    Before(usize),
+    /// The selections from after the edits of an undo group.
+    After(usize),
+}
+
 enum IndentDirection {
     In,
     Out,
@@ -63,6 +100,15 @@ pub struct Editor {
     last_rev_id: RevId,
     /// The revision of the last save.
     pristine_rev_id: RevId,
+    /// The text as of the last load or save, used as the base when merging
+    /// changes made on disk.
+    pristine_text: Rope,
+    /// Regions changed both in the buffer and on disk, which the user has
+    /// not yet resolved.
+    conflicts: Conflicts,
+    /// The undo group of the merge that produced `conflicts`, which are
+    /// dropped when it is undone.
+    merge_undo_group: Option<usize>,
     undo_group_id: usize,
     /// Undo groups that may still be toggled
     live_undos: Vec<usize>,
@@ -73,8 +119,25 @@ pub struct Editor {
     undos: BTreeSet<usize>,
     /// undo groups that are no longer live and should be gc'ed
     gc_undos: BTreeSet<usize>,
+    /// All live undo groups, including undone branches
+    undo_tree: UndoTree,
+    /// Set by an undo or redo, until the next event
+    undo_selection: Option<UndoSelection>,
+    /// Set by an edit that places the selection itself, to the active view's
+    /// selection after it, until the next event
+    edit_selection: Option<Selection>,
+    /// Closers inserted by auto-closing, which typing moves over
+    auto_closers: AutoClosers,
     force_undo_group: bool,
 
+    /// The line ending used when this buffer is saved. The buffer itself
+    /// always uses `\n`.
+    line_ending: LineEnding,
+    /// The line ending as of the last load or save.
+    pristine_line_ending: LineEnding,
+    /// If set, edits to the buffer are rejected.
+    read_only: bool,
+
     this_edit_type: EditType,
     last_edit_type: EditType,
 
@@ -102,10 +165,13 @@ impl Editor {
         let last_rev_id = engine.get_head_rev_id();
 
         Editor {
-            text: buffer,
+            text: buffer.clone(),
             engine,
             last_rev_id,
             pristine_rev_id: last_rev_id,
+            pristine_text: buffer,
+            conflicts: Conflicts::default(),
+            merge_undo_group: None,
             undo_group_id: 1,
             // GC only works on undone edits or prefixes of the visible edits,
             // but initial file loading can create an edit with undo group 0,
@@ -114,7 +180,14 @@ impl Editor {
             cur_undo: 1,
             undos: BTreeSet::new(),
             gc_undos: BTreeSet::new(),
+            undo_tree: UndoTree::new(0),
+            undo_selection: None,
+            edit_selection: None,
+            auto_closers: AutoClosers::default(),
             force_undo_group: false,
+            line_ending: LineEnding::Lf,
+            pristine_line_ending: LineEnding::Lf,
+            read_only: false,
             last_edit_type: EditType::Other,
             this_edit_type: EditType::Other,
             layers: Layers::default(),
@@ -124,6 +197,47 @@ impl Editor {
         }
     }
 
+    /// Creates a new `Editor` from a saved undo history, with the text of
+    /// the history's most recent revision. The buffer starts out pristine.
+    pub(crate) fn with_history(history: UndoHistory) -> Editor {
+        let UndoHistory {
+            mut engine,
+            undo_group_id,
+            live_undos,
+            cur_undo,
+            undos,
+            gc_undos,
+            undo_tree,
+        } = history;
+        engine.resume_revisions();
+        let mut editor = Editor::with_text(engine.get_head().clone());
+        let last_rev_id = engine.get_head_rev_id();
+        editor.engine = engine;
+        editor.last_rev_id = last_rev_id;
+        editor.pristine_rev_id = last_rev_id;
+        editor.last_synced_rev = last_rev_id;
+        editor.undo_group_id = undo_group_id;
+        editor.live_undos = live_undos;
+        editor.cur_undo = cur_undo;
+        editor.undos = undos;
+        editor.gc_undos = gc_undos;
+        editor.undo_tree = undo_tree;
+        editor
+    }
+
+    /// Returns this editor's undo history, for saving.
+    pub(crate) fn get_undo_history(&self) -> UndoHistory<&Engine> {
+        UndoHistory {
+            engine: &self.engine,
+            undo_group_id: self.undo_group_id,
+            live_undos: self.live_undos.clone(),
+            cur_undo: self.cur_undo,
+            undos: self.undos.clone(),
+            gc_undos: self.gc_undos.clone(),
+            undo_tree: self.undo_tree.clone(),
+        }
+    }
+
     pub(crate) fn get_buffer(&self) -> &Rope {
         &self.text
     }
@@ -148,17 +262,63 @@ impl Editor {
         *self.live_undos.last().unwrap_or(&0)
     }
 
+    /// Returns the undo group of the buffer's current state.
+    pub(crate) fn get_current_undo_group(&self) -> usize {
+        self.live_undos[self.cur_undo - 1]
+    }
+
+    /// Returns the oldest undo group that is still live.
+    pub(crate) fn get_oldest_undo_group(&self) -> usize {
+        self.undo_tree.root()
+    }
+
+    /// If the current event was an undo or redo, returns the selections
+    /// that views should restore.
+    pub(crate) fn get_undo_selection(&self) -> Option<UndoSelection> {
+        self.undo_selection
+    }
+
+    /// If the current event moved lines, reflowed paragraphs or auto-closed
+    /// a pair, returns the selection the active view should have, since its
+    /// regions can't follow the edit on their own.
+    pub(crate) fn get_edit_selection(&self) -> Option<&Selection> {
+        self.edit_selection.as_ref()
+    }
+
     pub(crate) fn update_edit_type(&mut self) {
         self.last_edit_type = self.this_edit_type;
-        self.this_edit_type = EditType::Other
+        self.this_edit_type = EditType::Other;
+        self.undo_selection = None;
+        self.edit_selection = None;
+    }
+
+    pub(crate) fn get_line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Sets the line ending of the file this buffer was loaded from.
+    pub(crate) fn set_line_ending(&mut self, line_ending: LineEnding) {
+        self.line_ending = line_ending;
+        self.pristine_line_ending = line_ending;
+    }
+
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub(crate) fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
     }
 
     pub(crate) fn set_pristine(&mut self) {
         self.pristine_rev_id = self.engine.get_head_rev_id();
+        self.pristine_text = self.text.clone();
+        self.pristine_line_ending = self.line_ending;
     }
 
     pub(crate) fn is_pristine(&self) -> bool {
         self.engine.is_equivalent_revision(self.pristine_rev_id, self.engine.get_head_rev_id())
+            && self.line_ending == self.pristine_line_ending
     }
 
     /// Set whether or not edits are forced into the same undo group rather than being split by
@@ -174,9 +334,87 @@ impl Editor {
     /// Sets this Editor's contents to `text`, preserving undo state and cursor
     /// position when possible.
     pub fn reload(&mut self, text: Rope) {
+        self.replace_text(text);
+        self.set_pristine();
+        self.conflicts.clear();
+        self.merge_undo_group = None;
+    }
+
+    /// Merges `text`, the new contents of this buffer's file, into the
+    /// buffer as a single edit. Changes that conflict with unsaved edits
+    /// in the buffer are not applied; they are returned, in the coordinates
+    /// of the merged text, and should be passed to `set_conflicts` once the
+    /// edit has been committed.
+    pub(crate) fn merge(&mut self, text: Rope) -> Vec<Conflict> {
+        let merge = merge::merge(&self.pristine_text, &self.text, &text);
+        if !merge.delta.is_identity() {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(merge.delta);
+            self.merge_undo_group = self.live_undos.last().cloned();
+        } else {
+            self.merge_undo_group = None;
+        }
+        // subsequent changes on disk are relative to what we just read.
+        self.pristine_text = text;
+        merge.conflicts
+    }
+
+    pub(crate) fn get_conflicts(&self) -> &Conflicts {
+        &self.conflicts
+    }
+
+    pub(crate) fn set_conflicts(&mut self, conflicts: Vec<Conflict>) {
+        self.conflicts.set(conflicts);
+    }
+
+    /// Resolves the conflicts touching the selection, either by keeping the
+    /// buffer's text or by replacing it with the text on disk.
+    fn resolve_conflicts(&mut self, view: &mut View, take_theirs: bool) {
+        let conflicts = self.conflicts.take_in_regions(view.sel_regions());
+        if conflicts.is_empty() {
+            return;
+        }
+        if take_theirs {
+            let mut builder = DeltaBuilder::new(self.text.len());
+            for conflict in conflicts {
+                builder.replace(conflict.iv, conflict.theirs);
+            }
+            self.this_edit_type = EditType::Other;
+            self.add_delta(builder.build());
+        } else {
+            // nothing changes in the text, but the annotations need updating
+            view.set_dirty(&self.text);
+        }
+    }
+
+    /// Overwrites the hex digit at each caret in a hex dump with each of
+    /// `chars` in turn, moving the carets along. Characters that are not hex
+    /// digits, and carets that are not on a hex digit, are ignored.
+    pub(crate) fn overwrite_hex(&mut self, view: &mut View, chars: &str) {
+        let mut carets = view.sel_regions().iter().map(|r| r.end).collect::<Vec<_>>();
+        let mut changed = false;
+        for c in chars.chars() {
+            if let Some((delta, new_carets)) = hex::overwrite(&self.text, &carets, c) {
+                self.this_edit_type = EditType::InsertChars;
+                self.add_delta(delta);
+                carets = new_carets;
+                changed = true;
+            }
+        }
+        if changed {
+            // edits never change the length of the text, so this selection
+            // is unaffected when the view is updated for them.
+            let mut selection = Selection::new();
+            carets.into_iter().for_each(|c| selection.add_region(SelRegion::caret(c)));
+            view.set_selection(&self.text, selection);
+        }
+    }
+
+    /// Replaces this Editor's contents with `text` as a single edit,
+    /// preserving undo state and cursor position when possible.
+    pub(crate) fn replace_text(&mut self, text: Rope) {
         let delta = LineHashDiff::compute_delta(self.get_buffer(), &text);
         self.add_delta(delta);
-        self.set_pristine();
     }
 
     // each outstanding plugin edit represents a rev_in_flight.
@@ -231,11 +469,14 @@ impl Editor {
     /// breaks are to be considered invalid after this method, until the
     /// `commit_delta` call.
     fn add_delta(&mut self, delta: RopeDelta) {
+        let delta = LineEnding::normalize_delta(delta);
         let head_rev_id = self.engine.get_head_rev_id();
         let undo_group = self.calculate_undo_group();
+        self.undo_tree.record_edit(undo_group, &delta);
         self.last_edit_type = self.this_edit_type;
         let priority = 0x10000;
-        self.engine.edit_rev(priority, undo_group, head_rev_id.token(), delta);
+        self.engine.edit_rev(priority, undo_group, head_rev_id.token(), delta.clone());
+        self.auto_closers.apply_delta(head_rev_id, self.engine.get_head_rev_id(), &delta);
         self.text = self.engine.get_head().clone();
     }
 
@@ -248,13 +489,16 @@ impl Editor {
             *self.live_undos.last().unwrap()
         } else {
             let undo_group = self.undo_group_id;
-            self.gc_undos.extend(&self.live_undos[self.cur_undo..]);
+            // the undone groups stay in the tree, as a branch that can be
+            // returned to with `goto_undo_node`
             self.live_undos.truncate(self.cur_undo);
+            self.undo_tree.add(undo_group, self.live_undos[self.cur_undo - 1]);
             self.live_undos.push(undo_group);
             if self.live_undos.len() <= MAX_UNDOS {
                 self.cur_undo += 1;
             } else {
-                self.gc_undos.insert(self.live_undos.remove(0));
+                self.live_undos.remove(0);
+                self.gc_undos.extend(self.undo_tree.set_root(self.live_undos[0]));
             }
             self.undo_group_id += 1;
             undo_group
@@ -266,11 +510,22 @@ impl Editor {
         let _t = trace_block("Editor::apply_plugin_edit", &["core"]);
         //TODO: get priority working, so that plugin edits don't necessarily move cursor
         let PluginEdit { rev, delta, priority, undo_group, .. } = edit;
+        let delta = LineEnding::normalize_delta(delta);
         let priority = priority as usize;
         let undo_group = undo_group.unwrap_or_else(|| self.calculate_undo_group());
-        match self.engine.try_edit_rev(priority, undo_group, rev, delta) {
+        let head_rev_id = self.engine.get_head_rev_id();
+        match self.engine.try_edit_rev(priority, undo_group, rev, delta.clone()) {
             Err(e) => error!("Error applying plugin edit: {}", e),
-            Ok(_) => self.text = self.engine.get_head().clone(),
+            Ok(_) => {
+                self.undo_tree.record_edit(undo_group, &delta);
+                // the edit may have been made in an earlier revision
+                let new_head = self.engine.get_head_rev_id();
+                match self.engine.try_delta_rev_head(head_rev_id.token()) {
+                    Ok(delta) => self.auto_closers.apply_delta(head_rev_id, new_head, &delta),
+                    Err(_) => self.auto_closers = AutoClosers::default(),
+                }
+                self.text = self.engine.get_head().clone();
+            }
         };
     }
 
@@ -299,6 +554,7 @@ impl Editor {
             _ => InsertDrift::Default,
         };
         self.layers.update_all(&delta);
+        self.conflicts.update(&delta);
 
         self.last_rev_id = self.engine.get_head_rev_id();
         self.sync_state_changed();
@@ -370,22 +626,27 @@ impl Editor {
         }
     }
 
+    /// Deletes backwards from each caret, or deletes each selected region.
+    /// A caret between the opener and closer of an auto-closed pair deletes
+    /// both.
     fn delete_backward(&mut self, view: &View, config: &BufferItems) {
         // TODO: this function is workable but probably overall code complexity
         // could be improved by implementing a "backspace" movement instead.
-        let mut builder = DeltaBuilder::new(self.text.len());
+        let mut deletions = Selection::new();
         for region in view.sel_regions() {
-            let start = offset_for_delete_backwards(&view, &region, &self.text, &config);
-            let iv = Interval::new(start, region.max());
-            if !iv.is_empty() {
-                builder.delete(iv);
-            }
-        }
-
-        if !builder.is_empty() {
-            self.this_edit_type = EditType::Delete;
-            self.add_delta(builder.build());
+            let offset = region.end;
+            let empty_pair = config.auto_close_pairs.iter().find(|pair| {
+                region.is_caret() && auto_pairs::is_in_empty_pair(&self.text, offset, pair)
+            });
+            let (start, end) = match empty_pair {
+                Some((open, close)) => (offset - open.len(), offset + close.len()),
+                None => {
+                    (offset_for_delete_backwards(&view, &region, &self.text, &config), region.max())
+                }
+            };
+            deletions.add_region(SelRegion::new(start, end));
         }
+        self.delete_sel_regions(&deletions);
     }
 
     /// Common logic for a number of delete methods. For each region in the
@@ -454,9 +715,9 @@ impl Editor {
         saved
     }
 
-    fn insert_newline(&mut self, view: &View, config: &BufferItems) {
+    fn insert_newline(&mut self, view: &View) {
         self.this_edit_type = EditType::InsertNewline;
-        self.insert(view, &config.line_ending);
+        self.insert(view, "\n");
     }
 
     fn insert_tab(&mut self, view: &View, config: &BufferItems) {
@@ -552,19 +813,105 @@ impl Editor {
         tab_text
     }
 
-    fn do_insert(&mut self, view: &View, config: &BufferItems, chars: &str) {
+    fn do_insert(&mut self, view: &mut View, config: &BufferItems, chars: &str) {
         let pair_search = config.surrounding_pairs.iter().find(|pair| pair.0 == chars);
         let caret_exists = view.sel_regions().iter().any(|region| region.is_caret());
         if let (Some(pair), false) = (pair_search, caret_exists) {
             self.this_edit_type = EditType::Surround;
             self.surround(view, pair.0.to_string(), pair.1.to_string());
+        } else if self.type_over_closers(view, chars) {
+            // the carets moved over the closers
+        } else if let Some(pair) = self.pair_to_close(view, config, chars) {
+            self.insert_pair(view, &pair.0, &pair.1);
         } else {
             self.this_edit_type = EditType::InsertChars;
             self.insert(view, chars);
         }
     }
 
+    /// If each region is a caret before a closer `chars` that auto-closing
+    /// inserted, moves the carets past the closers and returns `true`.
+    fn type_over_closers(&mut self, view: &mut View, chars: &str) -> bool {
+        let rev = self.engine.get_head_rev_id();
+        let closers = view
+            .sel_regions()
+            .iter()
+            .map(|region| Interval::new(region.end, region.end + chars.len()))
+            .collect::<Vec<_>>();
+        let all_closers = view.sel_regions().iter().all(|region| region.is_caret())
+            && closers.iter().all(|&closer| {
+                self.auto_closers.contains(rev, closer)
+                    && self.text.slice_to_cow(closer.start()..closer.end()) == chars
+            });
+        if !all_closers {
+            return false;
+        }
+        let mut selection = Selection::new();
+        for closer in closers {
+            self.auto_closers.remove(closer);
+            selection.add_region(SelRegion::caret(closer.end()));
+        }
+        view.set_selection(&self.text, selection);
+        true
+    }
+
+    /// Returns the pair that typing `chars` at each caret opens, if its
+    /// closer should be inserted too. Pairs aren't closed in strings or
+    /// comments.
+    fn pair_to_close(
+        &self,
+        view: &View,
+        config: &BufferItems,
+        chars: &str,
+    ) -> Option<(String, String)> {
+        let pairs = &config.auto_close_pairs;
+        let pair = pairs.iter().find(|pair| pair.0 == chars)?;
+        let should_close = view.sel_regions().iter().all(|region| {
+            region.is_caret()
+                && auto_pairs::should_close(&self.text, region.end, pair, pairs)
+                && !self.is_in_string_or_comment(region.end)
+        });
+        match should_close {
+            true => Some(pair.clone()),
+            false => None,
+        }
+    }
+
+    /// Returns `true` if a syntax plugin's scopes put `offset` inside a
+    /// string or a comment. The end of a string is outside of it.
+    fn is_in_string_or_comment(&self, offset: usize) -> bool {
+        let prev = match self.text.prev_codepoint_offset(offset) {
+            Some(prev) => prev,
+            None => return false,
+        };
+        self.layers.has_scope_at(prev, "comment")
+            || self.layers.has_scope_at(prev, "string")
+                && self.layers.has_scope_at(offset, "string")
+    }
+
+    /// Inserts `open` and `close` at each caret, leaving the carets between
+    /// them.
+    fn insert_pair(&mut self, view: &View, open: &str, close: &str) {
+        let mut builder = DeltaBuilder::new(self.text.len());
+        let pair = Rope::from([open, close].concat());
+        for region in view.sel_regions() {
+            builder.replace(Interval::new(region.end, region.end), pair.clone());
+        }
+        self.this_edit_type = EditType::InsertChars;
+        self.add_delta(builder.build());
+
+        let rev = self.engine.get_head_rev_id();
+        let mut selection = Selection::new();
+        for (i, region) in view.sel_regions().iter().enumerate() {
+            let caret = region.end + i * (open.len() + close.len()) + open.len();
+            self.auto_closers.add(rev, Interval::new(caret, caret + close.len()));
+            selection.add_region(SelRegion::caret(caret));
+        }
+        self.edit_selection = Some(selection);
+    }
+
     fn do_paste(&mut self, view: &View, chars: &str) {
+        let chars = &chars.replace("\r\n", "\n");
         if view.sel_regions().len() == 1 || view.sel_regions().len() != count_lines(chars) {
             self.insert(view, chars);
         } else {
@@ -595,6 +942,8 @@ impl Editor {
         if self.cur_undo > 1 {
             self.cur_undo -= 1;
             assert!(self.undos.insert(self.live_undos[self.cur_undo]));
+            self.undo_selection = Some(UndoSelection::Before(self.live_undos[self.cur_undo]));
+            self.undo_tree.visit(self.live_undos[self.cur_undo - 1]);
             self.this_edit_type = EditType::Undo;
             self.update_undos();
         }
@@ -604,14 +953,59 @@ impl Editor {
         if self.cur_undo < self.live_undos.len() {
             assert!(self.undos.remove(&self.live_undos[self.cur_undo]));
             self.cur_undo += 1;
+            self.undo_selection = Some(UndoSelection::After(self.live_undos[self.cur_undo - 1]));
+            self.undo_tree.visit(self.live_undos[self.cur_undo - 1]);
             self.this_edit_type = EditType::Redo;
             self.update_undos();
         }
     }
 
+    /// Returns the undo tree, and the node of the current state.
+    pub(crate) fn get_undo_tree(&self) -> UndoTreeInfo {
+        self.undo_tree.info(self.live_undos[self.cur_undo - 1])
+    }
+
+    /// Returns the buffer to the state just after the edits of undo group
+    /// `node`, which may be on another branch of the undo tree.
+    fn goto_undo_node(&mut self, node: usize) {
+        let path = match self.undo_tree.path_to(node) {
+            Some(path) => path,
+            None => {
+                warn!("undo tree has no node {}", node);
+                return;
+            }
+        };
+        let tree_groups = self.undo_tree.groups().collect::<BTreeSet<_>>();
+        let applied = path.iter().cloned().collect::<BTreeSet<_>>();
+        // groups awaiting gc are no longer in the tree, and stay as they are
+        self.undos = &(&self.undos - &tree_groups) | &(&tree_groups - &applied);
+        self.undo_tree.visit(node);
+        self.cur_undo = path.len();
+        self.live_undos = path;
+        self.live_undos.extend(self.undo_tree.redo_path(node));
+        self.undo_selection = Some(UndoSelection::After(node));
+        self.this_edit_type = EditType::Undo;
+        self.update_undos();
+    }
+
+    /// Returns the buffer to the state it was in `seconds_ago`, or as close
+    /// to it as the undo history allows.
+    fn undo_to_time(&mut self, seconds_ago: u64) {
+        let time = SystemTime::now()
+            .checked_sub(Duration::from_secs(seconds_ago))
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        let node = self.undo_tree.group_at(time);
+        self.goto_undo_node(node);
+    }
+
     fn update_undos(&mut self) {
         self.engine.undo(self.undos.clone());
         self.text = self.engine.get_head().clone();
+        if self.merge_undo_group.filter(|group| self.undos.contains(group)).is_some() {
+            // the conflicts refer to text that is no longer there
+            self.conflicts.clear();
+            self.merge_undo_group = None;
+        }
     }
 
     fn sel_region_to_interval_and_rope(&self, region: SelRegion) -> (Interval, Rope) {
@@ -785,7 +1179,7 @@ impl Editor {
         view.set_selection(&self.text, final_selection);
     }
 
-    fn duplicate_line(&mut self, view: &View, config: &BufferItems) {
+    fn duplicate_line(&mut self, view: &View) {
         let mut builder = DeltaBuilder::new(self.text.len());
         // get affected lines or regions
         let mut to_duplicate = BTreeSet::new();
@@ -816,7 +1210,7 @@ impl Editor {
 
             // last line does not have new line character so it needs to be manually added
             if end == self.text.len() {
-                builder.replace(iv, Rope::from(&config.line_ending))
+                builder.replace(iv, Rope::from("\n"))
             }
         }
 
@@ -824,6 +1218,103 @@ impl Editor {
         self.add_delta(builder.build());
     }
 
+    /// Moves the lines of each selection up or down past the next line,
+    /// together with their selections. Selections on adjacent lines move
+    /// as one block, and a block that is already at the top or bottom of
+    /// the buffer stays where it is.
+    fn move_lines(&mut self, view: &View, up: bool) {
+        let n_lines = line_ops::n_lines(&self.text);
+        let rewrites = line_ops::selection_lines(&self.text, view.sel_regions(), true)
+            .into_iter()
+            .filter_map(|block| match up {
+                true if block.start > 0 => {
+                    let mut order = block.clone().collect::<Vec<_>>();
+                    order.push(block.start - 1);
+                    Some(LineRewrite { lines: block.start - 1..block.end, order })
+                }
+                false if block.end < n_lines => {
+                    let mut order = vec![block.end];
+                    order.extend(block.clone());
+                    Some(LineRewrite { lines: block.start..block.end + 1, order })
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        self.rewrite_lines(view, &rewrites);
+    }
+
+    /// Sorts the lines of each selection, or of the whole buffer if
+    /// nothing is selected.
+    fn sort_lines(&mut self, view: &View, options: SortOptions) {
+        self.reorder_lines(view, |lines| line_ops::sort_order(lines, options));
+    }
+
+    /// Removes all but the first of each set of equal lines, in each
+    /// selection or in the whole buffer if nothing is selected.
+    fn unique_lines(&mut self, view: &View) {
+        self.reorder_lines(view, |lines| line_ops::unique_order(lines));
+    }
+
+    /// Reverses the order of the lines of each selection, or of the whole
+    /// buffer if nothing is selected.
+    fn reverse_lines(&mut self, view: &View) {
+        self.reorder_lines(view, |lines| (0..lines.len()).rev().collect());
+    }
+
+    /// Rewrites the selected lines, or all lines, in the order returned by
+    /// `order_function`, which gets the lines' text.
+    fn reorder_lines<F>(&mut self, view: &View, order_function: F)
+    where
+        F: Fn(&[Cow<str>]) -> Vec<usize>,
+    {
+        let rewrites = line_ops::selected_or_all_lines(&self.text, view.sel_regions())
+            .into_iter()
+            .map(|lines| {
+                let text = lines
+                    .clone()
+                    .map(|line| {
+                        let (start, end) = line_ops::line_bounds(&self.text, line);
+                        self.text.slice_to_cow(start..end)
+                    })
+                    .collect::<Vec<_>>();
+                let order = order_function(&text).into_iter().map(|i| lines.start + i).collect();
+                LineRewrite { lines, order }
+            })
+            .collect::<Vec<_>>();
+        self.rewrite_lines(view, &rewrites);
+    }
+
+    fn rewrite_lines(&mut self, view: &View, rewrites: &[LineRewrite]) {
+        if let Some((delta, selection)) =
+            line_ops::rewrite_lines(&self.text, rewrites, view.get_selection())
+        {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(delta);
+            self.edit_selection = Some(selection);
+        }
+    }
+
+    /// Joins the lines of each selection, or each caret's line with the
+    /// next one.
+    fn join_lines(&mut self, view: &View) {
+        if let Some(delta) = line_ops::join_lines(&self.text, view.sel_regions()) {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(delta);
+        }
+    }
+
+    /// Rewraps the paragraphs of each selection, or at each caret, to
+    /// `width` columns.
+    fn reflow(&mut self, view: &View, width: usize, tab_size: usize) {
+        if let Some((delta, selection)) =
+            reflow::reflow(&self.text, view.get_selection(), width, tab_size)
+        {
+            self.this_edit_type = EditType::Other;
+            self.add_delta(delta);
+            self.edit_selection = Some(selection);
+        }
+    }
+
     pub(crate) fn do_edit(
         &mut self,
         view: &mut View,
@@ -838,21 +1329,37 @@ impl Editor {
             Transpose => self.do_transpose(view),
             Undo => self.do_undo(),
             Redo => self.do_redo(),
+            UndoTreeGoto(node) => self.goto_undo_node(node),
+            UndoToTime(seconds_ago) => self.undo_to_time(seconds_ago),
             Uppercase => self.transform_text(view, |s| s.to_uppercase()),
             Lowercase => self.transform_text(view, |s| s.to_lowercase()),
             Capitalize => self.capitalize_text(view),
             Indent => self.modify_indent(view, config, IndentDirection::In),
             Outdent => self.modify_indent(view, config, IndentDirection::Out),
-            InsertNewline => self.insert_newline(view, config),
+            InsertNewline => self.insert_newline(view),
             InsertTab => self.insert_tab(view, config),
             Insert(chars) => self.do_insert(view, config, &chars),
             Paste(chars) => self.do_paste(view, &chars),
             Yank => self.yank(view, kill_ring),
             ReplaceNext => self.replace(view, false),
             ReplaceAll => self.replace(view, true),
-            DuplicateLine => self.duplicate_line(view, config),
+            DuplicateLine => self.duplicate_line(view),
+            MoveLinesUp => self.move_lines(view, true),
+            MoveLinesDown => self.move_lines(view, false),
+            JoinLines => self.join_lines(view),
+            SortLines(options) => self.sort_lines(view, options),
+            UniqueLines => self.unique_lines(view),
+            ReverseLines => self.reverse_lines(view),
+            Reflow(width) => self.reflow(view, width, config.tab_size),
             IncreaseNumber => self.change_number(view, |s| s.checked_add(1)),
             DecreaseNumber => self.change_number(view, |s| s.checked_sub(1)),
+            SetLineEnding(line_ending) => {
+                // the text doesn't change, but the buffer is no longer pristine
+                self.line_ending = line_ending;
+                view.set_dirty(&self.text);
+            }
+            KeepMine => self.resolve_conflicts(view, false),
+            TakeTheirs => self.resolve_conflicts(view, true),
         }
     }
 
@@ -864,15 +1371,16 @@ impl Editor {
         self.text.measure::<LinesMetric>() + 1
     }
 
+    /// Updates the spans of a plugin's layer, returning the interval of
+    /// the buffer whose styles changed.
     pub fn update_spans(
         &mut self,
-        view: &mut View,
         plugin: PluginId,
         start: usize,
         len: usize,
         spans: Vec<ScopeSpan>,
         rev: RevToken,
-    ) {
+    ) -> Interval {
         let _t = trace_block("Editor::update_spans", &["core"]);
         // TODO: more protection against invalid input
         let mut start = start;
@@ -897,7 +1405,7 @@ impl Editor {
         }
         let iv = Interval::new(start, end_offset);
         self.layers.update_layer(plugin, iv, spans);
-        view.invalidate_styles(&self.text, start, end_offset);
+        iv
     }
 
     pub(crate) fn get_rev(&self, rev: RevToken) -> Option<Cow<Rope>> {
@@ -1022,4 +1530,58 @@ mod tests {
         assert_eq!(editor.get_buffer().to_string(), "sshello");
     }
 
+    #[test]
+    fn restore_undo_history() {
+        let mut editor = Editor::with_text("one");
+        editor.replace_text(Rope::from("one two"));
+        editor.commit_delta();
+        let json = serde_json::to_string(&editor.get_undo_history()).unwrap();
+
+        let mut editor = Editor::with_history(serde_json::from_str(&json).unwrap());
+        assert_eq!(editor.get_buffer().to_string(), "one two");
+        assert!(editor.is_pristine());
+        editor.do_undo();
+        assert_eq!(editor.get_buffer().to_string(), "one");
+        assert!(!editor.is_pristine());
+        editor.do_redo();
+        assert!(editor.is_pristine());
+
+        // new edits don't collide with the restored revisions
+        editor.replace_text(Rope::from("one two three"));
+        assert!(editor.commit_delta().is_some());
+        editor.do_undo();
+        assert_eq!(editor.get_buffer().to_string(), "one two");
+    }
+
+    #[test]
+    fn undo_tree_branches() {
+        let mut editor = Editor::with_text("one");
+        editor.replace_text(Rope::from("one two"));
+        editor.do_undo();
+        editor.replace_text(Rope::from("one three"));
+        assert_eq!(editor.get_buffer().to_string(), "one three");
+        let tree = editor.get_undo_tree();
+        let parents = tree.nodes.iter().map(|n| n.parent).collect::<Vec<_>>();
+        assert_eq!(parents, [None, Some(0), Some(0)]);
+        assert_eq!(tree.current, 2);
+
+        // the undone branch is kept
+        editor.goto_undo_node(1);
+        assert_eq!(editor.get_buffer().to_string(), "one two");
+        editor.do_undo();
+        assert_eq!(editor.get_buffer().to_string(), "one");
+        editor.do_redo();
+        assert_eq!(editor.get_buffer().to_string(), "one two");
+        editor.goto_undo_node(2);
+        assert_eq!(editor.get_buffer().to_string(), "one three");
+        editor.goto_undo_node(7);
+        assert_eq!(editor.get_buffer().to_string(), "one three");
+
+        editor.undo_to_time(0);
+        assert_eq!(editor.get_buffer().to_string(), "one three");
+        editor.undo_to_time(3600);
+        assert_eq!(editor.get_buffer().to_string(), "one");
+        assert_eq!(editor.get_undo_tree().current, 0);
+    }
+
 }
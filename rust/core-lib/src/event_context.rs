@@ -35,18 +35,21 @@ use crate::config::{BufferItems, Table};
 use crate::styles::ThemeStyleMap;
 
 use crate::client::Client;
-use crate::edit_types::{EventDomain, SpecialEvent};
+use crate::column_selection::ColumnMeasure;
+use crate::edit_types::{BufferEvent, EventDomain, SpecialEvent, ViewEvent};
 use crate::editor::Editor;
 use crate::file::FileInfo;
+use crate::hex;
 use crate::plugins::Plugin;
 use crate::recorder::Recorder;
-use crate::selection::InsertDrift;
+use crate::selection::{InsertDrift, SelRegion, Selection};
+use crate::session::SessionView;
 use crate::syntax::LanguageId;
 use crate::tabs::{
     BufferId, PluginId, ViewId, FIND_VIEW_IDLE_MASK, RENDER_VIEW_IDLE_MASK, REWRAP_VIEW_IDLE_MASK,
 };
 use crate::view::View;
-use crate::width_cache::WidthCache;
+use crate::width_cache::{CodepointMono, WidthCache, WidthMeasure};
 use crate::WeakXiCore;
 
 // Maximum returned result from plugin get_data RPC.
@@ -109,8 +112,37 @@ impl<'a> EventContext<'a> {
         self.plugins.iter().for_each(f)
     }
 
+    /// Iterates over this view and its siblings, the other views of the
+    /// same buffer.
+    fn iter_views(&self) -> impl Iterator<Item = &'a RefCell<View>> + '_ {
+        iter::once(self.view).chain(self.siblings.iter().cloned())
+    }
+
+    /// Returns the id plugins know this buffer by. Plugins only see one
+    /// view of each buffer: the oldest one.
+    pub(crate) fn plugin_view_id(&self) -> ViewId {
+        self.siblings.iter().map(|v| v.borrow().get_view_id()).fold(self.view_id, ViewId::min)
+    }
+
+    fn is_plugin_view(&self) -> bool {
+        self.plugin_view_id() == self.view_id
+    }
+
+    /// Invalidates every view of the buffer, forcing a full render.
+    pub(crate) fn set_views_dirty(&self) {
+        let editor = self.editor.borrow();
+        self.iter_views().for_each(|view| view.borrow_mut().set_dirty(editor.get_buffer()));
+    }
+
     pub(crate) fn do_edit(&mut self, cmd: EditNotification) {
         let event: EventDomain = cmd.into();
+        if event.is_edit() {
+            if let Err(message) = self.check_writable() {
+                warn!("rejected {:?}: {}", event, message);
+                self.client.alert(message);
+                return;
+            }
+        }
 
         {
             // Handle recording-- clone every non-toggle and play event into the recording buffer
@@ -137,7 +169,13 @@ impl<'a> EventContext<'a> {
         use self::EventDomain as E;
         match event {
             E::View(cmd) => {
-                self.with_view(|view, text| view.do_edit(text, cmd));
+                match cmd {
+                    ViewEvent::MoveToMatchingBracket | ViewEvent::SelectToMatchingBracket => {
+                        self.do_bracket_edit(cmd)
+                    }
+                    cmd if self.view.borrow().is_column_event(&cmd) => self.do_column_edit(cmd),
+                    cmd => self.with_view(|view, text| view.do_edit(text, cmd)),
+                }
                 self.editor.borrow_mut().update_edit_type();
                 if self.with_view(|v, t| v.needs_wrap_in_visible_region(t)) {
                     self.rewrap();
@@ -146,6 +184,7 @@ impl<'a> EventContext<'a> {
                     self.do_incremental_find();
                 }
             }
+            E::Buffer(cmd) if self.is_hex_view() => self.do_hex_edit(cmd),
             E::Buffer(cmd) => {
                 self.with_editor(|ed, view, k_ring, conf| ed.do_edit(view, k_ring, conf, cmd))
             }
@@ -153,6 +192,57 @@ impl<'a> EventContext<'a> {
         }
     }
 
+    fn do_bracket_edit(&mut self, cmd: ViewEvent) {
+        let editor = self.editor.borrow();
+        self.view.borrow_mut().do_bracket_edit(editor.get_buffer(), editor.get_layers(), cmd);
+    }
+
+    fn do_column_edit(&mut self, cmd: ViewEvent) {
+        // text is measured the way it is for wrapping: by the client when
+        // wrapping to the window, and in codepoints otherwise
+        let client: &dyn WidthMeasure = match self.config.word_wrap {
+            true => self.client,
+            false => &CodepointMono,
+        };
+        let mut width_cache = self.width_cache.borrow_mut();
+        let mut measure = ColumnMeasure::new(&mut width_cache, client, self.config.tab_size);
+        let editor = self.editor.borrow();
+        self.view.borrow_mut().do_column_edit(editor.get_buffer(), cmd, &mut measure);
+    }
+
+    /// Returns an error message if this buffer is read-only, and so can't
+    /// be edited.
+    fn check_writable(&self) -> Result<(), String> {
+        if self.editor.borrow().is_read_only() {
+            return Err(format!("The buffer in {} is read-only", self.view_id));
+        }
+        Ok(())
+    }
+
+    /// Returns `true` if this buffer is a hex dump of a binary file.
+    fn is_hex_view(&self) -> bool {
+        self.info.map(|info| info.binary).unwrap_or(false)
+    }
+
+    /// Handles an edit to a hex dump. Typing overwrites hex digits; other
+    /// edits that would change the layout of the dump are ignored.
+    fn do_hex_edit(&mut self, cmd: BufferEvent) {
+        match cmd {
+            BufferEvent::Insert(chars) => {
+                self.with_editor(|ed, view, _, _| ed.overwrite_hex(view, &chars))
+            }
+            BufferEvent::Undo
+            | BufferEvent::Redo
+            | BufferEvent::UndoTreeGoto(_)
+            | BufferEvent::UndoToTime(_)
+            | BufferEvent::KeepMine
+            | BufferEvent::TakeTheirs => {
+                self.with_editor(|ed, view, k_ring, conf| ed.do_edit(view, k_ring, conf, cmd))
+            }
+            cmd => warn!("{:?} is not supported in a hex view", cmd),
+        }
+    }
+
     fn do_special(&mut self, cmd: SpecialEvent) {
         match cmd {
             SpecialEvent::Resize(size) => {
@@ -175,6 +265,7 @@ impl<'a> EventContext<'a> {
             SpecialEvent::RequestHover { request_id, position } => {
                 self.do_request_hover(request_id, position)
             }
+            SpecialEvent::GotoOffset(offset) => self.do_goto_offset(offset as usize),
             SpecialEvent::DebugToggleComment => self.do_debug_toggle_comment(),
             SpecialEvent::Reindent => self.do_reindent(),
             SpecialEvent::ToggleRecording(_) => {}
@@ -216,9 +307,14 @@ impl<'a> EventContext<'a> {
 
     pub(crate) fn do_edit_sync(&mut self, cmd: EditRequest) -> Result<Value, RemoteError> {
         use self::EditRequest::*;
+        if let Cut = cmd {
+            self.check_writable().map_err(|message| RemoteError::custom(403, message, None))?;
+        }
         let result = match cmd {
+            Cut if self.is_hex_view() => Ok(self.with_editor(|ed, view, _, _| ed.do_copy(view))),
             Cut => Ok(self.with_editor(|ed, view, _, _| ed.do_cut(view))),
             Copy => Ok(self.with_editor(|ed, view, _, _| ed.do_copy(view))),
+            UndoTreeGet => Ok(json!(self.editor.borrow().get_undo_tree())),
         };
         self.after_edit("core");
         self.render_if_needed();
@@ -233,10 +329,26 @@ impl<'a> EventContext<'a> {
                 let style_map = self.style_map.borrow();
                 ed.get_layers_mut().add_scopes(plugin, scopes, &style_map);
             }
-            UpdateSpans { start, len, spans, rev } => self.with_editor(|ed, view, _, _| {
-                ed.update_spans(view, plugin, start, len, spans, rev)
-            }),
-            Edit { edit } => self.with_editor(|ed, _, _, _| ed.apply_plugin_edit(edit)),
+            UpdateSpans { start, len, spans, rev } => {
+                let mut ed = self.editor.borrow_mut();
+                let iv = ed.update_spans(plugin, start, len, spans, rev);
+                self.iter_views().for_each(|view| {
+                    view.borrow_mut().invalidate_styles(ed.get_buffer(), iv.start(), iv.end())
+                });
+            }
+            // plugins see the dump as text, and would break its layout
+            Edit { .. } if self.is_hex_view() => {
+                warn!("rejected edit from plugin {:?} to a hex view", plugin)
+            }
+            Edit { edit } => match self.check_writable() {
+                Ok(()) => self.with_editor(|ed, _, _, _| ed.apply_plugin_edit(edit)),
+                Err(message) => {
+                    warn!("rejected edit from plugin {:?}: {}", plugin, message);
+                    let name = self.plugins.iter().find(|p| p.id == plugin).map(|p| &p.name);
+                    let name = name.map(String::as_str).unwrap_or("a plugin");
+                    self.client.alert(format!("Rejected an edit from {}: {}", name, message));
+                }
+            },
             Alert { msg } => self.client.alert(&msg),
             AddStatusItem { key, value, alignment } => {
                 let plugin_name = &self.plugins.iter().find(|p| p.id == plugin).unwrap().name;
@@ -292,16 +404,29 @@ impl<'a> EventContext<'a> {
 
     fn update_views(&self, ed: &Editor, delta: &RopeDelta, last_text: &Rope, drift: InsertDrift) {
         let mut width_cache = self.width_cache.borrow_mut();
-        let iter_views = iter::once(&self.view).chain(self.siblings.iter());
-        iter_views.for_each(|view| {
-            view.borrow_mut().after_edit(
+        let undo_selection = ed.get_undo_selection();
+        let undo_group = ed.get_current_undo_group();
+        let oldest_group = ed.get_oldest_undo_group();
+        self.iter_views().for_each(|view| {
+            let mut view = view.borrow_mut();
+            let is_active = view.get_view_id() == self.view_id;
+            let before = view.get_selection().clone();
+            view.after_edit(
                 ed.get_buffer(),
                 last_text,
                 delta,
                 self.client,
                 &mut width_cache,
                 drift,
-            )
+                is_active,
+            );
+            if let (true, Some(sel)) = (is_active, ed.get_edit_selection()) {
+                view.set_selection(ed.get_buffer(), sel.clone());
+            }
+            match undo_selection {
+                Some(which) => view.restore_undo_selection(ed.get_buffer(), which, is_active),
+                None => view.record_undo_selection(undo_group, before, oldest_group),
+            }
         });
     }
 
@@ -318,8 +443,9 @@ impl<'a> EventContext<'a> {
         let v: Value = serde_json::to_value(&ed.get_edit_type()).unwrap();
         let edit_type_str = v.as_str().unwrap().to_string();
 
+        let plugin_view_id = self.plugin_view_id();
         let update = PluginUpdate::new(
-            self.view_id,
+            plugin_view_id,
             ed.get_head_rev_token(),
             delta,
             new_len,
@@ -337,7 +463,7 @@ impl<'a> EventContext<'a> {
             ed.increment_revs_in_flight();
             let weak_core = self.weak_core.clone();
             let id = plugin.id;
-            let view_id = self.view_id;
+            let view_id = plugin_view_id;
             plugin.update(&update, move |resp| {
                 weak_core.handle_plugin_update(id, view_id, resp);
             });
@@ -363,14 +489,21 @@ impl<'a> EventContext<'a> {
     fn render(&mut self) {
         let _t = trace_block("EventContext::render", &["core"]);
         let ed = self.editor.borrow();
-        //TODO: render other views
-        self.view.borrow_mut().render_if_dirty(
-            ed.get_buffer(),
-            self.client,
-            self.style_map,
-            ed.get_layers().get_merged(),
-            ed.is_pristine(),
-        )
+        let is_hex_view = self.is_hex_view();
+        self.iter_views().for_each(|view| {
+            let mut view = view.borrow_mut();
+            if !is_hex_view {
+                view.update_bracket_matches(ed.get_buffer(), ed.get_layers());
+            }
+            view.render_if_dirty(
+                ed.get_buffer(),
+                self.client,
+                self.style_map,
+                ed.get_layers().get_merged(),
+                ed.get_conflicts(),
+                ed.is_pristine(),
+            )
+        });
     }
 }
 
@@ -381,9 +514,9 @@ impl<'a> EventContext<'a> {
 /// special cases here.
 impl<'a> EventContext<'a> {
     pub(crate) fn finish_init(&mut self, config: &Table) {
-        if !self.plugins.is_empty() {
-            let info = self.plugin_info();
-            self.plugins.iter().for_each(|plugin| plugin.new_buffer(&info));
+        // plugins already know about buffers with other views
+        if self.siblings.is_empty() {
+            self.notify_plugins_new_buffer();
         }
 
         let available_plugins = self
@@ -395,17 +528,37 @@ impl<'a> EventContext<'a> {
 
         self.client.config_changed(self.view_id, config);
         self.client.language_changed(self.view_id, &self.language);
+        if self.info.map(|info| info.mixed_line_endings).unwrap_or(false) {
+            let line_ending = self.editor.borrow().get_line_ending();
+            self.client.mixed_line_endings(self.view_id, line_ending);
+        }
+        if self.is_hex_view() {
+            self.client.hex_view(self.view_id, hex::BYTES_PER_LINE);
+        }
+        if self.editor.borrow().is_read_only() {
+            self.client.read_only(self.view_id, true);
+        }
         self.update_wrap_settings(true);
         self.with_view(|view, text| view.set_dirty(text));
         self.render()
     }
 
+    /// Tells plugins about this view's buffer, as if it had just been
+    /// opened.
+    pub(crate) fn notify_plugins_new_buffer(&mut self) {
+        if !self.plugins.is_empty() {
+            let info = self.plugin_info();
+            self.plugins.iter().for_each(|plugin| plugin.new_buffer(&info));
+        }
+    }
+
     pub(crate) fn after_save(&mut self, path: &Path) {
         // notify plugins
-        self.plugins.iter().for_each(|plugin| plugin.did_save(self.view_id, path));
+        let plugin_view_id = self.plugin_view_id();
+        self.plugins.iter().for_each(|plugin| plugin.did_save(plugin_view_id, path));
 
         self.editor.borrow_mut().set_pristine();
-        self.with_view(|view, text| view.set_dirty(text));
+        self.set_views_dirty();
         self.render()
     }
 
@@ -413,7 +566,9 @@ impl<'a> EventContext<'a> {
     pub(crate) fn close_view(&self) -> bool {
         // we probably want to notify plugins _before_ we close the view
         // TODO: determine what plugins we're stopping
-        self.plugins.iter().for_each(|plug| plug.close_view(self.view_id));
+        if self.is_plugin_view() {
+            self.plugins.iter().for_each(|plug| plug.close_view(self.view_id));
+        }
         self.siblings.is_empty()
     }
 
@@ -433,14 +588,20 @@ impl<'a> EventContext<'a> {
         }
 
         self.client.config_changed(self.view_id, &changes);
-        self.plugins.iter().for_each(|plug| plug.config_changed(self.view_id, &changes));
+        if self.is_plugin_view() {
+            self.plugins.iter().for_each(|plug| plug.config_changed(self.view_id, &changes));
+        }
         self.render()
     }
 
     pub(crate) fn language_changed(&mut self, new_language_id: &LanguageId) {
         self.language = new_language_id.clone();
         self.client.language_changed(self.view_id, new_language_id);
-        self.plugins.iter().for_each(|plug| plug.language_changed(self.view_id, new_language_id));
+        if self.is_plugin_view() {
+            self.plugins
+                .iter()
+                .for_each(|plug| plug.language_changed(self.view_id, new_language_id));
+        }
     }
 
     pub(crate) fn reload(&mut self, text: Rope) {
@@ -449,13 +610,58 @@ impl<'a> EventContext<'a> {
         self.render();
     }
 
+    /// Discards unsaved changes, replacing the buffer's contents with
+    /// `text`, the contents of its file, as a single undoable edit.
+    pub(crate) fn revert(&mut self, text: Rope) {
+        self.with_editor(|ed, _, _, _| ed.reload(text));
+        self.after_edit("core");
+        // the text may not have changed, but the buffer is now pristine
+        self.set_views_dirty();
+        self.render();
+    }
+
+    /// Merges `text`, the new contents of the file on disk, into a buffer
+    /// with unsaved changes. Conflicting changes are shown as annotations.
+    pub(crate) fn merge(&mut self, text: Rope) {
+        let conflicts = self.with_editor(|ed, _, _, _| ed.merge(text));
+        self.after_edit("core");
+        self.editor.borrow_mut().set_conflicts(conflicts);
+        self.set_views_dirty();
+        self.render();
+    }
+
+    /// Replaces the buffer's contents with `text`, as a single undoable edit.
+    /// Unlike `reload`, this leaves the buffer dirty.
+    pub(crate) fn replace_contents(&mut self, text: Rope) {
+        self.with_editor(|ed, _, _, _| ed.replace_text(text));
+        self.after_edit("core");
+        self.render();
+    }
+
+    /// Restores this view's selection and scroll position from a session.
+    /// Offsets past the end of the buffer are moved to its end.
+    pub(crate) fn restore_position(&mut self, state: &SessionView) {
+        let (first, last) = state.scroll;
+        self.with_view(|view, text| {
+            let clamp = |offset: usize| {
+                text.at_or_prev_codepoint_boundary(offset.min(text.len())).unwrap_or(0)
+            };
+            let mut selection = Selection::new();
+            for &(start, end) in &state.selection {
+                selection.add_region(SelRegion::new(clamp(start), clamp(end)));
+            }
+            if selection.is_empty() {
+                selection.add_region(SelRegion::caret(0));
+            }
+            view.restore_position(text, selection, first, last);
+        });
+        self.render();
+    }
+
     pub(crate) fn plugin_info(&mut self) -> PluginBufferInfo {
         let ed = self.editor.borrow();
         let nb_lines = ed.get_buffer().measure::<LinesMetric>() + 1;
-        let views: Vec<ViewId> = iter::once(&self.view)
-            .chain(self.siblings.iter())
-            .map(|v| v.borrow().get_view_id())
-            .collect();
+        let views = vec![self.plugin_view_id()];
 
         let changes = serde_json::to_value(self.config).unwrap();
         let path = self.info.map(|info| info.path.to_owned());
@@ -472,20 +678,18 @@ impl<'a> EventContext<'a> {
     }
 
     pub(crate) fn plugin_started(&mut self, plugin: &Plugin) {
-        self.client.plugin_started(self.view_id, &plugin.name)
+        self.iter_views()
+            .for_each(|view| self.client.plugin_started(view.borrow().get_view_id(), &plugin.name))
     }
 
     pub(crate) fn plugin_stopped(&mut self, plugin: &Plugin) {
-        self.client.plugin_stopped(self.view_id, &plugin.name, 0);
-        let needs_render = self.with_editor(|ed, view, _, _| {
-            if ed.get_layers_mut().remove_layer(plugin.id).is_some() {
-                view.set_dirty(ed.get_buffer());
-                true
-            } else {
-                false
-            }
+        self.iter_views().for_each(|view| {
+            self.client.plugin_stopped(view.borrow().get_view_id(), &plugin.name, 0)
         });
+        let needs_render =
+            self.editor.borrow_mut().get_layers_mut().remove_layer(plugin.id).is_some();
         if needs_render {
+            self.set_views_dirty();
             self.render();
         }
     }
@@ -500,25 +704,27 @@ impl<'a> EventContext<'a> {
     }
 
     /// Returns the text to be saved, appending a newline if necessary.
+    ///
+    /// Line endings are converted to the buffer's line ending when the text
+    /// is written.
     pub(crate) fn text_for_save(&mut self) -> Rope {
         let editor = self.editor.borrow();
         let mut rope = editor.get_buffer().clone();
         let rope_len = rope.len();
 
-        if rope_len < 1 || !self.config.save_with_newline {
+        if rope_len < 1 || !self.config.save_with_newline || self.is_hex_view() {
             return rope;
         }
 
         let cursor = Cursor::new(&rope, rope.len());
         let has_newline_at_eof = match cursor.get_leaf() {
-            Some((last_chunk, _)) => last_chunk.ends_with(&self.config.line_ending),
+            Some((last_chunk, _)) => last_chunk.ends_with('\n'),
             // The rope can't be empty, since we would have returned earlier if it was
             None => unreachable!(),
         };
 
         if !has_newline_at_eof {
-            let line_ending = &self.config.line_ending;
-            rope.edit(rope_len.., line_ending);
+            rope.edit(rope_len.., "\n");
             rope
         } else {
             rope
@@ -604,12 +810,29 @@ impl<'a> EventContext<'a> {
             self.client,
             self.style_map,
             ed.get_layers().get_merged(),
+            ed.get_conflicts(),
             first,
             last,
             ed.is_pristine(),
         )
     }
 
+    /// Moves the cursor to `offset`. In a hex view this is an offset in the
+    /// file; otherwise it is a byte offset in the buffer.
+    fn do_goto_offset(&mut self, offset: usize) {
+        let is_hex_view = self.is_hex_view();
+        self.with_view(|view, text| {
+            let offset = if is_hex_view {
+                hex::offset_of_byte(text, offset)
+            } else {
+                text.at_or_prev_codepoint_boundary(offset.min(text.len())).unwrap_or(0)
+            };
+            let before = view.get_selection().clone();
+            view.set_selection(text, SelRegion::caret(offset));
+            view.record_selection_change(before, true);
+        });
+    }
+
     fn selected_line_ranges(&mut self) -> Vec<(usize, usize)> {
         let ed = self.editor.borrow();
         let mut prev_range: Option<Range<usize>> = None;
@@ -646,7 +869,7 @@ impl<'a> EventContext<'a> {
         let line_ranges = self.selected_line_ranges();
         // this is handled by syntect only; this is definitely not the long-term solution.
         if let Some(plug) = self.plugins.iter().find(|p| p.name == "xi-syntect-plugin") {
-            plug.dispatch_command(self.view_id, "reindent", &json!(line_ranges));
+            plug.dispatch_command(self.plugin_view_id(), "reindent", &json!(line_ranges));
         }
     }
 
@@ -655,13 +878,14 @@ impl<'a> EventContext<'a> {
 
         // this is handled by syntect only; this is definitely not the long-term solution.
         if let Some(plug) = self.plugins.iter().find(|p| p.name == "xi-syntect-plugin") {
-            plug.dispatch_command(self.view_id, "toggle_comment", &json!(line_ranges));
+            plug.dispatch_command(self.plugin_view_id(), "toggle_comment", &json!(line_ranges));
         }
     }
 
     fn do_request_hover(&mut self, request_id: usize, position: Option<ClientPosition>) {
         if let Some(position) = self.get_resolved_position(position) {
-            self.with_each_plugin(|p| p.get_hover(self.view_id, request_id, position))
+            let plugin_view_id = self.plugin_view_id();
+            self.with_each_plugin(|p| p.get_hover(plugin_view_id, request_id, position))
         }
     }
 
@@ -691,7 +915,11 @@ mod tests {
     use super::*;
     use crate::config::ConfigManager;
     use crate::core::dummy_weak_core;
+    use crate::line_ending::LineEnding;
+    use crate::plugins::rpc::{PluginEdit, PluginNotification, ScopeSpan};
+    use crate::plugins::PluginPid;
     use crate::tabs::BufferId;
+    use xi_rope::{DeltaBuilder, RopeDelta};
     use xi_rpc::test_utils::DummyPeer;
 
     struct ContextHarness {
@@ -774,6 +1002,17 @@ mod tests {
         }
     }
 
+    fn plugin_edit(rev: u64, delta: RopeDelta) -> PluginEdit {
+        PluginEdit {
+            rev,
+            delta,
+            priority: 55,
+            after_cursor: false,
+            undo_group: None,
+            author: "plugin_one".into(),
+        }
+    }
+
     #[test]
     fn smoke_test() {
         let harness = ContextHarness::new("");
@@ -1855,21 +2094,22 @@ mod tests {
         four really see.|\n\
         lines to nice" );
 
-        // Undo entire playback in a single command
+        // Undo entire playback in a single command, restoring the
+        // selections from before its edits
         ctx.do_edit(EditNotification::Undo);
         assert_eq!(harness.debug_render(),"\
         this is a about\n\
         that has string\n\
-        four really nice|\n\
-        lines to see." );
+        four really [|nice]\n\
+        lines to [|see.]" );
 
         // Make sure we can redo in a single command as well
         ctx.do_edit(EditNotification::Redo);
         assert_eq!(harness.debug_render(),"\
         this is a about\n\
         that has string\n\
-        four really see.|\n\
-        lines to nice" );
+        four really [|see.]\n\
+        lines to [|nice]" );
 
         // We shouldn't be able to use cleared recordings
         ctx.do_edit(EditNotification::Undo);
@@ -1877,9 +2117,9 @@ mod tests {
         ctx.do_edit(EditNotification::ClearRecording { recording_name: recording_name.clone() });
         ctx.do_edit(EditNotification::PlayRecording { recording_name });
         assert_eq!(harness.debug_render(),"\
-        this is a string\n\
-        that has about\n\
-        four really nice|\n\
+        this is a [|string]\n\
+        that has [|about]\n\
+        four really nice\n\
         lines to see." );
     }
 
@@ -1926,10 +2166,6 @@ mod tests {
 
     #[test]
     fn test_illegal_plugin_edit() {
-        use xi_rope::DeltaBuilder;
-        use crate::plugins::rpc::{PluginNotification, PluginEdit};
-        use crate::plugins::PluginPid;
-
         let text = "text";
         let harness = ContextHarness::new(text);
         let mut ctx = harness.make_context();
@@ -1939,19 +2175,35 @@ mod tests {
         let mut builder = DeltaBuilder::new(0); // wrong length
         builder.replace(iv, "1".into());
 
-        let edit_one = PluginEdit {
-            rev: rev_token,
-            delta: builder.build(),
-            priority: 55,
-            after_cursor: false,
-            undo_group: None,
-            author: "plugin_one".into(),
-        };
+        let edit_one = plugin_edit(rev_token, builder.build());
 
         ctx.do_plugin_cmd(PluginPid(1), PluginNotification::Edit { edit: edit_one });
         let new_rev_token = ctx.editor.borrow().get_head_rev_token();
         // no change should be made
         assert_eq!(rev_token, new_rev_token);
+        let undo_tree = ctx.editor.borrow().get_undo_tree();
+        assert!(undo_tree.nodes.iter().all(|node| node.summary.is_empty()));
+    }
+
+    #[test]
+    fn line_endings() {
+        let harness = ContextHarness::new("ab");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::SetLineEnding { line_ending: LineEnding::CrLf });
+        assert_eq!(harness.editor.borrow().get_line_ending(), LineEnding::CrLf);
+        assert!(!harness.editor.borrow().is_pristine());
+        ctx.do_edit(EditNotification::SetLineEnding { line_ending: LineEnding::Lf });
+        assert!(harness.editor.borrow().is_pristine());
+
+        // inserted text is normalized to '\n'
+        ctx.do_edit(EditNotification::Insert { chars: "1\r\n".into() });
+        assert_eq!(harness.debug_render(), "1\n|ab");
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let mut builder = DeltaBuilder::new(4);
+        builder.replace(Interval::new(4, 4), "\r\n2".into());
+        let edit = plugin_edit(rev, builder.build());
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::Edit { edit });
+        assert_eq!(harness.debug_render(), "1\n|ab\n2");
     }
 
     
@@ -1979,4 +2231,352 @@ mod tests {
 
         assert_eq!(harness.debug_render(), "wor\nd|");
     }
+
+    #[test]
+    fn merge_changes_on_disk() {
+        use crate::rpc::GestureType::*;
+
+        // lines must be long enough to be matched when diffing.
+        let one = "this is the first line of the file\n";
+        let two = "this is the second line of the file\n";
+        let three = "this is the third line of the file\n";
+        let harness = ContextHarness::new(format!("{}{}{}", one, two, three));
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::Insert { chars: "mine: ".into() });
+
+        ctx.merge(Rope::from(format!("{}theirs\n{}four\n", one, three)));
+        assert_eq!(harness.debug_render(), format!("{}mine: |{}{}four\n", one, two, three));
+        assert_eq!(harness.editor.borrow().get_conflicts().len(), 1);
+
+        // the merge is a single undo group
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), format!("{}mine: |{}{}", one, two, three));
+        // undoing the merge drops its conflicts
+        assert!(harness.editor.borrow().get_conflicts().is_empty());
+        ctx.do_edit(EditNotification::Redo);
+        ctx.merge(Rope::from(format!("{}other\n{}four\n", one, three)));
+        assert_eq!(harness.editor.borrow().get_conflicts().len(), 1);
+
+        ctx.do_edit(EditNotification::KeepMine);
+        assert!(harness.editor.borrow().get_conflicts().is_empty());
+
+        ctx.merge(Rope::from(format!("{}THEIRS\n{}four\n", one, three)));
+        assert_eq!(harness.editor.borrow().get_conflicts().len(), 1);
+        ctx.do_edit(EditNotification::TakeTheirs);
+        let text = String::from(harness.editor.borrow().get_buffer());
+        assert_eq!(text, format!("{}THEIRS\n{}four\n", one, three));
+        assert!(harness.editor.borrow().get_conflicts().is_empty());
+    }
+
+    #[test]
+    fn hex_view_edits() {
+        let tmp = tempdir::TempDir::new("xi-test-hex").unwrap();
+        let path = tmp.path().join("file.bin");
+        std::fs::write(&path, b"0123456789abcdef\0ghij").unwrap();
+        let (rope, info) = crate::file::load_file(&crate::vfs::LocalFileSystem, &path, None, |_, _| ()).unwrap();
+        assert!(info.binary);
+
+        let harness = ContextHarness::new(String::from(&rope));
+        let mut ctx = harness.make_context();
+        ctx.info = Some(&info);
+        let bytes = |harness: &ContextHarness| {
+            let mut buf = Vec::new();
+            for line in harness.editor.borrow().get_buffer().lines_raw(..) {
+                hex::parse_line(&line, &mut buf).unwrap();
+            }
+            buf
+        };
+
+        ctx.do_edit(EditNotification::GotoOffset { offset: 17 });
+        ctx.do_edit(EditNotification::Insert { chars: "4x1".into() });
+        assert_eq!(bytes(&harness), b"0123456789abcdef\0Ahij");
+        let render = harness.debug_render();
+        assert!(render.contains("00 41 |6"), "{}", render);
+        assert!(render.contains("|.Ahij|"), "{}", render);
+
+        // edits that would change the layout are ignored
+        ctx.do_edit(EditNotification::DeleteBackward);
+        ctx.do_edit(EditNotification::InsertNewline);
+        assert_eq!(bytes(&harness), b"0123456789abcdef\0Ahij");
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(bytes(&harness), b"0123456789abcdef\0ghij");
+        assert!(ctx.text_for_save().slice_to_cow(..).ends_with("|.ghij|\n"));
+
+        // plugins can't edit the dump
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let mut builder = DeltaBuilder::new(rope.len());
+        builder.replace(Interval::new(0, 0), "x".into());
+        let edit = plugin_edit(rev, builder.build());
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::Edit { edit });
+        assert_eq!(ctx.editor.borrow().get_head_rev_token(), rev);
+    }
+
+    #[test]
+    fn read_only_rejects_edits() {
+        let harness = ContextHarness::new("text");
+        let mut ctx = harness.make_context();
+        ctx.editor.borrow_mut().set_read_only(true);
+        let rev_token = ctx.editor.borrow().get_head_rev_token();
+
+        ctx.do_edit(EditNotification::Insert { chars: "more ".into() });
+        ctx.do_edit(EditNotification::Reindent);
+        assert!(ctx.do_edit_sync(EditRequest::Cut).is_err());
+        let mut builder = DeltaBuilder::new(4);
+        builder.replace(Interval::new(0, 0), "plugin ".into());
+        let edit = plugin_edit(rev_token, builder.build());
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::Edit { edit });
+        assert_eq!(harness.debug_render(), "|text");
+        assert_eq!(ctx.editor.borrow().get_head_rev_token(), rev_token);
+
+        // moving the cursor still works
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        assert_eq!(harness.debug_render(), "text|");
+        assert!(ctx.do_edit_sync(EditRequest::Copy).is_ok());
+
+        ctx.editor.borrow_mut().set_read_only(false);
+        ctx.do_edit(EditNotification::Insert { chars: "!".into() });
+        assert_eq!(harness.debug_render(), "text!|");
+
+        // conflicts can't be resolved by taking the text on disk
+        ctx.merge(Rope::from("TEXT\n"));
+        assert_eq!(harness.editor.borrow().get_conflicts().len(), 1);
+        ctx.editor.borrow_mut().set_read_only(true);
+        ctx.do_edit(EditNotification::SelectAll);
+        ctx.do_edit(EditNotification::TakeTheirs);
+        assert_eq!(harness.debug_render(), "[text!|]");
+        ctx.do_edit(EditNotification::KeepMine);
+        assert!(harness.editor.borrow().get_conflicts().is_empty());
+    }
+
+    #[test]
+    fn undo_restores_selections() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("one two\nthree four");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 3, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 5, ty: ToggleSel });
+        ctx.do_edit(EditNotification::Insert { chars: "s".into() });
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "!".into() });
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        assert_eq!(harness.debug_render(), "|ones two\nthrees four!");
+
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "ones two\nthrees four|");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "one| two\nthree| four");
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::Redo);
+        assert_eq!(harness.debug_render(), "ones| two\nthrees| four");
+    }
+
+    #[test]
+    fn selection_history() {
+        let harness = ContextHarness::new("one\ntwo\nthree");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::GotoLine { line: 2 });
+        ctx.do_edit(EditNotification::MoveRight);
+        ctx.do_edit(EditNotification::MoveRight);
+        assert_eq!(harness.debug_render(), "one\ntwo\nth|ree");
+
+        ctx.do_edit(EditNotification::UndoSelection);
+        assert_eq!(harness.debug_render(), "one\ntwo\n|three");
+        ctx.do_edit(EditNotification::UndoSelection);
+        assert_eq!(harness.debug_render(), "on|e\ntwo\nthree");
+        ctx.do_edit(EditNotification::UndoSelection);
+        assert_eq!(harness.debug_render(), "|one\ntwo\nthree");
+        ctx.do_edit(EditNotification::UndoSelection);
+        assert_eq!(harness.debug_render(), "|one\ntwo\nthree");
+
+        ctx.do_edit(EditNotification::Insert { chars: "1".into() });
+        ctx.do_edit(EditNotification::RedoSelection);
+        assert_eq!(harness.debug_render(), "1on|e\ntwo\nthree");
+    }
+
+    #[test]
+    fn column_selection() {
+        use crate::rpc::{GestureType::*, SelectionGranularity::Column};
+        let harness = ContextHarness::new("abcd\nab\n\nabcd");
+        let mut ctx = harness.make_context();
+        let select = Select { granularity: Column, multi: false };
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 1, ty: select });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 3, ty: Drag });
+        assert_eq!(harness.debug_render(), "a[bc|]d\na[b|]\n\na[bc|]d");
+        ctx.do_edit(EditNotification::Insert { chars: "X".into() });
+        assert_eq!(harness.debug_render(), "aX|d\naX|\n\naX|d");
+
+        // past the end of the lines
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 6, ty: select });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 6, ty: Drag });
+        assert_eq!(harness.debug_render(), "aXd|\naX|\n\naXd");
+
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::ColumnSelectRight);
+        ctx.do_edit(EditNotification::ColumnSelectDown);
+        ctx.do_edit(EditNotification::ColumnSelectDown);
+        ctx.do_edit(EditNotification::ColumnSelectDown);
+        ctx.do_edit(EditNotification::ColumnSelectRight);
+        assert_eq!(harness.debug_render(), "[aX|]d\n[aX|]\n\n[aX|]d");
+        ctx.do_edit(EditNotification::ColumnSelectUp);
+        ctx.do_edit(EditNotification::ColumnSelectLeft);
+        assert_eq!(harness.debug_render(), "[a|]Xd\n[a|]X\n\naXd");
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "|Xd\n|X\n\naXd");
+
+        // a block copied from the lines is pasted one line per region
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: select });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 2, ty: Drag });
+        assert_eq!(harness.debug_render(), "[Xd|]\n[X|]\n\naXd");
+        let copied = ctx.do_edit_sync(EditRequest::Copy).unwrap();
+        assert_eq!(copied, json!("Xd\nX"));
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 0, ty: select });
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 0, ty: Drag });
+        assert_eq!(harness.debug_render(), "|Xd\n|X\n\naXd");
+        ctx.do_edit(EditNotification::Paste { chars: "Xd\nX".into() });
+        assert_eq!(harness.debug_render(), "Xd|Xd\nX|X\n\naXd");
+    }
+
+    #[test]
+    fn line_commands() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("one\ntwo\nthree\nfour\nfive\n");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 1, ty: PointSelect });
+        ctx.do_edit(EditNotification::Gesture { line: 3, col: 2, ty: ToggleSel });
+        ctx.do_edit(EditNotification::MoveLinesDown);
+        assert_eq!(harness.debug_render(), "two\no|ne\nthree\nfive\nfo|ur\n");
+        // the last line stays put, the other one moves
+        ctx.do_edit(EditNotification::MoveLinesDown);
+        assert_eq!(harness.debug_render(), "two\nthree\no|ne\nfive\nfo|ur\n");
+        ctx.do_edit(EditNotification::MoveLinesUp);
+        assert_eq!(harness.debug_render(), "two\no|ne\nthree\nfo|ur\nfive\n");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "two\nthree\no|ne\nfive\nfo|ur\n");
+
+        ctx.do_edit(EditNotification::SortLines {
+            case_insensitive: false,
+            natural: false,
+            numeric: false,
+            reverse: false,
+        });
+        assert_eq!(harness.debug_render(), "five\nfo|ur\no|ne\nthree\ntwo\n");
+        ctx.do_edit(EditNotification::ReverseLines);
+        assert_eq!(harness.debug_render(), "two\nthree\no|ne\nfo|ur\nfive\n");
+        ctx.do_edit(EditNotification::JoinLines);
+        assert_eq!(harness.debug_render(), "two\nthree\no|ne fo|ur five\n");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "two\nthree\no|ne\nfo|ur\nfive\n");
+
+        // only the selected lines
+        ctx.do_edit(EditNotification::Gesture { line: 1, col: 0, ty: PointSelect });
+        ctx.do_edit(EditNotification::MoveDownAndModifySelection);
+        ctx.do_edit(EditNotification::MoveDownAndModifySelection);
+        ctx.do_edit(EditNotification::ReverseLines);
+        assert_eq!(harness.debug_render(), "two\n[one\nthree\n|]four\nfive\n");
+        ctx.do_edit(EditNotification::MoveLinesUp);
+        assert_eq!(harness.debug_render(), "[one\nthree\n|]two\nfour\nfive\n");
+    }
+
+    #[test]
+    fn auto_close_pairs() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Insert { chars: "(".into() });
+        assert_eq!(harness.debug_render(), "(|)");
+        ctx.do_edit(EditNotification::Insert { chars: "a".into() });
+        ctx.do_edit(EditNotification::Insert { chars: ")".into() });
+        assert_eq!(harness.debug_render(), "(a)|");
+
+        // the closer moves with edits from plugins
+        ctx.do_edit(EditNotification::Insert { chars: "(".into() });
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let mut builder = DeltaBuilder::new(5);
+        builder.replace(Interval::new(0, 0), "x".into());
+        let edit = plugin_edit(rev, builder.build());
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::Edit { edit });
+        assert_eq!(harness.debug_render(), "x(a)(|)");
+        ctx.do_edit(EditNotification::Insert { chars: ")".into() });
+        assert_eq!(harness.debug_render(), "x(a)()|");
+        ctx.do_edit(EditNotification::DeleteBackward);
+        ctx.do_edit(EditNotification::DeleteBackward);
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::DeleteForward);
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        assert_eq!(harness.debug_render(), "(a)|");
+        // only closers that were inserted are typed over
+        ctx.do_edit(EditNotification::MoveLeft);
+        ctx.do_edit(EditNotification::Insert { chars: ")".into() });
+        assert_eq!(harness.debug_render(), "(a)|)");
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "\"".into() });
+        assert_eq!(harness.debug_render(), "(a))\"|\"");
+        ctx.do_edit(EditNotification::DeleteBackward);
+        assert_eq!(harness.debug_render(), "(a))|");
+        // not before a word
+        ctx.do_edit(EditNotification::MoveToBeginningOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "[".into() });
+        assert_eq!(harness.debug_render(), "[|(a))");
+
+        // nor inside a string
+        let harness = ContextHarness::new("x = \"a \"");
+        let mut ctx = harness.make_context();
+        let scopes = vec![vec!["source.rust".into(), "string.quoted.double".into()]];
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::AddScopes { scopes });
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let spans = vec![ScopeSpan { start: 4, end: 8, scope_id: 0 }];
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::UpdateSpans { start: 0, len: 8, spans, rev });
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 6, ty: PointSelect });
+        ctx.do_edit(EditNotification::Insert { chars: "(".into() });
+        assert_eq!(harness.debug_render(), "x = \"a(| \"");
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::Insert { chars: "(".into() });
+        assert_eq!(harness.debug_render(), "x = \"a( \"(|)");
+    }
+
+    #[test]
+    fn matching_brackets() {
+        use crate::rpc::GestureType::*;
+        let harness = ContextHarness::new("f(a, \")\") + (b)");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 1, ty: PointSelect });
+        ctx.do_edit(EditNotification::MoveToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f(a, \"|)\") + (b)");
+
+        // the bracket in the string is skipped once the plugin says it is one
+        let scopes = vec![vec!["source.rust".into(), "string.quoted.double".into()]];
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::AddScopes { scopes });
+        let rev = ctx.editor.borrow().get_head_rev_token();
+        let spans = vec![ScopeSpan { start: 5, end: 8, scope_id: 0 }];
+        ctx.do_plugin_cmd(PluginPid(1), PluginNotification::UpdateSpans { start: 0, len: 15, spans, rev });
+        ctx.do_edit(EditNotification::Gesture { line: 0, col: 1, ty: PointSelect });
+        ctx.do_edit(EditNotification::MoveToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f(a, \")\"|) + (b)");
+        // and back, to the same side of the bracket
+        ctx.do_edit(EditNotification::MoveToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f|(a, \")\") + (b)");
+
+        ctx.do_edit(EditNotification::MoveToEndOfDocument);
+        ctx.do_edit(EditNotification::SelectToMatchingBracket);
+        assert_eq!(harness.debug_render(), "f(a, \")\") + [|(b)]");
+        ctx.do_edit(EditNotification::UndoSelection);
+        assert_eq!(harness.debug_render(), "f(a, \")\") + (b)|");
+    }
+
+    #[test]
+    fn reflow() {
+        let harness = ContextHarness::new("// one two three\n// four\n\nfive six\n");
+        let mut ctx = harness.make_context();
+        ctx.do_edit(EditNotification::MoveToRightEndOfLine);
+        ctx.do_edit(EditNotification::Reflow { width: 12 });
+        assert_eq!(harness.debug_render(), "// one two\n// three|\n// four\n\nfive six\n");
+        ctx.do_edit(EditNotification::Reflow { width: 80 });
+        assert_eq!(harness.debug_render(), "// one two three| four\n\nfive six\n");
+        ctx.do_edit(EditNotification::Undo);
+        assert_eq!(harness.debug_render(), "// one two\n// three|\n// four\n\nfive six\n");
+    }
 }
@@ -14,33 +14,63 @@
 
 //! Interactions with the file system.
 
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::ffi::OsString;
 use std::fmt;
-use std::fs::{self, File, Permissions};
+use std::hash::Hasher;
 use std::io::{self, Read, Write};
+use std::mem;
 use std::path::{Path, PathBuf};
 use std::str;
+use std::sync::Arc;
 use std::time::SystemTime;
 
+use xi_rope::tree::TreeBuilder;
 use xi_rope::Rope;
 use xi_rpc::RemoteError;
 
+use crate::backup::{self, BackupSettings};
+use crate::compression::{Compression, Compressor, Decompressor};
+use crate::hex;
+use crate::line_ending::{LineEnding, LineEndingCounts};
 use crate::tabs::BufferId;
+use crate::vfs::{self, FileSystem, FileSystems};
 
 #[cfg(feature = "notify")]
 use crate::tabs::OPEN_FILE_EVENT_TOKEN;
 #[cfg(feature = "notify")]
 use crate::watcher::FileWatcher;
-#[cfg(target_family = "unix")]
-use std::os::unix::fs::PermissionsExt;
 
 const UTF8_BOM: &str = "\u{feff}";
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+
+/// The size of the chunks in which files are read.
+const LOAD_CHUNK_SIZE: usize = 1 << 20;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// The number of bytes inspected when guessing whether a file without a BOM
+/// is UTF-16.
+const UTF16_SNIFF_LEN: usize = 1024;
+
+/// Unicode scalar values for the bytes `0x80..=0x9F` in Windows-1252.
+/// The five bytes undefined in that encoding map to the corresponding C1
+/// control characters, so that every byte round-trips.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
 
 /// Tracks all state related to open files.
 pub struct FileManager {
     open_files: HashMap<PathBuf, BufferId>,
     file_info: HashMap<BufferId, FileInfo>,
+    /// Where files are read from and written to, by URI scheme.
+    file_systems: FileSystems,
     /// A monitor of filesystem events, for things like reloading changed files.
     #[cfg(feature = "notify")]
     watcher: FileWatcher,
@@ -49,34 +79,90 @@ pub struct FileManager {
 #[derive(Debug)]
 pub struct FileInfo {
     pub encoding: CharacterEncoding,
+    /// The predominant line ending in the file when it was loaded or last
+    /// saved, or `None` if it contained no line endings. Buffers always use
+    /// `\n` internally.
+    pub line_ending: Option<LineEnding>,
+    /// Whether the file contained a mix of line endings when it was loaded.
+    pub mixed_line_endings: bool,
+    /// Whether the file is binary data. If so, its buffer holds a hex dump
+    /// of its contents, which is converted back to bytes when saved.
+    pub binary: bool,
+    /// The codec the file is compressed with, if it is compressed. Its
+    /// buffer holds the decompressed contents, and it is compressed again
+    /// when saved.
+    pub compression: Option<Compression>,
+    /// Whether the file could not be written to when it was loaded or
+    /// last saved. Buffers of read-only files start out read-only.
+    pub read_only: bool,
     pub path: PathBuf,
     pub mod_time: Option<SystemTime>,
+    /// A hash of the file's contents, after decompression, when it was
+    /// loaded or last saved. This is kept on disk with undo histories and
+    /// sessions, so it is computed with `FnvHasher`, which is stable.
+    /// Modification times change without the contents changing (for
+    /// instance on some network file systems, or when a file is checked
+    /// out again), so this is what determines whether a file has changed.
+    pub content_hash: Option<u64>,
     pub has_changed: bool,
     #[cfg(target_family = "unix")]
     pub permissions: Option<u32>,
 }
 
+#[derive(Debug)]
 pub enum FileError {
     Io(io::Error, PathBuf),
     UnknownEncoding(PathBuf),
     HasChanged(PathBuf),
+    NoFile(BufferId),
+    /// The backup of a file could not be written before it was saved.
+    Backup(io::Error, PathBuf),
+    /// The path is a URI with a scheme that has no file system registered.
+    UnsupportedScheme(PathBuf),
+    /// The file looked compressed, but could not be decompressed.
+    Decompression(io::Error, PathBuf),
 }
 
-#[derive(Debug, Clone, Copy)]
+/// The encoding of a file on disk. Buffers are always UTF-8 internally;
+/// this determines how bytes are decoded on load and encoded on save.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CharacterEncoding {
+    #[serde(rename = "utf-8")]
     Utf8,
+    #[serde(rename = "utf-8-bom")]
     Utf8WithBom,
+    #[serde(rename = "utf-16le")]
+    Utf16Le,
+    #[serde(rename = "utf-16le-bom")]
+    Utf16LeWithBom,
+    #[serde(rename = "utf-16be")]
+    Utf16Be,
+    #[serde(rename = "utf-16be-bom")]
+    Utf16BeWithBom,
+    #[serde(rename = "iso-8859-1")]
+    Latin1,
+    #[serde(rename = "windows-1252")]
+    Windows1252,
 }
 
 impl FileManager {
     #[cfg(feature = "notify")]
     pub fn new(watcher: FileWatcher) -> Self {
-        FileManager { open_files: HashMap::new(), file_info: HashMap::new(), watcher }
+        FileManager {
+            open_files: HashMap::new(),
+            file_info: HashMap::new(),
+            file_systems: FileSystems::default(),
+            watcher,
+        }
     }
 
     #[cfg(not(feature = "notify"))]
     pub fn new() -> Self {
-        FileManager { open_files: HashMap::new(), file_info: HashMap::new() }
+        FileManager {
+            open_files: HashMap::new(),
+            file_info: HashMap::new(),
+            file_systems: FileSystems::default(),
+        }
     }
 
     #[cfg(feature = "notify")]
@@ -84,21 +170,92 @@ impl FileManager {
         &mut self.watcher
     }
 
+    /// Makes `fs` handle paths that are URIs with this scheme.
+    pub fn register_file_system<S: Into<String>>(&mut self, scheme: S, fs: Arc<dyn FileSystem>) {
+        self.file_systems.register(scheme, fs);
+    }
+
+    /// Returns the file system that `path` belongs to.
+    pub fn file_system(&self, path: &Path) -> Result<Arc<dyn FileSystem>, FileError> {
+        self.file_systems.get(path).ok_or_else(|| FileError::UnsupportedScheme(path.to_owned()))
+    }
+
     pub fn get_info(&self, id: BufferId) -> Option<&FileInfo> {
         self.file_info.get(&id)
     }
 
+    /// Sets the encoding that will be used the next time this buffer
+    /// is saved.
+    pub fn set_encoding(
+        &mut self,
+        id: BufferId,
+        encoding: CharacterEncoding,
+    ) -> Result<(), FileError> {
+        match self.file_info.get_mut(&id) {
+            Some(info) => {
+                info.encoding = encoding;
+                Ok(())
+            }
+            None => Err(FileError::NoFile(id)),
+        }
+    }
+
+    /// Reads this buffer's file from disk again, decoding it with the
+    /// provided encoding instead of the guessed one.
+    pub fn reopen_with_encoding(
+        &mut self,
+        id: BufferId,
+        encoding: CharacterEncoding,
+    ) -> Result<Rope, FileError> {
+        self.reopen(id, Some(encoding))
+    }
+
+    /// Reads this buffer's file from disk again, decoding it with the
+    /// buffer's current encoding. A hex view stays a hex view.
+    pub fn revert(&mut self, id: BufferId) -> Result<Rope, FileError> {
+        let encoding = match self.file_info.get(&id) {
+            Some(info) if info.binary => None,
+            Some(info) => Some(info.encoding),
+            None => return Err(FileError::NoFile(id)),
+        };
+        self.reopen(id, encoding)
+    }
+
+    fn reopen(
+        &mut self,
+        id: BufferId,
+        encoding: Option<CharacterEncoding>,
+    ) -> Result<Rope, FileError> {
+        let path = match self.file_info.get(&id) {
+            Some(info) => info.path.clone(),
+            None => return Err(FileError::NoFile(id)),
+        };
+        let fs = self.file_system(&path)?;
+        let (rope, info) = try_load_file(&*fs, &path, encoding)?;
+        self.file_info.insert(id, info);
+        Ok(rope)
+    }
+
     pub fn get_editor(&self, path: &Path) -> Option<BufferId> {
         self.open_files.get(path).cloned()
     }
 
     /// Returns `true` if this file is open and has changed on disk.
     /// This state is stashed.
+    ///
+    /// The file's contents are only hashed if its modification time has
+    /// changed since it was last checked.
     pub fn check_file(&mut self, path: &Path, id: BufferId) -> bool {
+        let fs = match self.file_systems.get(path) {
+            Some(fs) => fs,
+            None => return false,
+        };
         if let Some(info) = self.file_info.get_mut(&id) {
-            let mod_t = get_mod_time(path);
+            let mod_t = fs.metadata(path).ok().and_then(|m| m.mod_time);
             if mod_t != info.mod_time {
-                info.has_changed = true
+                let hash = get_content_hash(&*fs, path);
+                info.mod_time = mod_t;
+                info.has_changed = hash.is_none() || hash != info.content_hash;
             }
             return info.has_changed;
         }
@@ -106,147 +263,579 @@ impl FileManager {
     }
 
     pub fn open(&mut self, path: &Path, id: BufferId) -> Result<Rope, FileError> {
-        if !path.exists() {
-            let _ = File::create(path).map_err(|e| FileError::Io(e, path.to_owned()))?;
+        let fs = self.file_system(path)?;
+        if fs.metadata(path).is_err() {
+            fs.write(path, &mut |_| Ok(())).map_err(|e| FileError::Io(e, path.to_owned()))?;
         }
 
-        let (rope, info) = try_load_file(path)?;
+        let (rope, info) = try_load_file(&*fs, path, None)?;
+        self.finish_open(id, info);
+        Ok(rope)
+    }
 
-        self.open_files.insert(path.to_owned(), id);
+    /// Starts tracking a file that was loaded with `load_file`.
+    pub fn finish_open(&mut self, id: BufferId, info: FileInfo) {
+        let path = info.path.clone();
+        self.open_files.insert(path.clone(), id);
         if self.file_info.insert(id, info).is_none() {
             #[cfg(feature = "notify")]
-            self.watcher.watch(path, false, OPEN_FILE_EVENT_TOKEN);
+            self.watch(&path);
         }
-        Ok(rope)
     }
 
     pub fn close(&mut self, id: BufferId) {
         if let Some(info) = self.file_info.remove(&id) {
             self.open_files.remove(&info.path);
             #[cfg(feature = "notify")]
-            self.watcher.unwatch(&info.path, OPEN_FILE_EVENT_TOKEN);
+            self.unwatch(&info.path);
         }
     }
 
-    pub fn save(&mut self, path: &Path, text: &Rope, id: BufferId) -> Result<(), FileError> {
+    /// Saves `text`, which uses `\n` line endings, to `path`, converting
+    /// line endings to `line_ending`.
+    pub fn save(
+        &mut self,
+        path: &Path,
+        text: &Rope,
+        line_ending: LineEnding,
+        id: BufferId,
+    ) -> Result<(), FileError> {
         let is_existing = self.file_info.contains_key(&id);
         if is_existing {
-            self.save_existing(path, text, id)
+            self.save_existing(path, text, line_ending, id)
         } else {
-            self.save_new(path, text, id)
+            self.save_new(path, text, line_ending, id)
         }
     }
 
-    fn save_new(&mut self, path: &Path, text: &Rope, id: BufferId) -> Result<(), FileError> {
-        try_save(path, text, CharacterEncoding::Utf8, self.get_info(id))
+    fn save_new(
+        &mut self,
+        path: &Path,
+        text: &Rope,
+        line_ending: LineEnding,
+        id: BufferId,
+    ) -> Result<(), FileError> {
+        let fs = self.file_system(path)?;
+        // if this buffer already has a file, 'save as' keeps its encoding
+        let encoding = self.get_info(id).map(|i| i.encoding).unwrap_or(CharacterEncoding::Utf8);
+        let binary = self.get_info(id).map(|i| i.binary).unwrap_or(false);
+        // but is compressed if the new file's name says it should be
+        let compression = Compression::from_extension(path);
+        let content_hash = try_save(&*fs, path, text, encoding, line_ending, binary, compression)
             .map_err(|e| FileError::Io(e, path.to_owned()))?;
+        let metadata = fs.metadata(path).ok();
         let info = FileInfo {
-            encoding: CharacterEncoding::Utf8,
+            encoding,
+            line_ending: Some(line_ending),
+            mixed_line_endings: false,
+            binary,
+            compression,
+            read_only: metadata.as_ref().map(|m| m.read_only).unwrap_or(false),
             path: path.to_owned(),
-            mod_time: get_mod_time(path),
+            mod_time: metadata.as_ref().and_then(|m| m.mod_time),
+            content_hash: Some(content_hash),
             has_changed: false,
             #[cfg(target_family = "unix")]
-            permissions: get_permissions(path),
+            permissions: metadata.and_then(|m| m.permissions),
         };
         self.open_files.insert(path.to_owned(), id);
         self.file_info.insert(id, info);
         #[cfg(feature = "notify")]
-        self.watcher.watch(path, false, OPEN_FILE_EVENT_TOKEN);
+        self.watch(path);
         Ok(())
     }
 
-    fn save_existing(&mut self, path: &Path, text: &Rope, id: BufferId) -> Result<(), FileError> {
+    fn save_existing(
+        &mut self,
+        path: &Path,
+        text: &Rope,
+        line_ending: LineEnding,
+        id: BufferId,
+    ) -> Result<(), FileError> {
         let prev_path = self.file_info[&id].path.clone();
         if prev_path != path {
-            self.save_new(path, text, id)?;
+            self.save_new(path, text, line_ending, id)?;
             self.open_files.remove(&prev_path);
             #[cfg(feature = "notify")]
-            self.watcher.unwatch(&prev_path, OPEN_FILE_EVENT_TOKEN);
-        } else if self.file_info[&id].has_changed {
+            self.unwatch(&prev_path);
+        } else if self.check_file(path, id) {
             return Err(FileError::HasChanged(path.to_owned()));
         } else {
+            let fs = self.file_system(path)?;
             let encoding = self.file_info[&id].encoding;
-            try_save(path, text, encoding, self.get_info(id))
-                .map_err(|e| FileError::Io(e, path.to_owned()))?;
-            self.file_info.get_mut(&id).unwrap().mod_time = get_mod_time(path);
+            let binary = self.file_info[&id].binary;
+            let compression = self.file_info[&id].compression;
+            let content_hash =
+                try_save(&*fs, path, text, encoding, line_ending, binary, compression)
+                    .map_err(|e| FileError::Io(e, path.to_owned()))?;
+            let info = self.file_info.get_mut(&id).unwrap();
+            info.mod_time = fs.metadata(path).ok().and_then(|m| m.mod_time);
+            info.content_hash = Some(content_hash);
+            info.line_ending = Some(line_ending);
+            info.mixed_line_endings = false;
         }
         Ok(())
     }
+
+    #[cfg(feature = "notify")]
+    fn watch(&mut self, path: &Path) {
+        if let Some(fs) = self.file_systems.get(path) {
+            fs.watch(path, &mut self.watcher, OPEN_FILE_EVENT_TOKEN);
+        }
+    }
+
+    #[cfg(feature = "notify")]
+    fn unwatch(&mut self, path: &Path) {
+        if let Some(fs) = self.file_systems.get(path) {
+            fs.unwatch(path, &mut self.watcher, OPEN_FILE_EVENT_TOKEN);
+        }
+    }
+}
+
+/// Backs up the file at `path` before it is saved over. Only files on the
+/// local disk are backed up.
+pub(crate) fn write_backup(path: &Path, backup: &BackupSettings) -> Result<(), FileError> {
+    match vfs::local_path(path) {
+        Some(local) => backup::write_backup(local, backup)
+            .map(|_| ())
+            .map_err(|e| FileError::Backup(e, path.to_owned())),
+        None => Ok(()),
+    }
 }
 
-fn try_load_file<P>(path: P) -> Result<(Rope, FileInfo), FileError>
+/// Loads the file at `path`. If `encoding` is `None`, it is guessed from
+/// the file's contents.
+fn try_load_file<P>(
+    fs: &dyn FileSystem,
+    path: P,
+    encoding: Option<CharacterEncoding>,
+) -> Result<(Rope, FileInfo), FileError>
 where
     P: AsRef<Path>,
 {
-    // TODO: support for non-utf8
-    // it's arguable that the rope crate should have file loading functionality
-    let mut f =
-        File::open(path.as_ref()).map_err(|e| FileError::Io(e, path.as_ref().to_owned()))?;
-    let mut bytes = Vec::new();
-    f.read_to_end(&mut bytes).map_err(|e| FileError::Io(e, path.as_ref().to_owned()))?;
-
-    let encoding = CharacterEncoding::guess(&bytes);
-    let rope = try_decode(bytes, encoding, path.as_ref())?;
+    load_file(fs, path, encoding, |_, _| ())
+}
+
+/// Loads the file at `path` from `fs`, reading and decoding it in chunks so that the
+/// whole file is never held in memory alongside the rope. After each chunk,
+/// `progress` is called with the number of bytes read and the file's size.
+///
+/// If `encoding` is `None`, it is guessed from the start of the file. A
+/// large file that starts out as valid UTF-8 may turn out not to be, in
+/// which case it is read again with a legacy encoding. A file that looks
+/// like binary data is loaded as a hex dump. Compressed files are
+/// decompressed; a file that only looks compressed, because it happens to
+/// start with the magic bytes of a codec, is loaded as it is.
+pub fn load_file<P, F>(
+    fs: &dyn FileSystem,
+    path: P,
+    encoding: Option<CharacterEncoding>,
+    mut progress: F,
+) -> Result<(Rope, FileInfo), FileError>
+where
+    P: AsRef<Path>,
+    F: FnMut(u64, u64),
+{
+    let path = path.as_ref();
+    match load_decompressed(fs, path, encoding, true, &mut progress) {
+        Err(FileError::Decompression(e, _)) => {
+            info!("loading {:?} without decompressing it: {}", path, e);
+            load_decompressed(fs, path, encoding, false, &mut progress)
+        }
+        other => other,
+    }
+}
+
+/// Loads the file at `path`, decompressing it if `decompress` is `true`
+/// and it is compressed.
+fn load_decompressed<F>(
+    fs: &dyn FileSystem,
+    path: &Path,
+    encoding: Option<CharacterEncoding>,
+    decompress: bool,
+    progress: &mut F,
+) -> Result<(Rope, FileInfo), FileError>
+where
+    F: FnMut(u64, u64),
+{
+    let (encoding, is_guess) = match encoding {
+        Some(encoding) => (encoding, false),
+        None => match guess_file_encoding(fs, path, decompress)? {
+            Some(guess) => guess,
+            None => return load_binary(fs, path, decompress, progress),
+        },
+    };
+
+    match load_chunks(fs, path, encoding, decompress, progress) {
+        Err(FileError::UnknownEncoding(_)) if is_guess => {
+            // Every byte is valid in both legacy encodings, and they only
+            // differ in 0x80..=0x9F, which Latin-1 maps to C1 controls.
+            let (rope, mut info) =
+                load_chunks(fs, path, CharacterEncoding::Windows1252, decompress, progress)?;
+            let is_1252 = |c: char| c >= '\u{100}' || ('\u{80}'..'\u{a0}').contains(&c);
+            if !rope.iter_chunks(..).any(|chunk| chunk.chars().any(is_1252)) {
+                info.encoding = CharacterEncoding::Latin1;
+            }
+            Ok((rope, info))
+        }
+        other => other,
+    }
+}
+
+/// Guesses the encoding of the file at `path` from its first chunk. Also
+/// returns `true` if the guess is UTF-8 but the rest of the file has not
+/// been checked. Returns `None` if the file looks like binary data.
+fn guess_file_encoding(
+    fs: &dyn FileSystem,
+    path: &Path,
+    decompress: bool,
+) -> Result<Option<(CharacterEncoding, bool)>, FileError> {
+    let mut f = open(fs, path, decompress).map_err(|e| FileError::Io(e, path.to_owned()))?;
+    let compressed = f.compression().is_some();
+    let mut buf = vec![0; LOAD_CHUNK_SIZE];
+    let n = read_chunk(&mut f, &mut buf).map_err(|e| read_error(e, path, compressed))?;
+    let encoding = CharacterEncoding::guess(&buf[..n]);
+    let is_complete = n < buf.len();
+    // UTF-16 is full of zero bytes, so only the other guesses can be binary.
+    match encoding {
+        CharacterEncoding::Utf8 | CharacterEncoding::Latin1 | CharacterEncoding::Windows1252
+            if hex::is_binary(&buf[..n]) =>
+        {
+            Ok(None)
+        }
+        // the chunk may end partway through a character, so a legacy guess
+        // isn't trustworthy either.
+        CharacterEncoding::Utf8 | CharacterEncoding::Latin1 | CharacterEncoding::Windows1252
+            if !is_complete =>
+        {
+            Ok(Some((CharacterEncoding::Utf8, true)))
+        }
+        other => Ok(Some((other, false))),
+    }
+}
+
+fn load_chunks<F>(
+    fs: &dyn FileSystem,
+    path: &Path,
+    encoding: CharacterEncoding,
+    decompress: bool,
+    progress: &mut F,
+) -> Result<(Rope, FileInfo), FileError>
+where
+    F: FnMut(u64, u64),
+{
+    let io_err = |e| FileError::Io(e, path.to_owned());
+    let metadata = fs.metadata(path).map_err(io_err)?;
+    let mut f = open(fs, path, decompress).map_err(io_err)?;
+    let compressed = f.compression().is_some();
+    let total = metadata.len;
+
+    let mut decoder = ChunkDecoder::new(encoding);
+    let mut hasher = FnvHasher::default();
+    let mut builder = TreeBuilder::new();
+    let mut line_endings = LineEndingCounts::default();
+    // a `\r` at the end of a chunk may be half of a `\r\n`.
+    let mut trailing_cr = false;
+    let mut buf = vec![0; LOAD_CHUNK_SIZE];
+    loop {
+        let n = read_chunk(&mut f, &mut buf).map_err(|e| read_error(e, path, compressed))?;
+        let is_last = n < buf.len();
+        hasher.write(&buf[..n]);
+
+        let mut text = decoder
+            .decode(&buf[..n], is_last)
+            .ok_or_else(|| FileError::UnknownEncoding(path.to_owned()))?;
+        if trailing_cr {
+            text.insert(0, '\r');
+        }
+        trailing_cr = !is_last && text.ends_with('\r');
+        if trailing_cr {
+            text.pop();
+        }
+        let (text, counts) = LineEnding::normalize(text);
+        line_endings.crlf += counts.crlf;
+        line_endings.lf += counts.lf;
+        builder.push_str(&text);
+
+        progress(f.bytes_read(), total);
+        if is_last {
+            break;
+        }
+    }
+
     let info = FileInfo {
         encoding,
-        mod_time: get_mod_time(&path),
+        line_ending: line_endings.predominant(),
+        mixed_line_endings: line_endings.is_mixed(),
+        binary: false,
+        compression: f.compression(),
+        read_only: metadata.read_only,
+        mod_time: metadata.mod_time,
+        content_hash: Some(hasher.finish()),
         #[cfg(target_family = "unix")]
-        permissions: get_permissions(&path),
-        path: path.as_ref().to_owned(),
+        permissions: metadata.permissions,
+        path: path.to_owned(),
         has_changed: false,
     };
-    Ok((rope, info))
+    Ok((builder.build(), info))
 }
 
+/// Loads the file at `path` as a hex dump.
+fn load_binary<F>(
+    fs: &dyn FileSystem,
+    path: &Path,
+    decompress: bool,
+    progress: &mut F,
+) -> Result<(Rope, FileInfo), FileError>
+where
+    F: FnMut(u64, u64),
+{
+    let io_err = |e| FileError::Io(e, path.to_owned());
+    let metadata = fs.metadata(path).map_err(io_err)?;
+    let mut f = open(fs, path, decompress).map_err(io_err)?;
+    let compressed = f.compression().is_some();
+    let total = metadata.len;
+
+    let mut hasher = FnvHasher::default();
+    let mut builder = TreeBuilder::new();
+    // a multiple of the line length, so that lines don't span chunks
+    let mut buf = vec![0; LOAD_CHUNK_SIZE];
+    let mut text = String::new();
+    let mut loaded = 0;
+    loop {
+        let n = read_chunk(&mut f, &mut buf).map_err(|e| read_error(e, path, compressed))?;
+        hasher.write(&buf[..n]);
+        text.clear();
+        for (i, line) in buf[..n].chunks(hex::BYTES_PER_LINE).enumerate() {
+            hex::dump_line(loaded + i * hex::BYTES_PER_LINE, line, &mut text);
+        }
+        builder.push_str(&text);
+        loaded += n;
+
+        progress(f.bytes_read(), total);
+        if n < buf.len() {
+            break;
+        }
+    }
+
+    let info = FileInfo {
+        encoding: CharacterEncoding::Utf8,
+        line_ending: None,
+        mixed_line_endings: false,
+        binary: true,
+        compression: f.compression(),
+        read_only: metadata.read_only,
+        mod_time: metadata.mod_time,
+        content_hash: Some(hasher.finish()),
+        #[cfg(target_family = "unix")]
+        permissions: metadata.permissions,
+        path: path.to_owned(),
+        has_changed: false,
+    };
+    Ok((builder.build(), info))
+}
+
+/// Opens the file at `path` for reading, decompressing it if `decompress`
+/// is `true` and it is compressed.
+fn open(fs: &dyn FileSystem, path: &Path, decompress: bool) -> io::Result<Decompressor> {
+    let reader = fs.read(path)?;
+    if decompress {
+        Decompressor::new(reader)
+    } else {
+        Ok(Decompressor::plain(reader))
+    }
+}
+
+/// An error reading a file, which for a compressed file is most likely
+/// because it isn't really compressed.
+fn read_error(e: io::Error, path: &Path, compressed: bool) -> FileError {
+    if compressed {
+        FileError::Decompression(e, path.to_owned())
+    } else {
+        FileError::Io(e, path.to_owned())
+    }
+}
+
+/// Reads from `f` until `buf` is full or the end of the file is reached,
+/// returning the number of bytes read.
+fn read_chunk(f: &mut dyn Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match f.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(len)
+}
+
+/// Writes `text` to `path` in `fs`, compressing it with `compression`, and
+/// returns a hash of the bytes written before compression.
 fn try_save(
+    fs: &dyn FileSystem,
     path: &Path,
     text: &Rope,
     encoding: CharacterEncoding,
-    file_info: Option<&FileInfo>,
-) -> io::Result<()> {
-    let tmp_extension = path.extension().map_or_else(
-        || OsString::from("swp"),
-        |ext| {
-            let mut ext = ext.to_os_string();
-            ext.push(".swp");
-            ext
-        },
-    );
-    let tmp_path = &path.with_extension(tmp_extension);
+    line_ending: LineEnding,
+    binary: bool,
+    compression: Option<Compression>,
+) -> io::Result<u64> {
+    let mut hash = 0;
+    fs.write(path, &mut |w| {
+        let mut w = Compressor::new(w, compression);
+        hash = write_contents(&mut w, text, encoding, line_ending, binary)?;
+        w.finish()
+    })?;
+    Ok(hash)
+}
 
-    let mut f = File::create(tmp_path)?;
-    match encoding {
-        CharacterEncoding::Utf8WithBom => f.write_all(UTF8_BOM.as_bytes())?,
-        CharacterEncoding::Utf8 => (),
+/// Writes `text` to `w`, converting it to bytes, and returns a hash of the
+/// bytes written.
+fn write_contents<W: Write + ?Sized>(
+    w: &mut W,
+    text: &Rope,
+    encoding: CharacterEncoding,
+    line_ending: LineEnding,
+    binary: bool,
+) -> io::Result<u64> {
+    let mut hasher = FnvHasher::default();
+    let mut buf = Vec::new();
+    if binary {
+        for line in text.lines_raw(..) {
+            buf.clear();
+            hex::parse_line(&line, &mut buf)?;
+            w.write_all(&buf)?;
+            hasher.write(&buf);
+        }
+        return Ok(hasher.finish());
     }
 
+    w.write_all(encoding.bom())?;
+    hasher.write(encoding.bom());
     for chunk in text.iter_chunks(..text.len()) {
-        f.write_all(chunk.as_bytes())?;
+        let chunk = match line_ending {
+            LineEnding::Lf => Cow::from(chunk),
+            LineEnding::CrLf => Cow::from(chunk.replace('\n', "\r\n")),
+        };
+        let bytes = match encoding {
+            CharacterEncoding::Utf8 | CharacterEncoding::Utf8WithBom => chunk.as_bytes(),
+            _ => {
+                buf.clear();
+                encoding.encode(&chunk, &mut buf)?;
+                &buf
+            }
+        };
+        w.write_all(bytes)?;
+        hasher.write(bytes);
     }
+    Ok(hasher.finish())
+}
 
-    fs::rename(tmp_path, path)?;
+/// Decodes a file a chunk at a time. Bytes at the end of a chunk that don't
+/// form a complete character are kept until the next chunk.
+struct ChunkDecoder {
+    encoding: CharacterEncoding,
+    pending: Vec<u8>,
+    /// Whether the start of the file, where a BOM might be, has been seen.
+    started: bool,
+}
 
-    if let Some(info_unwrapped) = file_info {
-        #[cfg(target_family = "unix")]
-        fs::set_permissions(
-            path,
-            Permissions::from_mode(info_unwrapped.permissions.unwrap_or(0o644)),
-        )?;
+impl ChunkDecoder {
+    fn new(encoding: CharacterEncoding) -> Self {
+        ChunkDecoder { encoding, pending: Vec::new(), started: false }
     }
 
-    Ok(())
+    /// Decodes the next chunk of the file, returning `None` if it isn't
+    /// valid in this encoding.
+    fn decode(&mut self, bytes: &[u8], is_last: bool) -> Option<String> {
+        let mut input = mem::take(&mut self.pending);
+        input.extend_from_slice(bytes);
+        let mut body = input.as_slice();
+        if !self.started {
+            let bom = self.encoding.bom();
+            if body.len() < bom.len() && bom.starts_with(body) && !is_last {
+                self.pending = input;
+                return Some(String::new());
+            }
+            body = body.strip_prefix(bom).unwrap_or(body);
+            self.started = true;
+        }
+
+        match self.encoding {
+            CharacterEncoding::Utf8 | CharacterEncoding::Utf8WithBom => {
+                match str::from_utf8(body) {
+                    Ok(s) => Some(s.to_owned()),
+                    // a character is cut off at the end of the chunk
+                    Err(e) if e.error_len().is_none() && !is_last => {
+                        let (valid, rest) = body.split_at(e.valid_up_to());
+                        self.pending = rest.to_vec();
+                        str::from_utf8(valid).ok().map(str::to_owned)
+                    }
+                    Err(_) => None,
+                }
+            }
+            CharacterEncoding::Utf16Le
+            | CharacterEncoding::Utf16LeWithBom
+            | CharacterEncoding::Utf16Be
+            | CharacterEncoding::Utf16BeWithBom => {
+                let big_endian = self.encoding == CharacterEncoding::Utf16Be
+                    || self.encoding == CharacterEncoding::Utf16BeWithBom;
+                let mut end = body.len();
+                if !is_last {
+                    end -= end % 2;
+                    // keep a high surrogate with its pair
+                    if end >= 2 {
+                        let last = &body[end - 2..end];
+                        let unit = if big_endian {
+                            u16::from_be_bytes([last[0], last[1]])
+                        } else {
+                            u16::from_le_bytes([last[0], last[1]])
+                        };
+                        if (0xD800..0xDC00).contains(&unit) {
+                            end -= 2;
+                        }
+                    }
+                    self.pending = body[end..].to_vec();
+                }
+                decode_utf16(&body[..end], big_endian)
+            }
+            CharacterEncoding::Latin1 => Some(body.iter().map(|&b| b as char).collect()),
+            CharacterEncoding::Windows1252 => {
+                Some(body.iter().map(|&b| windows_1252_to_char(b)).collect())
+            }
+        }
+    }
 }
 
-fn try_decode(bytes: Vec<u8>, encoding: CharacterEncoding, path: &Path) -> Result<Rope, FileError> {
-    match encoding {
-        CharacterEncoding::Utf8 => Ok(Rope::from(
-            str::from_utf8(&bytes).map_err(|_e| FileError::UnknownEncoding(path.to_owned()))?,
-        )),
-        CharacterEncoding::Utf8WithBom => {
-            let s = String::from_utf8(bytes)
-                .map_err(|_e| FileError::UnknownEncoding(path.to_owned()))?;
-            Ok(Rope::from(&s[UTF8_BOM.len()..]))
+/// Decodes UTF-16 text, returning `None` if the input has an odd length
+/// or contains unpaired surrogates.
+fn decode_utf16(bytes: &[u8], big_endian: bool) -> Option<String> {
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let units = bytes.chunks(2).map(|pair| {
+        if big_endian {
+            u16::from_be_bytes([pair[0], pair[1]])
+        } else {
+            u16::from_le_bytes([pair[0], pair[1]])
         }
+    });
+    std::char::decode_utf16(units).collect::<Result<String, _>>().ok()
+}
+
+fn windows_1252_to_char(b: u8) -> char {
+    match b {
+        0x80..=0x9F => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+        _ => b as char,
+    }
+}
+
+fn char_to_windows_1252(c: char) -> Option<u8> {
+    match c as u32 {
+        0..=0x7F | 0xA0..=0xFF => Some(c as u8),
+        _ => WINDOWS_1252_HIGH.iter().position(|&hc| hc == c).map(|i| 0x80 + i as u8),
     }
 }
 
@@ -254,23 +843,156 @@ impl CharacterEncoding {
     fn guess(s: &[u8]) -> Self {
         if s.starts_with(UTF8_BOM.as_bytes()) {
             CharacterEncoding::Utf8WithBom
-        } else {
+        } else if s.starts_with(UTF16LE_BOM) {
+            CharacterEncoding::Utf16LeWithBom
+        } else if s.starts_with(UTF16BE_BOM) {
+            CharacterEncoding::Utf16BeWithBom
+        } else if let Some(encoding) = CharacterEncoding::guess_utf16(s) {
+            encoding
+        } else if str::from_utf8(s).is_ok() {
             CharacterEncoding::Utf8
+        } else if s.iter().any(|b| (0x80..=0x9F).contains(b)) {
+            // these are C1 controls in Latin-1, which are almost never
+            // intended; in Windows-1252 they're common punctuation.
+            CharacterEncoding::Windows1252
+        } else {
+            CharacterEncoding::Latin1
+        }
+    }
+
+    /// Guesses whether text without a BOM is UTF-16, based on the
+    /// distribution of zero bytes. Mostly-ASCII UTF-16 text has a zero in
+    /// (nearly) every high byte, and almost never in a low byte.
+    fn guess_utf16(s: &[u8]) -> Option<Self> {
+        let sample = &s[..s.len().min(UTF16_SNIFF_LEN)];
+        if sample.len() < 2 || s.len() % 2 != 0 {
+            return None;
+        }
+        let pairs = sample.len() / 2;
+        let (mut even_zeros, mut odd_zeros) = (0, 0);
+        for pair in sample.chunks(2).filter(|p| p.len() == 2) {
+            if pair[0] == 0 {
+                even_zeros += 1;
+            }
+            if pair[1] == 0 {
+                odd_zeros += 1;
+            }
+        }
+        let is_mostly = |n: usize| n * 10 >= pairs * 4;
+        let is_rare = |n: usize| n * 10 <= pairs;
+        if is_mostly(odd_zeros) && is_rare(even_zeros) {
+            Some(CharacterEncoding::Utf16Le)
+        } else if is_mostly(even_zeros) && is_rare(odd_zeros) {
+            Some(CharacterEncoding::Utf16Be)
+        } else {
+            None
+        }
+    }
+
+    /// The byte order mark written at the start of files in this encoding.
+    fn bom(self) -> &'static [u8] {
+        match self {
+            CharacterEncoding::Utf8WithBom => UTF8_BOM.as_bytes(),
+            CharacterEncoding::Utf16LeWithBom => UTF16LE_BOM,
+            CharacterEncoding::Utf16BeWithBom => UTF16BE_BOM,
+            _ => &[],
+        }
+    }
+
+    /// Appends the encoded form of `s` to `buf`. Returns an error if `s`
+    /// contains a character that cannot be represented in this encoding.
+    fn encode(self, s: &str, buf: &mut Vec<u8>) -> io::Result<()> {
+        use self::CharacterEncoding::*;
+        let unencodable = |c: char| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{:?} cannot be encoded as {}", c, self.label()),
+            )
+        };
+        match self {
+            Utf8 | Utf8WithBom => buf.extend_from_slice(s.as_bytes()),
+            Utf16Le | Utf16LeWithBom => {
+                s.encode_utf16().for_each(|u| buf.extend_from_slice(&u.to_le_bytes()))
+            }
+            Utf16Be | Utf16BeWithBom => {
+                s.encode_utf16().for_each(|u| buf.extend_from_slice(&u.to_be_bytes()))
+            }
+            Latin1 => {
+                for c in s.chars() {
+                    if c as u32 > 0xFF {
+                        return Err(unencodable(c));
+                    }
+                    buf.push(c as u8);
+                }
+            }
+            Windows1252 => {
+                for c in s.chars() {
+                    buf.push(char_to_windows_1252(c).ok_or_else(|| unencodable(c))?);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The name of this encoding, as used in the protocol.
+    pub fn label(self) -> &'static str {
+        use self::CharacterEncoding::*;
+        match self {
+            Utf8 => "utf-8",
+            Utf8WithBom => "utf-8-bom",
+            Utf16Le => "utf-16le",
+            Utf16LeWithBom => "utf-16le-bom",
+            Utf16Be => "utf-16be",
+            Utf16BeWithBom => "utf-16be-bom",
+            Latin1 => "iso-8859-1",
+            Windows1252 => "windows-1252",
         }
     }
 }
 
-/// Returns the modification timestamp for the file at a given path,
-/// if present.
-fn get_mod_time<P: AsRef<Path>>(path: P) -> Option<SystemTime> {
-    File::open(path).and_then(|f| f.metadata()).and_then(|meta| meta.modified()).ok()
+/// Returns a hash of the decompressed contents of the file at a given path,
+/// if it can be read. As when loading, a file that can't be decompressed
+/// is hashed as it is.
+fn get_content_hash(fs: &dyn FileSystem, path: &Path) -> Option<u64> {
+    hash_contents(fs, path, true).or_else(|| hash_contents(fs, path, false))
 }
 
-/// Returns the file permissions for the file at a given path on UNIXy systems,
-/// if present.
-#[cfg(target_family = "unix")]
-fn get_permissions<P: AsRef<Path>>(path: P) -> Option<u32> {
-    File::open(path).and_then(|f| f.metadata()).map(|meta| meta.permissions().mode()).ok()
+fn hash_contents(fs: &dyn FileSystem, path: &Path, decompress: bool) -> Option<u64> {
+    let mut f = open(fs, path, decompress).ok()?;
+    let mut hasher = FnvHasher::default();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match f.read(&mut buf) {
+            Ok(0) => return Some(hasher.finish()),
+            Ok(n) => hasher.write(&buf[..n]),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(_) => return None,
+        }
+    }
+}
+
+/// A 64-bit FNV-1a hasher. Unlike `DefaultHasher`, whose output is not
+/// guaranteed to be stable across releases, it can be used for hashes that
+/// are written to disk.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        FnvHasher(FNV_OFFSET_BASIS)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ u64::from(b)).wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
 }
 
 impl From<FileError> for RemoteError {
@@ -289,6 +1011,10 @@ impl FileError {
             FileError::Io(_, _) => 5,
             FileError::UnknownEncoding(_) => 6,
             FileError::HasChanged(_) => 7,
+            FileError::NoFile(_) => 8,
+            FileError::Backup(_, _) => 9,
+            FileError::UnsupportedScheme(_) => 10,
+            FileError::Decompression(_, _) => 11,
         }
     }
 }
@@ -304,6 +1030,330 @@ impl fmt::Display for FileError {
                  Please save elsewhere and reload the file. File path: {:?}",
                 p
             ),
+            FileError::NoFile(ref id) => write!(f, "Buffer {} has not been saved to a file", id),
+            FileError::Backup(ref e, ref p) => {
+                write!(f, "Could not write a backup before saving: {}. File path: {:?}", e, p)
+            }
+            FileError::UnsupportedScheme(ref p) => {
+                write!(f, "No file system handles this kind of URI: {:?}", p)
+            }
+            FileError::Decompression(ref e, ref p) => {
+                write!(f, "Could not decompress file: {}. File path: {:?}", e, p)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vfs::{LocalFileSystem, MemoryFileSystem};
+    use std::fs;
+
+    fn try_decode(
+        bytes: Vec<u8>,
+        encoding: CharacterEncoding,
+        path: &Path,
+    ) -> Result<String, FileError> {
+        ChunkDecoder::new(encoding)
+            .decode(&bytes, true)
+            .ok_or_else(|| FileError::UnknownEncoding(path.to_owned()))
+    }
+
+    fn round_trip(bytes: &[u8]) -> (CharacterEncoding, Vec<u8>) {
+        let encoding = CharacterEncoding::guess(bytes);
+        let text = try_decode(bytes.to_vec(), encoding, Path::new("test")).unwrap();
+        let mut out = encoding.bom().to_vec();
+        encoding.encode(&text, &mut out).unwrap();
+        (encoding, out)
+    }
+
+    #[test]
+    fn guess_encoding() {
+        use super::CharacterEncoding::*;
+        assert_eq!(CharacterEncoding::guess(b"hello"), Utf8);
+        assert_eq!(CharacterEncoding::guess(b""), Utf8);
+        assert_eq!(CharacterEncoding::guess(b"\xEF\xBB\xBFhello"), Utf8WithBom);
+        assert_eq!(CharacterEncoding::guess(b"\xFF\xFEh\x00i\x00"), Utf16LeWithBom);
+        assert_eq!(CharacterEncoding::guess(b"\xFE\xFF\x00h\x00i"), Utf16BeWithBom);
+        assert_eq!(CharacterEncoding::guess(b"h\x00e\x00l\x00l\x00o\x00"), Utf16Le);
+        assert_eq!(CharacterEncoding::guess(b"\x00h\x00e\x00l\x00l\x00o"), Utf16Be);
+        assert_eq!(CharacterEncoding::guess(b"caf\xE9"), Latin1);
+        assert_eq!(CharacterEncoding::guess(b"\x93quoted\x94"), Windows1252);
+    }
+
+    #[test]
+    fn decode_legacy() {
+        let path = Path::new("test");
+        let text = try_decode(b"caf\xE9".to_vec(), CharacterEncoding::Latin1, path).unwrap();
+        assert_eq!(text, "café");
+        let text = try_decode(b"\x93hi\x94 \x80".to_vec(), CharacterEncoding::Windows1252, path);
+        assert_eq!(text.unwrap(), "\u{201C}hi\u{201D} €");
+        let emoji = b"\xFF\xFE\x3D\xD8\x00\xDE".to_vec();
+        let text = try_decode(emoji, CharacterEncoding::Utf16LeWithBom, path);
+        assert_eq!(text.unwrap(), "😀");
+        // unpaired surrogate
+        assert!(try_decode(b"\x3D\xD8".to_vec(), CharacterEncoding::Utf16Le, path).is_err());
+        assert!(try_decode(b"a\x00b".to_vec(), CharacterEncoding::Utf16Le, path).is_err());
+    }
+
+    #[test]
+    fn encoding_round_trip() {
+        let inputs: &[&[u8]] = &[
+            b"hello",
+            b"\xEF\xBB\xBFhello",
+            b"\xFF\xFEh\x00i\x00",
+            b"\xFE\xFF\x00h\x00i",
+            b"h\x00e\x00l\x00l\x00o\x00",
+            b"caf\xE9",
+            b"\x93quoted\x94 \x81\x8D\x8F\x90\x9D",
+        ];
+        for bytes in inputs {
+            let (encoding, out) = round_trip(bytes);
+            assert_eq!(&out[..], *bytes, "{:?}", encoding);
+        }
+    }
+
+    #[test]
+    fn unencodable() {
+        let mut buf = Vec::new();
+        assert!(CharacterEncoding::Latin1.encode("€", &mut buf).is_err());
+        assert!(CharacterEncoding::Windows1252.encode("€", &mut buf).is_ok());
+        assert!(CharacterEncoding::Windows1252.encode("😀", &mut buf).is_err());
+    }
+
+    #[test]
+    fn line_endings_round_trip() {
+        let tmp = tempdir::TempDir::new("xi-test-line-endings").unwrap();
+        let path = tmp.path().join("crlf.txt");
+        fs::write(&path, b"\xFF\xFEa\x00\r\x00\n\x00b\x00").unwrap();
+
+        let (rope, info) = try_load_file(&LocalFileSystem, &path, None).unwrap();
+        assert_eq!(String::from(&rope), "a\nb");
+        assert_eq!(info.line_ending, Some(LineEnding::CrLf));
+        assert!(!info.mixed_line_endings);
+
+        let rope = Rope::from("a\nb\nc");
+        try_save(&LocalFileSystem, &path, &rope, info.encoding, LineEnding::CrLf, false, None)
+            .unwrap();
+        let expected = b"\xFF\xFEa\x00\r\x00\n\x00b\x00\r\x00\n\x00c\x00";
+        assert_eq!(fs::read(&path).unwrap(), expected.to_vec());
+
+        fs::write(&path, "a\r\nb\nc\r\n").unwrap();
+        let (rope, info) = try_load_file(&LocalFileSystem, &path, None).unwrap();
+        assert_eq!(String::from(&rope), "a\nb\nc\n");
+        assert_eq!(info.line_ending, Some(LineEnding::CrLf));
+        assert!(info.mixed_line_endings);
+    }
+
+    #[test]
+    fn content_hash() {
+        let tmp = tempdir::TempDir::new("xi-test-content-hash").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "hello\n").unwrap();
+
+        let (_, info) = try_load_file(&LocalFileSystem, &path, None).unwrap();
+        assert_eq!(info.content_hash, get_content_hash(&LocalFileSystem, &path));
+
+        let rope = Rope::from("a\nb\n");
+        let hash = try_save(
+            &LocalFileSystem,
+            &path,
+            &rope,
+            CharacterEncoding::Utf16LeWithBom,
+            LineEnding::CrLf,
+            false,
+            None,
+        );
+        assert_eq!(hash.ok(), get_content_hash(&LocalFileSystem, &path));
+        let (_, info) = try_load_file(&LocalFileSystem, &path, None).unwrap();
+        assert_eq!(info.content_hash, get_content_hash(&LocalFileSystem, &path));
+
+        fs::write(&path, "goodbye\n").unwrap();
+        assert_ne!(info.content_hash, get_content_hash(&LocalFileSystem, &path));
+    }
+
+    #[test]
+    fn chunked_decode() {
+        use super::CharacterEncoding::*;
+        let text = "a\u{e9}\u{20ac}\u{1f600}b";
+        for &encoding in &[Utf8, Utf8WithBom, Utf16LeWithBom, Utf16Be, Windows1252] {
+            let text = if encoding == Windows1252 { "a\u{e9}\u{20ac}b" } else { text };
+            let mut bytes = encoding.bom().to_vec();
+            encoding.encode(text, &mut bytes).unwrap();
+            for split in 0..bytes.len() {
+                let mut decoder = ChunkDecoder::new(encoding);
+                let mut decoded = decoder.decode(&bytes[..split], false).unwrap();
+                decoded.push_str(&decoder.decode(&bytes[split..], true).unwrap());
+                assert_eq!(decoded, text, "{:?} split at {}", encoding, split);
+            }
         }
+        let mut decoder = ChunkDecoder::new(Utf8);
+        assert_eq!(decoder.decode(b"a\xF0\x9F", false), Some("a".into()));
+        assert_eq!(decoder.decode(b"", true), None);
+    }
+
+    #[test]
+    fn load_in_chunks() {
+        let tmp = tempdir::TempDir::new("xi-test-load").unwrap();
+        let path = tmp.path().join("large.txt");
+        // a \r\n and a multi-byte character straddle the chunk boundary
+        let mut bytes = vec![b'a'; LOAD_CHUNK_SIZE - 1];
+        bytes.extend_from_slice("\r\n\u{e9}\n".as_bytes());
+        fs::write(&path, &bytes).unwrap();
+
+        let mut last_progress = (0, 0);
+        let (rope, info) = load_file(&LocalFileSystem, &path, None, |loaded, total| {
+            last_progress = (loaded, total)
+        })
+        .unwrap();
+        assert_eq!(rope.len(), LOAD_CHUNK_SIZE - 1 + "\n\u{e9}\n".len());
+        assert_eq!(rope.slice_to_cow(LOAD_CHUNK_SIZE - 2..), "a\n\u{e9}\n");
+        assert!(info.mixed_line_endings);
+        assert_eq!(info.content_hash, get_content_hash(&LocalFileSystem, &path));
+        assert_eq!(last_progress, (bytes.len() as u64, bytes.len() as u64));
+
+        // not UTF-8 after all
+        bytes.push(0xE9);
+        fs::write(&path, &bytes).unwrap();
+        let (rope, info) = try_load_file(&LocalFileSystem, &path, None).unwrap();
+        assert_eq!(info.encoding, CharacterEncoding::Latin1);
+        assert!(String::from(&rope).ends_with("a\n\u{c3}\u{a9}\n\u{e9}"));
+    }
+
+    #[test]
+    fn binary_round_trip() {
+        let tmp = tempdir::TempDir::new("xi-test-binary").unwrap();
+        let path = tmp.path().join("file.bin");
+        let bytes = (0..=255u8).chain(b"\r\n\0tail".iter().cloned()).collect::<Vec<_>>();
+        fs::write(&path, &bytes).unwrap();
+
+        let (rope, info) = try_load_file(&LocalFileSystem, &path, None).unwrap();
+        assert!(info.binary);
+        assert!(rope.slice_to_cow(..).starts_with("00000000  00 01 02 03"));
+        assert_eq!(info.content_hash, get_content_hash(&LocalFileSystem, &path));
+
+        let rope = Rope::from(String::from(&rope).replacen("00 01", "00 ff", 1));
+        let hash = try_save(
+            &LocalFileSystem,
+            &path,
+            &rope,
+            info.encoding,
+            LineEnding::CrLf,
+            info.binary,
+            None,
+        );
+        assert_eq!(hash.ok(), get_content_hash(&LocalFileSystem, &path));
+        let mut expected = bytes.clone();
+        expected[1] = 0xff;
+        assert_eq!(fs::read(&path).unwrap(), expected);
+
+        // an explicit encoding loads it as text
+        let (_, info) =
+            try_load_file(&LocalFileSystem, &path, Some(CharacterEncoding::Latin1)).unwrap();
+        assert!(!info.binary);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn detect_read_only() {
+        use std::fs::Permissions;
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = tempdir::TempDir::new("xi-test-read-only").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "text").unwrap();
+        let (_, info) = try_load_file(&LocalFileSystem, &path, None).unwrap();
+        assert!(!info.read_only);
+
+        fs::set_permissions(&path, Permissions::from_mode(0o444)).unwrap();
+        let (_, info) = try_load_file(&LocalFileSystem, &path, None).unwrap();
+        assert!(info.read_only);
+    }
+
+    #[test]
+    fn unencodable_save_keeps_file() {
+        let tmp = tempdir::TempDir::new("xi-test-unencodable").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "original\n").unwrap();
+        let rope = Rope::from("\u{1F600}\n");
+        let encoding = CharacterEncoding::Latin1;
+        let result =
+            try_save(&LocalFileSystem, &path, &rope, encoding, LineEnding::Lf, false, None);
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "original\n");
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn memory_round_trip() {
+        let fs = MemoryFileSystem::new();
+        let path = Path::new("mem://dir/file.txt");
+        fs.insert(path, "one\r\ntwo\r\n");
+        let (rope, info) = try_load_file(&fs, path, None).unwrap();
+        assert_eq!(String::from(&rope), "one\ntwo\n");
+        assert_eq!(info.line_ending, Some(LineEnding::CrLf));
+        assert_eq!(info.path, path);
+        assert_eq!(info.content_hash, get_content_hash(&fs, path));
+
+        let rope = Rope::from("three\n");
+        let hash = try_save(&fs, path, &rope, info.encoding, LineEnding::CrLf, false, None);
+        assert_eq!(hash.ok(), get_content_hash(&fs, path));
+        assert_eq!(fs.get(path).unwrap(), b"three\r\n");
+    }
+
+    #[test]
+    fn compressed_round_trip() {
+        use flate2::read::GzDecoder;
+        use flate2::write::GzEncoder;
+
+        let tmp = tempdir::TempDir::new("xi-test-compressed").unwrap();
+        let path = tmp.path().join("fixture.json.gz");
+        let mut w = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        w.write_all(b"{\"a\": 1}\r\n").unwrap();
+        fs::write(&path, w.finish().unwrap()).unwrap();
+
+        let (rope, info) = try_load_file(&LocalFileSystem, &path, None).unwrap();
+        assert_eq!(String::from(&rope), "{\"a\": 1}\n");
+        assert_eq!(info.compression, Some(Compression::Gzip));
+        assert_eq!(info.line_ending, Some(LineEnding::CrLf));
+        assert_eq!(info.content_hash, get_content_hash(&LocalFileSystem, &path));
+
+        let rope = Rope::from("{\"a\": 2}\n");
+        let hash = try_save(
+            &LocalFileSystem,
+            &path,
+            &rope,
+            info.encoding,
+            LineEnding::CrLf,
+            info.binary,
+            info.compression,
+        );
+        assert_eq!(hash.ok(), get_content_hash(&LocalFileSystem, &path));
+        let mut contents = String::new();
+        GzDecoder::new(fs::File::open(&path).unwrap()).read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "{\"a\": 2}\r\n");
+    }
+
+    #[test]
+    fn stable_content_hash() {
+        let fs = MemoryFileSystem::new();
+        let path = Path::new("mem://a.txt");
+        fs.insert(path, "foobar");
+        let (_, info) = try_load_file(&fs, path, None).unwrap();
+        // the published FNV-1a test vector
+        assert_eq!(info.content_hash, Some(0x8594_4171_f739_67e8));
+    }
+
+    #[test]
+    fn load_not_really_compressed() {
+        let fs = MemoryFileSystem::new();
+        let path = Path::new("mem://data.bin");
+        // the gzip magic, followed by an invalid compression method
+        fs.insert(path, vec![0x1f, 0x8b, 0x00, 0x00, 0x01, 0x02]);
+        let (rope, info) = try_load_file(&fs, path, None).unwrap();
+        assert!(info.binary);
+        assert_eq!(info.compression, None);
+        assert!(String::from(&rope).contains("1f 8b 00 00 01 02"));
+        assert_eq!(info.content_hash, get_content_hash(&fs, path));
     }
 }
@@ -0,0 +1,306 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Hex dumps, for viewing and editing binary files.
+//!
+//! The buffer of a binary file holds a dump of its bytes, with one line
+//! for every `BYTES_PER_LINE` bytes:
+//!
+//! ```text
+//! 00000000  48 65 6c 6c 6f 00 01 02 03 04 05 06 07 08 09 0a |Hello...........|
+//! ```
+//!
+//! The only edits allowed are overwriting hex digits, which keeps the layout
+//! of the dump intact; it is converted back to bytes when saved.
+
+use std::fmt::Write;
+use std::io;
+use std::str;
+
+use xi_rope::{DeltaBuilder, Rope, RopeDelta};
+
+/// The number of bytes shown on each line of a dump.
+pub const BYTES_PER_LINE: usize = 16;
+
+/// The column of the first hex digit in each line.
+const HEX_START: usize = 10;
+
+/// The column of the first character after the opening `|` of the
+/// ASCII column.
+const ASCII_START: usize = HEX_START + BYTES_PER_LINE * 3 + 1;
+
+/// Text is considered binary if more than this percentage of a sample of
+/// it is not valid UTF-8.
+const MAX_INVALID_PERCENT: usize = 30;
+
+/// Returns `true` if `sample`, the start of a file, looks like binary data
+/// rather than text: that is, if it contains a NUL byte, or is mostly not
+/// valid UTF-8.
+pub fn is_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
+    }
+    let mut invalid = 0;
+    let mut rest = sample;
+    while let Err(e) = str::from_utf8(rest) {
+        match e.error_len() {
+            Some(len) => {
+                invalid += len;
+                rest = &rest[e.valid_up_to() + len..];
+            }
+            // the sample ends partway through a character
+            None => break,
+        }
+    }
+    invalid * 100 > sample.len() * MAX_INVALID_PERCENT
+}
+
+/// Appends the line of the dump for `bytes`, which start at `offset` in the
+/// file, to `out`. There must be at most `BYTES_PER_LINE` bytes.
+pub fn dump_line(offset: usize, bytes: &[u8], out: &mut String) {
+    debug_assert!(bytes.len() <= BYTES_PER_LINE);
+    let _ = write!(out, "{:08x}  ", offset);
+    for i in 0..BYTES_PER_LINE {
+        match bytes.get(i) {
+            Some(b) => {
+                let _ = write!(out, "{:02x} ", b);
+            }
+            None => out.push_str("   "),
+        }
+    }
+    out.push('|');
+    out.extend(bytes.iter().map(|&b| ascii_char(b)));
+    out.push_str("|\n");
+}
+
+/// Appends the bytes in a line of a dump to `buf`.
+pub fn parse_line(line: &str, buf: &mut Vec<u8>) -> io::Result<()> {
+    let invalid =
+        || io::Error::new(io::ErrorKind::InvalidData, format!("invalid hex dump line: {:?}", line));
+    // the first word is the offset, and the ASCII column starts with '|'
+    let mut count = 0;
+    for word in line.split_whitespace().skip(1).take_while(|w| !w.starts_with('|')) {
+        if word.len() != 2 || !word.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        buf.push(u8::from_str_radix(word, 16).map_err(|_| invalid())?);
+        count += 1;
+    }
+    if count > BYTES_PER_LINE {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+/// Returns the offset in `text`, a dump, of the first hex digit of the byte
+/// at `byte` in the file. Offsets past the end of the file are clamped to
+/// the last byte.
+pub fn offset_of_byte(text: &Rope, byte: usize) -> usize {
+    let n_lines = text.line_of_offset(text.len());
+    let mut line = byte / BYTES_PER_LINE;
+    let mut col = byte % BYTES_PER_LINE;
+    if line >= n_lines {
+        if n_lines == 0 {
+            return 0;
+        }
+        line = n_lines - 1;
+        col = BYTES_PER_LINE - 1;
+    }
+    let line_start = text.offset_of_line(line);
+    let line_len = text.offset_of_line(line + 1) - line_start;
+    // the last line may be short
+    let line_bytes = line_len.saturating_sub(ASCII_START + 2);
+    line_start + HEX_START + col.min(line_bytes.saturating_sub(1)) * 3
+}
+
+/// The location of a hex digit in a dump.
+struct HexDigit {
+    /// The offset of the digit.
+    offset: usize,
+    /// The offset of the byte's character in the ASCII column.
+    ascii_offset: usize,
+    value: u8,
+    is_low: bool,
+    is_last_in_line: bool,
+}
+
+/// Overwrites the hex digit at each of `carets` in `text`, a dump, with
+/// `digit`. Returns the delta, and the new caret positions, which are moved
+/// to the following digit. Returns `None` if `digit` is not a hex digit, or
+/// if no caret is on a hex digit.
+pub(crate) fn overwrite(
+    text: &Rope,
+    carets: &[usize],
+    digit: char,
+) -> Option<(RopeDelta, Vec<usize>)> {
+    let nibble = digit.to_digit(16)? as u8;
+    let digit = digit.to_ascii_lowercase();
+
+    let mut replacements = Vec::new();
+    let mut new_carets = Vec::with_capacity(carets.len());
+    for &caret in carets {
+        let hex = match locate_digit(text, caret) {
+            Some(hex) => hex,
+            None => {
+                new_carets.push(caret);
+                continue;
+            }
+        };
+        let value = if hex.is_low {
+            (hex.value & 0xf0) | nibble
+        } else {
+            (nibble << 4) | (hex.value & 0x0f)
+        };
+        replacements.push((hex.offset, digit));
+        replacements.push((hex.ascii_offset, ascii_char(value)));
+
+        let next_line_start = text.offset_of_line(text.line_of_offset(caret) + 1);
+        let new_caret = if !hex.is_low {
+            caret + 1
+        } else if !hex.is_last_in_line {
+            caret + 2
+        } else if locate_digit(text, next_line_start + HEX_START).is_some() {
+            next_line_start + HEX_START
+        } else {
+            caret + 1
+        };
+        new_carets.push(new_caret);
+    }
+    if replacements.is_empty() {
+        return None;
+    }
+
+    // two carets may be on the same byte
+    replacements.sort_by_key(|(offset, _)| *offset);
+    replacements.dedup_by_key(|(offset, _)| *offset);
+    let mut builder = DeltaBuilder::new(text.len());
+    for (offset, c) in replacements {
+        builder.replace(offset..offset + 1, Rope::from(c.to_string()));
+    }
+    Some((builder.build(), new_carets))
+}
+
+fn locate_digit(text: &Rope, offset: usize) -> Option<HexDigit> {
+    if offset >= text.len() {
+        return None;
+    }
+    let line_start = text.offset_of_line(text.line_of_offset(offset));
+    let col = offset - line_start;
+    let hex_columns = HEX_START..HEX_START + BYTES_PER_LINE * 3;
+    if !hex_columns.contains(&col) || (col - HEX_START) % 3 == 2 {
+        return None;
+    }
+    let index = (col - HEX_START) / 3;
+    let byte_start = line_start + HEX_START + index * 3;
+    let ascii_offset = line_start + ASCII_START + index;
+    if ascii_offset >= text.len() {
+        return None;
+    }
+    let hex = text.slice_to_cow(byte_start..byte_start + 2);
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    Some(HexDigit {
+        offset,
+        ascii_offset,
+        value: u8::from_str_radix(&hex, 16).ok()?,
+        is_low: offset > byte_start,
+        is_last_in_line: index == BYTES_PER_LINE - 1,
+    })
+}
+
+fn ascii_char(b: u8) -> char {
+    match b {
+        0x20..=0x7e => b as char,
+        _ => '.',
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dump(bytes: &[u8]) -> Rope {
+        let mut s = String::new();
+        for (i, chunk) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+            dump_line(i * BYTES_PER_LINE, chunk, &mut s);
+        }
+        Rope::from(s)
+    }
+
+    fn parse(text: &Rope) -> Vec<u8> {
+        let mut buf = Vec::new();
+        for line in text.lines_raw(..) {
+            parse_line(&line, &mut buf).unwrap();
+        }
+        buf
+    }
+
+    #[test]
+    fn detect_binary() {
+        assert!(!is_binary(b"plain text\n"));
+        assert!(!is_binary("caf\u{e9}".as_bytes()));
+        assert!(!is_binary(b"mostly text, with one \xe9"));
+        assert!(is_binary(b"text\0with a nul"));
+        assert!(is_binary(b"\x89\xff\xfe\x80\x81text"));
+    }
+
+    #[test]
+    fn dump_and_parse() {
+        let bytes = (0..40u8).map(|b| b.wrapping_mul(7)).collect::<Vec<_>>();
+        let text = dump(&bytes);
+        assert_eq!(text.line_of_offset(text.len()), 3);
+        assert_eq!(
+            String::from(text.slice(..text.offset_of_line(1))),
+            "00000000  00 07 0e 15 1c 23 2a 31 38 3f 46 4d 54 5b 62 69 |.....#*18?FMT[bi|\n"
+        );
+        assert_eq!(parse(&text), bytes);
+
+        let mut buf = Vec::new();
+        assert!(parse_line("00000000  0g |.|\n", &mut buf).is_err());
+        assert!(parse_line("00000000  123 |.|\n", &mut buf).is_err());
+    }
+
+    #[test]
+    fn byte_offsets() {
+        let text = dump(b"0123456789abcdefghij");
+        assert_eq!(offset_of_byte(&text, 0), HEX_START);
+        assert_eq!(offset_of_byte(&text, 3), HEX_START + 9);
+        let second_line = text.offset_of_line(1);
+        assert_eq!(offset_of_byte(&text, 17), second_line + HEX_START + 3);
+        // clamped to the last byte
+        assert_eq!(offset_of_byte(&text, 100), second_line + HEX_START + 9);
+    }
+
+    #[test]
+    fn overwrite_digits() {
+        let text = dump(b"0123456789abcdefghij");
+        let caret = offset_of_byte(&text, 15);
+        let (delta, carets) = overwrite(&text, &[caret], 'A').unwrap();
+        let text = delta.apply(&text);
+        assert_eq!(carets, vec![caret + 1]);
+        assert_eq!(parse(&text)[15], 0xa6);
+
+        let (delta, carets) = overwrite(&text, &carets, '1').unwrap();
+        let text = delta.apply(&text);
+        assert_eq!(parse(&text)[15], 0xa1);
+        // moved to the first byte of the next line
+        assert_eq!(carets, vec![offset_of_byte(&text, 16)]);
+        assert!(String::from(&text).contains("|0123456789abcde.|"));
+
+        assert!(overwrite(&text, &carets, 'x').is_none());
+        // the offset column can't be edited
+        assert!(overwrite(&text, &[0], '1').is_none());
+    }
+}
@@ -137,6 +137,25 @@ impl Layers {
         self.merged.edit(iv, resolved);
     }
 
+    /// Returns `true` if any layer gives the text at `offset` a scope that
+    /// starts with `prefix`, such as "string" or "comment".
+    pub fn has_scope_at(&self, offset: usize, prefix: &str) -> bool {
+        let prefix = match Scope::new(prefix) {
+            Ok(prefix) => prefix,
+            Err(_) => return false,
+        };
+        if offset >= self.merged.len() {
+            return false;
+        }
+        let iv = Interval::new(offset, offset + 1);
+        self.layers.values().any(|layer| {
+            layer.scope_spans.subseq(iv).iter().any(|(_, &id)| {
+                let stack = layer.stack_lookup.get(id as usize);
+                stack.map(|stack| stack.iter().any(|&s| prefix.is_prefix_of(s))).unwrap_or(false)
+            })
+        })
+    }
+
     /// Prints scopes and style information for the given `Interval`.
     pub fn debug_print_spans(&self, iv: Interval) {
         for (id, layer) in &self.layers {
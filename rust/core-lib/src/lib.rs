@@ -40,12 +40,15 @@ extern crate serde;
 extern crate serde_json;
 #[macro_use]
 extern crate serde_derive;
+extern crate flate2;
 extern crate memchr;
 #[cfg(feature = "notify")]
 extern crate notify;
 extern crate syntect;
 extern crate time;
 extern crate toml;
+extern crate xz2;
+extern crate zip;
 
 extern crate xi_rope;
 extern crate xi_rpc;
@@ -67,8 +70,14 @@ mod ledger_includes {
 use ledger_includes::*;
 
 pub mod annotations;
+pub mod archive;
+pub mod auto_pairs;
 pub mod backspace;
+pub mod backup;
+pub mod brackets;
 pub mod client;
+pub mod column_selection;
+pub mod compression;
 pub mod config;
 pub mod core;
 pub mod edit_types;
@@ -78,18 +87,28 @@ pub mod file;
 pub mod find;
 #[cfg(feature = "ledger")]
 pub mod fuchsia;
+pub mod hex;
 pub mod index_set;
 pub mod layers;
 pub mod line_cache_shadow;
 pub mod line_ending;
+pub mod line_ops;
 pub mod linewrap;
+pub mod merge;
 pub mod movement;
+pub mod persistent_undo;
 pub mod plugins;
 pub mod recorder;
+pub mod recovery;
+pub mod reflow;
 pub mod selection;
+pub mod selection_history;
+pub mod session;
 pub mod styles;
 pub mod syntax;
 pub mod tabs;
+pub mod undo_tree;
+pub mod vfs;
 pub mod view;
 #[cfg(feature = "notify")]
 pub mod watcher;
@@ -16,11 +16,12 @@
 
 extern crate xi_rope;
 
-use memchr::memchr2;
-use xi_rope::Rope;
+use memchr::{memchr2, memchr_iter};
+use xi_rope::{DeltaElement, Rope, RopeDelta};
 
 /// An enumeration of valid line endings
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum LineEnding {
     CrLf, // DOS style, \r\n
     Lf,   // *nix style, \n
@@ -30,7 +31,75 @@ pub enum LineEnding {
 #[derive(Debug)]
 pub struct MixedLineEndingError;
 
+/// The number of each kind of line ending found in a document.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineEndingCounts {
+    pub crlf: usize,
+    pub lf: usize,
+}
+
+impl LineEndingCounts {
+    /// Returns the most common line ending, or `None` if there were no
+    /// line endings. Ties go to `Lf`.
+    pub fn predominant(&self) -> Option<LineEnding> {
+        match (self.crlf, self.lf) {
+            (0, 0) => None,
+            (crlf, lf) if crlf > lf => Some(LineEnding::CrLf),
+            _ => Some(LineEnding::Lf),
+        }
+    }
+
+    /// Returns `true` if both kinds of line ending were found.
+    pub fn is_mixed(&self) -> bool {
+        self.crlf > 0 && self.lf > 0
+    }
+}
+
 impl LineEnding {
+    /// Returns the characters that make up this line ending.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Lf => "\n",
+        }
+    }
+
+    /// Converts all `\r\n` line endings in `text` to `\n`, which is what
+    /// we use internally, returning the normalized text along with counts of
+    /// the line endings in the original. A lone `\r` is left as is.
+    pub fn normalize(text: String) -> (String, LineEndingCounts) {
+        let bytes = text.as_bytes();
+        let mut counts = LineEndingCounts::default();
+        for ix in memchr_iter(b'\n', bytes) {
+            if ix > 0 && bytes[ix - 1] == b'\r' {
+                counts.crlf += 1;
+            } else {
+                counts.lf += 1;
+            }
+        }
+        if counts.crlf == 0 {
+            (text, counts)
+        } else {
+            (text.replace("\r\n", "\n"), counts)
+        }
+    }
+
+    /// Converts the `\r\n` line endings in the text inserted by `delta` to
+    /// `\n`, so that edits keep the buffer normalized.
+    pub fn normalize_delta(mut delta: RopeDelta) -> RopeDelta {
+        for el in delta.els.iter_mut() {
+            if let DeltaElement::Insert(rope) = el {
+                if rope.iter_chunks(..).any(|chunk| chunk.contains('\r')) {
+                    let (text, counts) = LineEnding::normalize(String::from(&*rope));
+                    if counts.crlf > 0 {
+                        *rope = Rope::from(text);
+                    }
+                }
+            }
+        }
+        delta
+    }
+
     /// Breaks a rope down into chunks, and checks each chunk for line endings
     pub fn parse(rope: &Rope) -> Result<Option<Self>, MixedLineEndingError> {
         let mut crlf = false;
@@ -95,4 +164,34 @@ mod tests {
     fn bad_space() {
         assert!(LineEnding::parse_chunk("\r \n").is_err());
     }
+
+    #[test]
+    fn normalize() {
+        let (text, counts) = LineEnding::normalize("a\r\nb\r\nc".into());
+        assert_eq!(text, "a\nb\nc");
+        assert_eq!(counts.predominant(), Some(LineEnding::CrLf));
+        assert!(!counts.is_mixed());
+
+        let (text, counts) = LineEnding::normalize("a\r\nb\nc\n\rd".into());
+        assert_eq!(text, "a\nb\nc\n\rd");
+        assert_eq!(counts, LineEndingCounts { crlf: 1, lf: 2 });
+        assert_eq!(counts.predominant(), Some(LineEnding::Lf));
+        assert!(counts.is_mixed());
+
+        let (text, counts) = LineEnding::normalize("abc".into());
+        assert_eq!(text, "abc");
+        assert_eq!(counts.predominant(), None);
+    }
+
+    #[test]
+    fn normalize_delta() {
+        use xi_rope::{DeltaBuilder, Interval};
+
+        let base = Rope::from("ab\ncd");
+        let mut builder = DeltaBuilder::new(base.len());
+        builder.replace(Interval::new(1, 1), "x\r\ny\r".into());
+        builder.replace(Interval::new(3, 4), "z\r\n".into());
+        let delta = LineEnding::normalize_delta(builder.build());
+        assert_eq!(String::from(delta.apply(&base)), "ax\ny\rb\nz\nd");
+    }
 }
@@ -0,0 +1,470 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Commands that rearrange whole lines: moving, sorting, removing
+//! duplicates, reversing and joining.
+//!
+//! Lines here are logical lines, regardless of wrapping. The empty line
+//! after a final newline isn't counted, so that commands never move
+//! the final newline into the middle of the buffer.
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::iter;
+use std::ops::Range;
+
+use xi_rope::{DeltaBuilder, Interval, LinesMetric, Rope, RopeDelta, Transformer};
+
+use crate::selection::{SelRegion, Selection};
+
+/// How `sort_lines` compares lines.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SortOptions {
+    /// Ignore differences in case.
+    pub(crate) case_insensitive: bool,
+    /// Compare runs of digits by their value, so that "a2" sorts before "a10".
+    pub(crate) natural: bool,
+    /// Compare lines by the number they start with. Lines that don't start
+    /// with a number sort first.
+    pub(crate) numeric: bool,
+    /// Sort in descending order.
+    pub(crate) reverse: bool,
+}
+
+/// A new order for a range of lines. Lines of the range that are left out
+/// of `order` are removed.
+#[derive(Debug)]
+pub(crate) struct LineRewrite {
+    pub(crate) lines: Range<usize>,
+    /// The lines of the range, in their new order.
+    pub(crate) order: Vec<usize>,
+}
+
+/// Returns the number of lines in `text`, not counting the empty line after
+/// a final newline.
+pub(crate) fn n_lines(text: &Rope) -> usize {
+    let newlines = text.measure::<LinesMetric>();
+    if text.is_empty() || text.byte_at(text.len() - 1) == b'\n' {
+        newlines.max(1)
+    } else {
+        newlines + 1
+    }
+}
+
+/// Returns the start and end of `line`, not including its newline.
+pub(crate) fn line_bounds(text: &Rope, line: usize) -> (usize, usize) {
+    let start = text.offset_of_line(line);
+    let end = text.offset_of_line(line + 1);
+    if end > start && text.byte_at(end - 1) == b'\n' {
+        (start, end - 1)
+    } else {
+        (start, end)
+    }
+}
+
+/// Returns the lines that `region` is on. A region ending at the start of
+/// a line doesn't include that line.
+pub(crate) fn region_lines(text: &Rope, region: &SelRegion) -> Range<usize> {
+    let max_line = n_lines(text) - 1;
+    let first = text.line_of_offset(region.min()).min(max_line);
+    let mut last = text.line_of_offset(region.max()).min(max_line);
+    if last > first && text.offset_of_line(last) == region.max() {
+        last -= 1;
+    }
+    first..last + 1
+}
+
+/// Returns the lines of each region, merging those that overlap, or touch
+/// if `merge_adjacent` is set.
+pub(crate) fn selection_lines(
+    text: &Rope,
+    regions: &[SelRegion],
+    merge_adjacent: bool,
+) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for region in regions {
+        let lines = region_lines(text, region);
+        match ranges.last_mut() {
+            Some(last) if lines.start < last.end || merge_adjacent && lines.start == last.end => {
+                last.end = last.end.max(lines.end);
+            }
+            _ => ranges.push(lines),
+        }
+    }
+    ranges
+}
+
+/// Returns the ranges of lines a command that sorts or filters lines acts on:
+/// the lines of each selected region, or the whole buffer if nothing is
+/// selected.
+pub(crate) fn selected_or_all_lines(text: &Rope, regions: &[SelRegion]) -> Vec<Range<usize>> {
+    let selected = regions.iter().filter(|r| !r.is_caret()).cloned().collect::<Vec<_>>();
+    match selected.is_empty() {
+        true => iter::once(0..n_lines(text)).collect(),
+        false => selection_lines(text, &selected, false),
+    }
+}
+
+/// Returns the order that sorts `lines`. The sort is stable.
+pub(crate) fn sort_order<S: AsRef<str>>(lines: &[S], options: SortOptions) -> Vec<usize> {
+    let keys = lines
+        .iter()
+        .map(|line| match options.case_insensitive {
+            true => line.as_ref().to_lowercase(),
+            false => line.as_ref().to_owned(),
+        })
+        .collect::<Vec<_>>();
+    let mut order = (0..lines.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| {
+        let ordering = compare_lines(&keys[a], &keys[b], options);
+        match options.reverse {
+            true => ordering.reverse(),
+            false => ordering,
+        }
+    });
+    order
+}
+
+/// Returns the order that keeps the first of each set of equal lines.
+pub(crate) fn unique_order<S: AsRef<str>>(lines: &[S]) -> Vec<usize> {
+    let mut seen = HashSet::new();
+    (0..lines.len()).filter(|&i| seen.insert(lines[i].as_ref())).collect()
+}
+
+fn compare_lines(a: &str, b: &str, options: SortOptions) -> Ordering {
+    let by_number = match options.numeric {
+        true => leading_number(a).partial_cmp(&leading_number(b)).unwrap_or(Ordering::Equal),
+        false => Ordering::Equal,
+    };
+    by_number.then_with(|| match options.natural {
+        true => natural_cmp(a, b),
+        false => a.cmp(b),
+    })
+}
+
+/// Parses the number at the start of `s`, after any whitespace.
+fn leading_number(s: &str) -> Option<f64> {
+    let s = s.trim_start();
+    let mut end = 0;
+    let mut seen_digit = false;
+    let mut seen_point = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '-' | '+' if i == 0 => (),
+            '.' if !seen_point => seen_point = true,
+            c if c.is_ascii_digit() => seen_digit = true,
+            _ => break,
+        }
+        end = i + c.len_utf8();
+    }
+    match seen_digit {
+        true => s[..end].trim_end_matches('.').parse().ok(),
+        false => None,
+    }
+}
+
+/// Compares strings, treating each run of digits as a number.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (ca, cb) = match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ca), Some(cb)) => (ca, cb),
+        };
+        if ca.is_ascii_digit() && cb.is_ascii_digit() {
+            let (na, rest_a) = split_digits(a);
+            let (nb, rest_b) = split_digits(b);
+            let (na, nb) = (na.trim_start_matches('0'), nb.trim_start_matches('0'));
+            match na.len().cmp(&nb.len()).then_with(|| na.cmp(nb)) {
+                Ordering::Equal => (),
+                ordering => return ordering,
+            }
+            a = rest_a;
+            b = rest_b;
+        } else {
+            match ca.cmp(&cb) {
+                Ordering::Equal => (),
+                ordering => return ordering,
+            }
+            a = &a[ca.len_utf8()..];
+            b = &b[cb.len_utf8()..];
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// A position in a line that is being rewritten.
+#[derive(Debug, Clone, Copy)]
+struct LinePos {
+    rewrite: usize,
+    line: usize,
+    /// One past the end of the line for the start of the next line.
+    col: usize,
+}
+
+/// Builds the delta for `rewrites`, which are in order and don't overlap,
+/// and moves the regions of `selection` along with the lines they are on.
+/// A region across lines that don't stay together is stretched over the
+/// whole range instead. Returns `None` if the text wouldn't change.
+pub(crate) fn rewrite_lines(
+    text: &Rope,
+    rewrites: &[LineRewrite],
+    selection: &Selection,
+) -> Option<(RopeDelta, Selection)> {
+    let mut builder = DeltaBuilder::new(text.len());
+    // for each rewrite, the lines in their new order, with their new start
+    // relative to the start of the range
+    let mut new_starts = Vec::new();
+    for rewrite in rewrites {
+        let start = line_bounds(text, rewrite.lines.start).0;
+        let end = line_bounds(text, rewrite.lines.end - 1).1;
+        let mut new_text = String::new();
+        let mut starts = Vec::new();
+        for &line in &rewrite.order {
+            if !starts.is_empty() {
+                new_text.push('\n');
+            }
+            starts.push((line, new_text.len()));
+            let (line_start, line_end) = line_bounds(text, line);
+            new_text.push_str(&text.slice_to_cow(line_start..line_end));
+        }
+        if text.slice_to_cow(start..end) != new_text {
+            builder.replace(Interval::new(start, end), Rope::from(new_text));
+        }
+        new_starts.push(starts);
+    }
+    if builder.is_empty() {
+        return None;
+    }
+    let delta = builder.build();
+    let mut transformer = Transformer::new(&delta);
+    let range_starts = rewrites
+        .iter()
+        .map(|r| transformer.transform(line_bounds(text, r.lines.start).0, false))
+        .collect::<Vec<_>>();
+
+    let locate = |offset: usize, is_end: bool| {
+        let line = text.line_of_offset(offset);
+        let in_range = |r: &LineRewrite| r.lines.start <= line && line < r.lines.end;
+        if let Some(rewrite) = rewrites.iter().position(in_range) {
+            let col = offset - text.offset_of_line(line);
+            return Some(LinePos { rewrite, line, col });
+        }
+        // a region ending at the start of the line after a range
+        let ends_range = |r: &LineRewrite| r.lines.end == line;
+        match rewrites.iter().position(ends_range) {
+            Some(rewrite) if is_end && offset == text.offset_of_line(line) => {
+                let (line_start, line_end) = line_bounds(text, line - 1);
+                Some(LinePos { rewrite, line: line - 1, col: line_end - line_start + 1 })
+            }
+            _ => None,
+        }
+    };
+    let order_index = |pos: LinePos| {
+        let order = &rewrites[pos.rewrite].order;
+        order.iter().position(|&line| line == pos.line)
+    };
+    let new_offset = |pos: LinePos| {
+        let starts = &new_starts[pos.rewrite];
+        // a removed line's regions go to the line that was kept instead
+        let (line_start, line_end) = line_bounds(text, pos.line);
+        let content = text.slice_to_cow(line_start..line_end);
+        let new_start = order_index(pos)
+            .or_else(|| {
+                starts.iter().position(|&(kept, _)| {
+                    let (kept_start, kept_end) = line_bounds(text, kept);
+                    text.slice_to_cow(kept_start..kept_end) == content
+                })
+            })
+            .map(|i| starts[i].1)
+            .unwrap_or(0);
+        range_starts[pos.rewrite] + new_start + pos.col
+    };
+
+    let mut new_sel = Selection::new();
+    for region in selection.iter() {
+        let (min, max) = (region.min(), region.max());
+        let positions = (locate(min, false), locate(max, !region.is_caret()));
+        let stays_together = |lo: LinePos, hi: LinePos| {
+            let order = &rewrites[lo.rewrite].order;
+            match (order_index(lo), order_index(hi)) {
+                _ if lo.line == hi.line => true,
+                (Some(a), Some(b)) if a <= b && b - a == hi.line - lo.line => {
+                    order[a..=b].iter().zip(lo.line..).all(|(&line, want)| line == want)
+                }
+                _ => false,
+            }
+        };
+        let (new_min, new_max) = match positions {
+            (Some(lo), Some(hi)) if lo.rewrite == hi.rewrite && !stays_together(lo, hi) => {
+                let range_end = line_bounds(text, rewrites[lo.rewrite].lines.end - 1).1;
+                let end = if max <= range_end { range_end } else { max };
+                (range_starts[lo.rewrite], transformer.transform(end, true))
+            }
+            (lo, hi) => (
+                lo.map(new_offset).unwrap_or_else(|| transformer.transform(min, true)),
+                hi.map(new_offset).unwrap_or_else(|| transformer.transform(max, true)),
+            ),
+        };
+        new_sel.add_region(match region.start <= region.end {
+            true => SelRegion::new(new_min, new_max),
+            false => SelRegion::new(new_max, new_min),
+        });
+    }
+    Some((delta, new_sel))
+}
+
+/// Builds the delta that joins the lines of each region, or each caret's
+/// line with the next one. The whitespace around each joined newline is
+/// collapsed into a single space, or removed next to an empty line or
+/// inside brackets.
+pub(crate) fn join_lines(text: &Rope, regions: &[SelRegion]) -> Option<RopeDelta> {
+    let n_lines = n_lines(text);
+    let mut joins: Vec<usize> = Vec::new();
+    for region in regions {
+        let lines = region_lines(text, region);
+        let last = match lines.len() {
+            1 => lines.start,
+            _ => lines.end - 2,
+        };
+        for line in lines.start..=last {
+            if joins.last().map(|&prev| prev < line).unwrap_or(true) && line + 1 < n_lines {
+                joins.push(line);
+            }
+        }
+    }
+
+    let mut builder = DeltaBuilder::new(text.len());
+    // the last char of the joined line so far, other than whitespace
+    let mut last_char = None;
+    let mut prev_join = None;
+    for line in joins {
+        let (start, end) = line_bounds(text, line);
+        let left = text.slice_to_cow(start..end);
+        let continues = prev_join.map(|prev| prev + 1) == Some(line);
+        if !continues {
+            last_char = left.trim_end().chars().last();
+        }
+        let (next_start, next_end) = line_bounds(text, line + 1);
+        let right = text.slice_to_cow(next_start..next_end);
+        let right_trimmed = right.trim_start();
+
+        // if this line was joined to the previous one, its leading
+        // whitespace has already been removed
+        let trailing_start = start + left.trim_end().len();
+        let trim_start = match continues {
+            true => trailing_start.max(end - left.trim_start().len()),
+            false => trailing_start,
+        };
+        let trim_end = next_end - right_trimmed.len();
+        let separator = match (last_char, right_trimmed.chars().next()) {
+            (None, _) | (_, None) => "",
+            (Some('('), _) | (Some('['), _) | (Some('{'), _) => "",
+            (_, Some(')')) | (_, Some(']')) | (_, Some('}')) => "",
+            _ => " ",
+        };
+        if let Some(c) = right_trimmed.trim_end().chars().last() {
+            last_char = Some(c);
+        }
+        builder.replace(Interval::new(trim_start, trim_end), Rope::from(separator));
+        prev_join = Some(line);
+    }
+    match builder.is_empty() {
+        true => None,
+        false => Some(builder.build()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(lines: &[&str], options: SortOptions) -> Vec<String> {
+        sort_order(lines, options).into_iter().map(|i| lines[i].to_owned()).collect()
+    }
+
+    fn join(text: &str, regions: &[(usize, usize)]) -> String {
+        let mut text = Rope::from(text);
+        let regions = regions.iter().map(|&(s, e)| SelRegion::new(s, e)).collect::<Vec<_>>();
+        if let Some(delta) = join_lines(&text, &regions) {
+            text = delta.apply(&text);
+        }
+        String::from(text)
+    }
+
+    #[test]
+    fn sorting() {
+        let lines = ["b10", "B2", "a", "b2", "3 x", "-1.5 y"];
+        let options = SortOptions::default();
+        assert_eq!(sorted(&lines, options), ["-1.5 y", "3 x", "B2", "a", "b10", "b2"]);
+        let options = SortOptions { case_insensitive: true, ..SortOptions::default() };
+        assert_eq!(sorted(&lines, options), ["-1.5 y", "3 x", "a", "b10", "B2", "b2"]);
+        let options = SortOptions { natural: true, case_insensitive: true, ..options };
+        assert_eq!(sorted(&lines, options), ["-1.5 y", "3 x", "a", "B2", "b2", "b10"]);
+        let options = SortOptions { numeric: true, ..SortOptions::default() };
+        assert_eq!(sorted(&lines, options), ["B2", "a", "b10", "b2", "-1.5 y", "3 x"]);
+        let options = SortOptions { reverse: true, ..options };
+        assert_eq!(sorted(&lines, options), ["3 x", "-1.5 y", "b2", "b10", "a", "B2"]);
+    }
+
+    #[test]
+    fn unique() {
+        assert_eq!(unique_order(&["a", "b", "a", "", "b", ""]), vec![0, 1, 3]);
+    }
+
+    #[test]
+    fn rewrite_moves_regions() {
+        let text = Rope::from("one\ntwo\nthree\n");
+        // swap the first two lines, with carets in each
+        let rewrites = [LineRewrite { lines: 0..2, order: vec![1, 0] }];
+        let mut sel = Selection::new();
+        sel.add_region(SelRegion::caret(1));
+        sel.add_region(SelRegion::new(6, 7));
+        sel.add_region(SelRegion::caret(9));
+        let (delta, sel) = rewrite_lines(&text, &rewrites, &sel).unwrap();
+        assert_eq!(String::from(delta.apply(&text)), "two\none\nthree\n");
+        let regions = sel.iter().map(|r| (r.start, r.end)).collect::<Vec<_>>();
+        assert_eq!(regions, vec![(2, 3), (5, 5), (9, 9)]);
+
+        // nothing changes
+        let rewrites = [LineRewrite { lines: 0..3, order: vec![0, 1, 2] }];
+        assert!(rewrite_lines(&text, &rewrites, &sel).is_none());
+
+        // a removed line's caret goes to the line that is kept
+        let text = Rope::from("a\nb\na\n");
+        let rewrites = [LineRewrite { lines: 0..3, order: vec![0, 1] }];
+        let sel = Selection::new_simple(SelRegion::caret(5));
+        let (delta, sel) = rewrite_lines(&text, &rewrites, &sel).unwrap();
+        assert_eq!(String::from(delta.apply(&text)), "a\nb\n");
+        assert_eq!(sel[0], SelRegion::caret(1));
+    }
+
+    #[test]
+    fn joining() {
+        let text = "fn f(\n    a,\n    b\n) {\n\n  x\n}";
+        assert_eq!(join(text, &[(0, 0)]), "fn f(a,\n    b\n) {\n\n  x\n}");
+        assert_eq!(join(text, &[(0, 18)]), "fn f(a, b\n) {\n\n  x\n}");
+        assert_eq!(join(text, &[(0, 20)]), "fn f(a, b) {\n\n  x\n}");
+        assert_eq!(join(text, &[(20, 27)]), "fn f(\n    a,\n    b\n) {x\n}");
+        assert_eq!(join(text, &[(27, 27)]), "fn f(\n    a,\n    b\n) {\n\n  x}");
+        assert_eq!(join(text, &[(29, 29)]), text);
+        assert_eq!(join("a  \n  \n \n  b", &[(0, 10)]), "a b");
+    }
+}
@@ -0,0 +1,342 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Three-way merging of changes made on disk into a modified buffer.
+//!
+//! Both the buffer ("mine") and the new file contents ("theirs") are diffed
+//! against the text that was last loaded or saved ("base"). Changes are
+//! grouped into line-aligned hunks; a hunk changed on only one side is taken
+//! from that side, and a hunk changed differently on both sides is a
+//! conflict. Conflicts keep the buffer's version, and remember the other
+//! version so that the user can choose between them.
+
+use xi_rope::delta::DeltaElement;
+use xi_rope::diff::{Diff, LineHashDiff};
+use xi_rope::{DeltaBuilder, Interval, Rope, RopeDelta, Transformer};
+
+use crate::annotations::{AnnotationSlice, AnnotationType, ToAnnotation};
+use crate::selection::SelRegion;
+use crate::view::View;
+
+/// A region of the buffer that was changed both in the buffer and on disk.
+#[derive(Debug, Clone)]
+pub struct Conflict {
+    /// The region of the buffer holding our version of the text.
+    pub iv: Interval,
+    /// The version of this region on disk.
+    pub theirs: Rope,
+}
+
+/// The unresolved conflicts in a buffer.
+#[derive(Debug, Default)]
+pub struct Conflicts {
+    items: Vec<Conflict>,
+}
+
+/// The result of merging.
+pub(crate) struct Merge {
+    /// A delta that applies the non-conflicting changes from disk to the buffer.
+    pub(crate) delta: RopeDelta,
+    /// Conflicting regions, in the coordinates of the buffer after `delta`
+    /// is applied.
+    pub(crate) conflicts: Vec<Conflict>,
+}
+
+/// A change to the base text, replacing `start..end` with `text`.
+#[derive(Debug)]
+struct Hunk {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+/// Which side of the merge a hunk belongs to.
+#[derive(Clone, Copy, PartialEq)]
+enum Side {
+    Mine,
+    Theirs,
+}
+
+/// Merges the changes between `base` and `theirs` into `mine`.
+pub(crate) fn merge(base: &Rope, mine: &Rope, theirs: &Rope) -> Merge {
+    let mut hunks = hunks(&LineHashDiff::compute_delta(base, mine))
+        .into_iter()
+        .map(|h| (Side::Mine, expand_to_lines(base, &h), h))
+        .chain(
+            hunks(&LineHashDiff::compute_delta(base, theirs))
+                .into_iter()
+                .map(|h| (Side::Theirs, expand_to_lines(base, &h), h)),
+        )
+        .collect::<Vec<_>>();
+    hunks.sort_by_key(|(_, iv, _)| (iv.start(), iv.end()));
+
+    let mut builder = DeltaBuilder::new(mine.len());
+    let mut conflicts = Vec::new();
+    // the offset of a base position in `mine`, and in the merged text.
+    let mut mine_shift = 0isize;
+    let mut merged_shift = 0isize;
+
+    let mut i = 0;
+    while i < hunks.len() {
+        let mut cluster = hunks[i].1;
+        let mut j = i + 1;
+        while j < hunks.len() {
+            let iv = hunks[j].1;
+            if iv.start() < cluster.end() || iv.start() == cluster.start() {
+                cluster = cluster.union(iv);
+                j += 1;
+            } else {
+                break;
+            }
+        }
+
+        let cluster_hunks = &hunks[i..j];
+        let changed = |side| cluster_hunks.iter().any(|(s, _, _)| *s == side);
+        let side_text = |side| {
+            let hunks = cluster_hunks.iter().filter(|(s, _, _)| *s == side).map(|(_, _, h)| h);
+            apply_hunks(base, cluster, hunks)
+        };
+
+        let base_len = cluster.size() as isize;
+        let mine_start = (cluster.start() as isize + mine_shift) as usize;
+        let mine_text = side_text(Side::Mine);
+        let mine_len = mine_text.len() as isize;
+
+        if !changed(Side::Mine) {
+            let theirs_text = side_text(Side::Theirs);
+            let theirs_len = theirs_text.len() as isize;
+            builder.replace(
+                Interval::new(mine_start, mine_start + mine_len as usize),
+                theirs_text.into(),
+            );
+            merged_shift += theirs_len - mine_len;
+        } else if changed(Side::Theirs) {
+            let theirs_text = side_text(Side::Theirs);
+            if theirs_text != mine_text {
+                let start = (mine_start as isize + merged_shift) as usize;
+                let iv = Interval::new(start, start + mine_len as usize);
+                conflicts.push(Conflict { iv, theirs: theirs_text.into() });
+            }
+        }
+        mine_shift += mine_len - base_len;
+        i = j;
+    }
+
+    Merge { delta: builder.build(), conflicts }
+}
+
+/// Converts a delta into the list of regions of the base text it changes.
+fn hunks(delta: &RopeDelta) -> Vec<Hunk> {
+    let mut hunks = Vec::new();
+    let mut pos = 0;
+    let mut inserted: Option<String> = None;
+    for el in &delta.els {
+        match el {
+            DeltaElement::Copy(start, end) => {
+                if *start > pos || inserted.is_some() {
+                    let text = inserted.take().unwrap_or_default();
+                    hunks.push(Hunk { start: pos, end: *start, text });
+                }
+                pos = *end;
+            }
+            DeltaElement::Insert(node) => {
+                inserted.get_or_insert_with(String::new).push_str(&String::from(node));
+            }
+        }
+    }
+    if pos < delta.base_len || inserted.is_some() {
+        let text = inserted.unwrap_or_default();
+        hunks.push(Hunk { start: pos, end: delta.base_len, text });
+    }
+    hunks
+}
+
+/// Returns the interval of whole lines in `base` touched by `hunk`.
+fn expand_to_lines(base: &Rope, hunk: &Hunk) -> Interval {
+    let start = base.offset_of_line(base.line_of_offset(hunk.start));
+    let end_line = base.line_of_offset(hunk.end);
+    let end = if hunk.end > start && hunk.end == base.offset_of_line(end_line) {
+        hunk.end
+    } else {
+        base.offset_of_line(end_line + 1)
+    };
+    Interval::new(start, end)
+}
+
+/// Returns the text of `iv` in `base` after applying `hunks`, which must be
+/// sorted and contained in `iv`.
+fn apply_hunks<'a, I>(base: &Rope, iv: Interval, hunks: I) -> String
+where
+    I: Iterator<Item = &'a Hunk>,
+{
+    let mut result = String::new();
+    let mut pos = iv.start();
+    for hunk in hunks {
+        result.push_str(&base.slice_to_cow(pos..hunk.start));
+        result.push_str(&hunk.text);
+        pos = hunk.end;
+    }
+    result.push_str(&base.slice_to_cow(pos..iv.end()));
+    result
+}
+
+impl Conflicts {
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub(crate) fn set(&mut self, conflicts: Vec<Conflict>) {
+        self.items = conflicts;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.items.clear();
+    }
+
+    /// Updates the location of conflicts after an edit.
+    pub(crate) fn update(&mut self, delta: &RopeDelta) {
+        let mut transformer = Transformer::new(delta);
+        for conflict in &mut self.items {
+            let start = transformer.transform(conflict.iv.start(), false);
+            let end = transformer.transform(conflict.iv.end(), true);
+            conflict.iv = Interval::new(start, end.max(start));
+        }
+    }
+
+    /// Removes and returns the conflicts touching any of `regions`.
+    pub(crate) fn take_in_regions(&mut self, regions: &[SelRegion]) -> Vec<Conflict> {
+        let (taken, kept) = self.items.drain(..).partition(|conflict| {
+            regions.iter().any(|r| conflict.iv.start() <= r.max() && r.min() <= conflict.iv.end())
+        });
+        self.items = kept;
+        taken
+    }
+}
+
+impl ToAnnotation for Conflicts {
+    fn get_annotations(&self, interval: Interval, view: &View, text: &Rope) -> AnnotationSlice {
+        let conflicts = self
+            .items
+            .iter()
+            .filter(|c| c.iv.start() <= interval.end() && interval.start() <= c.iv.end());
+        let (ranges, payloads): (Vec<_>, Vec<_>) = conflicts
+            .map(|conflict| {
+                let (start_line, start_col) = view.offset_to_line_col(text, conflict.iv.start());
+                let (end_line, end_col) = view.offset_to_line_col(text, conflict.iv.end());
+                let payload = json!({ "theirs": String::from(&conflict.theirs) });
+                ([start_line, start_col, end_line, end_col], payload)
+            })
+            .unzip();
+        AnnotationSlice::new(AnnotationType::Conflict, ranges, Some(payloads))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // lines must be long enough to be matched by `LineHashDiff`.
+    const BASE: [&str; 4] = [
+        "this is the first line of the file",
+        "this is the second line of the file",
+        "this is the third line of the file",
+        "this is the fourth line of the file",
+    ];
+
+    /// Returns `BASE` with the given lines replaced.
+    fn text(changes: &[(usize, &str)]) -> String {
+        let mut result = String::new();
+        for (i, line) in BASE.iter().enumerate() {
+            let line = changes.iter().find(|(n, _)| *n == i).map(|(_, l)| *l).unwrap_or(line);
+            if !line.is_empty() {
+                result.push_str(line);
+                result.push('\n');
+            }
+        }
+        result
+    }
+
+    fn do_merge(base: &str, mine: &str, theirs: &str) -> (String, Vec<(String, String)>) {
+        let mine = Rope::from(mine);
+        let merge = merge(&Rope::from(base), &mine, &Rope::from(theirs));
+        let merged = merge.delta.apply(&mine);
+        let conflicts = merge
+            .conflicts
+            .iter()
+            .map(|c| (String::from(merged.slice(c.iv)), String::from(&c.theirs)))
+            .collect();
+        (String::from(merged), conflicts)
+    }
+
+    #[test]
+    fn merge_disjoint() {
+        let mine = text(&[(1, "mine")]);
+        let theirs = text(&[(3, "theirs\nfive")]);
+        let expected = text(&[(1, "mine"), (3, "theirs\nfive")]);
+        assert_eq!(do_merge(&text(&[]), &mine, &theirs), (expected, vec![]));
+
+        let mine = text(&[(0, "zero\nthis is the first line of the file")]);
+        let theirs = text(&[(1, "")]);
+        let expected = text(&[(0, "zero\nthis is the first line of the file"), (1, "")]);
+        assert_eq!(do_merge(&text(&[]), &mine, &theirs), (expected, vec![]));
+    }
+
+    #[test]
+    fn merge_same_change() {
+        let changed = text(&[(1, "two")]);
+        assert_eq!(do_merge(&text(&[]), &changed, &changed), (changed, vec![]));
+    }
+
+    #[test]
+    fn merge_conflict() {
+        let mine = text(&[(1, "mine")]);
+        let theirs = text(&[(1, "theirs"), (3, "FOUR")]);
+        let expected = text(&[(1, "mine"), (3, "FOUR")]);
+        assert_eq!(
+            do_merge(&text(&[]), &mine, &theirs),
+            (expected, vec![("mine\n".into(), "theirs\n".into())])
+        );
+    }
+
+    #[test]
+    fn merge_append() {
+        let base = text(&[]);
+        let mine = format!("{}mine\n", base);
+        let theirs = format!("{}theirs\n", base);
+        assert_eq!(
+            do_merge(&base, &mine, &theirs),
+            (mine, vec![("mine\n".into(), "theirs\n".into())])
+        );
+    }
+
+    #[test]
+    fn update_conflicts() {
+        let mut conflicts = Conflicts::default();
+        conflicts.set(vec![Conflict { iv: Interval::new(4, 8), theirs: Rope::from("x") }]);
+        let mut builder = DeltaBuilder::new(12);
+        builder.replace(Interval::new(0, 2), Rope::from(""));
+        conflicts.update(&builder.build());
+        assert_eq!(conflicts.items[0].iv, Interval::new(2, 6));
+
+        let taken = conflicts.take_in_regions(&[SelRegion::caret(1)]);
+        assert!(taken.is_empty());
+        let taken = conflicts.take_in_regions(&[SelRegion::caret(6)]);
+        assert_eq!(taken.len(), 1);
+        assert!(conflicts.is_empty());
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Undo history that outlives the buffer.
+//!
+//! With the `persistent_undo` setting, the undo history of a buffer is
+//! written to the `undo` directory inside the config directory when the
+//! buffer is closed, and restored when the file is next opened, if its
+//! contents haven't changed in the meantime.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use xi_rope::engine::Engine;
+
+use crate::editor::UndoHistory;
+use crate::recovery::hash_path;
+use crate::vfs;
+
+/// The contents of an undo history file.
+#[derive(Serialize, Deserialize)]
+struct HistoryFile<H> {
+    /// The path of the file the history belongs to.
+    path: PathBuf,
+    /// The hash of the file's contents when the history was written.
+    content_hash: u64,
+    history: H,
+}
+
+/// Reads and writes undo history files.
+pub(crate) struct UndoStore {
+    /// Where history files are written. If `None`, nothing is kept.
+    dir: Option<PathBuf>,
+}
+
+impl UndoStore {
+    pub(crate) fn new(dir: Option<PathBuf>) -> Self {
+        UndoStore { dir }
+    }
+
+    /// Writes the undo history of the file at `path`, whose contents have
+    /// the hash `content_hash`, replacing any previous history of that file.
+    pub(crate) fn write(
+        &self,
+        path: &Path,
+        content_hash: u64,
+        history: UndoHistory<&Engine>,
+    ) -> io::Result<()> {
+        let (dir, history_path) = match (self.dir.as_ref(), self.history_path(path)) {
+            (Some(dir), Some(history_path)) => (dir, history_path),
+            _ => return Ok(()),
+        };
+        fs::create_dir_all(dir)?;
+        let contents = HistoryFile { path: path.to_owned(), content_hash, history };
+        vfs::write_json(&history_path, &contents)
+    }
+
+    /// Returns the undo history of the file at `path`, if one was written
+    /// when the file's contents had the hash `content_hash`.
+    pub(crate) fn find(&self, path: &Path, content_hash: u64) -> Option<UndoHistory> {
+        let history_path = self.history_path(path)?;
+        let f = File::open(&history_path).ok()?;
+        let contents: HistoryFile<UndoHistory> = serde_json::from_reader(BufReader::new(f))
+            .map_err(|e| warn!("failed to read undo history {:?}: {}", history_path, e))
+            .ok()?;
+        // a different path is a hash collision
+        if contents.path != path || contents.content_hash != content_hash {
+            return None;
+        }
+        Some(contents.history)
+    }
+
+    fn history_path(&self, path: &Path) -> Option<PathBuf> {
+        let name = format!("{:016x}.json", hash_path(path));
+        self.dir.as_ref().map(|dir| dir.join(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::Editor;
+    use tempdir::TempDir;
+    use xi_rope::Rope;
+
+    #[test]
+    fn write_and_find() {
+        let tmp = TempDir::new("xi-test-undo").unwrap();
+        let store = UndoStore::new(Some(tmp.path().join("undo")));
+        let path = Path::new("/some/file.txt");
+        assert!(store.find(path, 5).is_none());
+
+        let mut editor = Editor::with_text("text");
+        editor.replace_text(Rope::from("more text"));
+        store.write(path, 5, editor.get_undo_history()).unwrap();
+        assert!(store.find(path, 6).is_none());
+        assert!(store.find(Path::new("/some/other.txt"), 5).is_none());
+        let history = store.find(path, 5).unwrap();
+        assert_eq!(String::from(history.get_text()), "more text");
+    }
+
+    #[test]
+    fn disabled() {
+        let store = UndoStore::new(None);
+        let path = Path::new("/some/file.txt");
+        store.write(path, 5, Editor::with_text("text").get_undo_history()).unwrap();
+        assert!(store.find(path, 5).is_none());
+    }
+}
@@ -224,6 +224,14 @@ impl PluginDescription {
             _ => false,
         }
     }
+
+    /// Returns `true` if this plugin runs without being explicitly requested.
+    pub fn is_autorun(&self) -> bool {
+        self.activations.iter().any(|act| match act {
+            PluginActivation::Autorun => true,
+            _ => false,
+        })
+    }
 }
 
 impl Default for PluginScope {
@@ -133,6 +133,16 @@ impl Recorder {
         self.recordings.remove(recording_name);
     }
 
+    /// Returns all saved recordings, by name.
+    pub(crate) fn get_recordings(&self) -> &HashMap<String, Recording> {
+        &self.recordings
+    }
+
+    /// Adds previously saved recordings, replacing any with the same names.
+    pub(crate) fn add_recordings(&mut self, recordings: HashMap<String, Recording>) {
+        self.recordings.extend(recordings);
+    }
+
     /// Cleans the recording buffer by filtering out any undo or redo events and then saving it
     /// with the specified name.
     ///
@@ -183,7 +193,8 @@ impl Recorder {
     }
 }
 
-struct Recording {
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct Recording {
     events: Vec<EventDomain>,
 }
 
@@ -0,0 +1,206 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Crash recovery for unsaved changes.
+//!
+//! The contents of dirty buffers are periodically written to a recovery
+//! directory inside the config directory. If xi-core exits without the buffer
+//! being saved or closed, the recovery file is left behind, and is offered to
+//! the client the next time that file is opened.
+//!
+//! Only the buffer's text is stored; undo history is not preserved.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::Hasher;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use xi_rope::Rope;
+
+use crate::file::FnvHasher;
+use crate::tabs::{BufferId, ViewId};
+use crate::vfs;
+
+/// How often the contents of dirty buffers are written to disk.
+pub(crate) const RECOVERY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The contents of a recovery file.
+#[derive(Debug, Serialize, Deserialize)]
+struct RecoveryFile {
+    /// The path of the file being edited.
+    path: PathBuf,
+    text: String,
+}
+
+/// Tracks recovery files written for open buffers.
+pub(crate) struct RecoveryManager {
+    /// Where recovery files are written. If `None`, recovery is disabled.
+    dir: Option<PathBuf>,
+    /// The revision most recently written, for each buffer.
+    written: HashMap<BufferId, u64>,
+    /// Client responses to offers to restore a recovery file, which
+    /// arrive on another thread.
+    responses: Arc<Mutex<Vec<(ViewId, bool)>>>,
+}
+
+impl RecoveryManager {
+    pub(crate) fn new(dir: Option<PathBuf>) -> Self {
+        RecoveryManager { dir, written: HashMap::new(), responses: Arc::default() }
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Returns `true` if a recovery file has been written for this buffer
+    /// since it was opened or last saved.
+    pub(crate) fn has_written(&self, id: BufferId) -> bool {
+        self.written.contains_key(&id)
+    }
+
+    /// Returns `true` if `rev` of this buffer has already been written.
+    pub(crate) fn is_current(&self, id: BufferId, rev: u64) -> bool {
+        self.written.get(&id) == Some(&rev)
+    }
+
+    /// Writes the recovery file for the buffer editing `path`.
+    pub(crate) fn write(
+        &mut self,
+        id: BufferId,
+        rev: u64,
+        path: &Path,
+        text: &Rope,
+    ) -> io::Result<()> {
+        let recovery_path = match self.recovery_path(path) {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+        let contents = RecoveryFile { path: path.to_owned(), text: String::from(text) };
+        vfs::write_json(&recovery_path, &contents)?;
+        self.written.insert(id, rev);
+        Ok(())
+    }
+
+    /// Returns the contents of the recovery file for `path`, if one exists
+    /// and it is newer than `mod_time`, the modification time of the file
+    /// itself. Outdated recovery files are removed.
+    pub(crate) fn find(&self, path: &Path, mod_time: Option<SystemTime>) -> Option<Rope> {
+        let recovery_path = self.recovery_path(path)?;
+        let recovery_time = fs::metadata(&recovery_path).and_then(|m| m.modified()).ok()?;
+        if mod_time.map(|t| t >= recovery_time).unwrap_or(false) {
+            info!("removing outdated recovery file for {:?}", path);
+            let _ = fs::remove_file(&recovery_path);
+            return None;
+        }
+
+        let contents: RecoveryFile = File::open(&recovery_path)
+            .map_err(serde_json::Error::io)
+            .and_then(|f| serde_json::from_reader(BufReader::new(f)))
+            .map_err(|e| warn!("failed to read recovery file {:?}: {}", recovery_path, e))
+            .ok()?;
+        if contents.path != path {
+            // this is a hash collision; ignore it.
+            return None;
+        }
+        Some(Rope::from(contents.text))
+    }
+
+    /// Removes the recovery file for `path`, if any. This should be called
+    /// when the buffer is saved or closed.
+    pub(crate) fn remove(&mut self, id: BufferId, path: &Path) {
+        self.written.remove(&id);
+        if let Some(recovery_path) = self.recovery_path(path) {
+            if recovery_path.exists() {
+                if let Err(e) = fs::remove_file(&recovery_path) {
+                    warn!("failed to remove recovery file {:?}: {}", recovery_path, e);
+                }
+            }
+        }
+    }
+
+    /// A handle for recording the client's responses to recovery offers.
+    pub(crate) fn responses(&self) -> Arc<Mutex<Vec<(ViewId, bool)>>> {
+        self.responses.clone()
+    }
+
+    /// Returns and clears any responses to recovery offers.
+    pub(crate) fn take_responses(&self) -> Vec<(ViewId, bool)> {
+        self.responses.lock().unwrap().drain(..).collect()
+    }
+
+    fn recovery_path(&self, path: &Path) -> Option<PathBuf> {
+        let name = format!("{:016x}.json", hash_path(path));
+        self.dir.as_ref().map(|dir| dir.join(name))
+    }
+}
+
+/// A 64-bit FNV-1a hash of the path, which is stable across releases.
+pub(crate) fn hash_path(path: &Path) -> u64 {
+    let mut hasher = FnvHasher::default();
+    hasher.write(path.to_string_lossy().as_bytes());
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn write_and_find() {
+        let tmp = TempDir::new("xi-test-recovery").unwrap();
+        let mut recovery = RecoveryManager::new(Some(tmp.path().to_owned()));
+        let path = Path::new("/some/file.txt");
+        let id = BufferId::new(1);
+
+        assert!(recovery.find(path, None).is_none());
+        recovery.write(id, 5, path, &Rope::from("unsaved")).unwrap();
+        assert!(recovery.has_written(id));
+        assert!(recovery.is_current(id, 5));
+        assert!(!recovery.is_current(id, 6));
+        assert_eq!(recovery.find(path, None).map(String::from), Some("unsaved".into()));
+        assert!(recovery.find(Path::new("/some/other.txt"), None).is_none());
+
+        recovery.remove(id, path);
+        assert!(!recovery.has_written(id));
+        assert!(recovery.find(path, None).is_none());
+    }
+
+    #[test]
+    fn outdated_recovery_file() {
+        let tmp = TempDir::new("xi-test-recovery").unwrap();
+        let mut recovery = RecoveryManager::new(Some(tmp.path().to_owned()));
+        let path = Path::new("/some/file.txt");
+        recovery.write(BufferId::new(1), 1, path, &Rope::from("unsaved")).unwrap();
+
+        let older = SystemTime::now() - Duration::from_secs(60);
+        assert!(recovery.find(path, Some(older)).is_some());
+        let newer = SystemTime::now() + Duration::from_secs(60);
+        assert!(recovery.find(path, Some(newer)).is_none());
+        // outdated files are removed
+        assert!(recovery.find(path, None).is_none());
+    }
+
+    #[test]
+    fn disabled() {
+        let mut recovery = RecoveryManager::new(None);
+        let path = Path::new("/some/file.txt");
+        assert!(!recovery.is_enabled());
+        recovery.write(BufferId::new(1), 1, path, &Rope::from("unsaved")).unwrap();
+        assert!(recovery.find(path, None).is_none());
+    }
+}
@@ -0,0 +1,389 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Reflowing paragraphs to a width.
+//!
+//! A paragraph is a run of lines with the same prefix: indentation, then
+//! optionally a comment leader (`//`, `///`, `//!`, `#`, or a `*` after
+//! indentation, as in block comments) and the whitespace after it. A line
+//! whose text starts with a list marker (`-`, `*`, `+`, or a number followed
+//! by `.` or `)`) starts a paragraph of its own, whose other lines are
+//! indented to the item's text. Blank lines, including those with nothing
+//! but a prefix, separate paragraphs, as do lines starting with a `#` that
+//! isn't followed by whitespace, such as `#[derive(Debug)]`.
+//!
+//! Reflowing lays out the text of a paragraph again, breaking lines where
+//! `LineBreakIterator` allows it, as late as fits in the width. Text that
+//! can't be broken is left longer than the width.
+
+use std::ops::Range;
+
+use xi_rope::{DeltaBuilder, Interval, Rope, RopeDelta};
+use xi_unicode::LineBreakIterator;
+
+use crate::line_ops::{line_bounds, n_lines, region_lines};
+use crate::selection::{SelRegion, Selection};
+
+/// A line of a paragraph, split into its parts.
+#[derive(Debug)]
+struct Line {
+    /// The text of the line, without its newline.
+    text: String,
+    /// The length of the indentation, comment leader and whitespace that
+    /// start the line.
+    prefix_len: usize,
+    /// If the line starts a list item, the length of the list marker and
+    /// the whitespace after it.
+    marker_len: Option<usize>,
+}
+
+impl Line {
+    fn new(text: String) -> Line {
+        let prefix_len = prefix_len(&text);
+        let marker_len = marker_len(&text[prefix_len..]);
+        Line { text, prefix_len, marker_len }
+    }
+
+    fn read(text: &Rope, line: usize) -> Line {
+        let (start, end) = line_bounds(text, line);
+        Line::new(text.slice_to_cow(start..end).into_owned())
+    }
+
+    fn prefix(&self) -> &str {
+        &self.text[..self.prefix_len]
+    }
+
+    /// Returns `true` if the line separates paragraphs: it is blank, or
+    /// starts with a `#` that isn't a comment leader, as in `#[derive]`.
+    fn is_separator(&self) -> bool {
+        let rest = self.text[self.prefix_len..].trim();
+        rest.is_empty() || self.prefix().trim().is_empty() && rest.starts_with('#')
+    }
+
+    /// Returns the prefix of the lines after this one in its paragraph.
+    fn continuation_prefix(&self) -> String {
+        let indent = " ".repeat(self.marker_len.unwrap_or(0));
+        [self.prefix(), &indent].concat()
+    }
+
+    /// Returns `true` if this line continues the paragraph that the line
+    /// `prev`, before it, is in.
+    fn continues(&self, prev: &Line) -> bool {
+        !self.is_separator()
+            && !prev.is_separator()
+            && self.marker_len.is_none()
+            && self.prefix() == prev.continuation_prefix()
+    }
+}
+
+/// Returns the length of the indentation, comment leader and whitespace at
+/// the start of `line`.
+fn prefix_len(line: &str) -> usize {
+    let indent = line.len() - line.trim_start().len();
+    let rest = &line[indent..];
+    let hashes = rest.len() - rest.trim_start_matches('#').len();
+    // `#` and `*` leaders are followed by whitespace, unlike `#[derive]`
+    // or `*emphasis*`
+    let before_space = |len: usize| rest[len..].trim_start().len() < rest.len() - len;
+    let leader = if rest.starts_with("//!") {
+        3
+    } else if rest.starts_with("//") {
+        rest.len() - rest.trim_start_matches('/').len()
+    } else if hashes > 0 && (hashes == rest.len() || before_space(hashes)) {
+        hashes
+    } else if rest.starts_with('*') && indent > 0 && (rest.len() == 1 || before_space(1)) {
+        1
+    } else {
+        0
+    };
+    let after = &rest[leader..];
+    indent + leader + after.len() - after.trim_start().len()
+}
+
+/// Returns the length of the list marker and the whitespace after it at the
+/// start of `text`, if there is one.
+fn marker_len(text: &str) -> Option<usize> {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let marker = match text[digits..].chars().next() {
+        Some('-') | Some('*') | Some('+') if digits == 0 => 1,
+        Some('.') | Some(')') if digits > 0 => digits + 1,
+        _ => return None,
+    };
+    let rest = &text[marker..];
+    let space = rest.len() - rest.trim_start().len();
+    match space > 0 && space < rest.len() {
+        true => Some(marker + space),
+        false => None,
+    }
+}
+
+/// Returns the width of `s` in columns, counting tabs up to the next tab stop.
+fn width_of(s: &str, tab_size: usize) -> usize {
+    s.chars().fold(0, |width, c| match c {
+        '\t' if tab_size > 0 => (width / tab_size + 1) * tab_size,
+        _ => width + 1,
+    })
+}
+
+/// Returns the lines of the paragraph that `line` is in, or `None` if the
+/// line separates paragraphs.
+fn paragraph_at(text: &Rope, line: usize) -> Option<Range<usize>> {
+    let current = Line::read(text, line);
+    if current.is_separator() {
+        return None;
+    }
+    let mut start = line;
+    let mut first = current;
+    while start > 0 {
+        let prev = Line::read(text, start - 1);
+        if !first.continues(&prev) {
+            break;
+        }
+        start -= 1;
+        first = prev;
+    }
+
+    let n_lines = n_lines(text);
+    let mut end = line + 1;
+    let mut last = Line::read(text, line);
+    while end < n_lines {
+        let next = Line::read(text, end);
+        if !next.continues(&last) {
+            break;
+        }
+        end += 1;
+        last = next;
+    }
+    Some(start..end)
+}
+
+/// Returns the paragraphs that the regions of `selection` are on.
+fn selected_paragraphs(text: &Rope, selection: &Selection) -> Vec<Range<usize>> {
+    let mut paragraphs: Vec<Range<usize>> = Vec::new();
+    for region in selection.iter() {
+        let lines = region_lines(text, region);
+        let mut line = lines.start;
+        if let Some(last) = paragraphs.last() {
+            line = line.max(last.end);
+        }
+        while line < lines.end {
+            match paragraph_at(text, line) {
+                Some(paragraph) => {
+                    line = paragraph.end;
+                    paragraphs.push(paragraph);
+                }
+                None => line += 1,
+            }
+        }
+    }
+    paragraphs
+}
+
+/// A paragraph laid out again.
+struct Reflowed {
+    /// The offsets of the paragraph in the text.
+    iv: Interval,
+    text: String,
+    /// The offsets after each character of the text of the paragraph,
+    /// other than prefixes and whitespace, before and after reflowing.
+    /// Selections are moved with them.
+    old_ends: Vec<usize>,
+    new_ends: Vec<usize>,
+}
+
+/// Returns the offsets just after each non-whitespace character of `s`,
+/// starting from `base`.
+fn char_ends(s: &str, base: usize) -> impl Iterator<Item = usize> + '_ {
+    s.char_indices().filter(|(_, c)| !c.is_whitespace()).map(move |(i, c)| base + i + c.len_utf8())
+}
+
+/// Lays out the text of the paragraph of `lines`, which starts at
+/// `start` in the text, to fit in `width` columns.
+fn reflow_paragraph(lines: &[Line], start: usize, width: usize, tab_size: usize) -> Reflowed {
+    // the list marker stays in the first line's prefix
+    let first = &lines[0];
+    let first_prefix = &first.text[..first.prefix_len + first.marker_len.unwrap_or(0)];
+    let continuation_prefix = first.continuation_prefix();
+
+    let mut words = String::new();
+    let mut old_ends = Vec::new();
+    let mut line_start = start;
+    for (i, line) in lines.iter().enumerate() {
+        let body_start = match i {
+            0 => first_prefix.len(),
+            _ => line.prefix_len,
+        };
+        let rest = &line.text[body_start..];
+        let body = rest.trim();
+        let body_offset = line_start + body_start + rest.len() - rest.trim_start().len();
+        old_ends.extend(char_ends(body, body_offset));
+        if !words.is_empty() {
+            words.push(' ');
+        }
+        words.push_str(body);
+        line_start += line.text.len() + 1;
+    }
+
+    let mut text = String::new();
+    let mut new_ends = Vec::new();
+    let mut prefix = first_prefix;
+    let mut add_line = |text: &mut String, prefix: &str, body: &str| {
+        text.push_str(prefix);
+        new_ends.extend(char_ends(body, start + text.len()));
+        text.push_str(body);
+    };
+    let mut line_start = 0;
+    let mut last_break = 0;
+    for (brk, _) in LineBreakIterator::new(&words) {
+        let line = words[line_start..brk].trim_end();
+        let too_long = width_of(prefix, tab_size) + width_of(line, tab_size) > width;
+        if too_long && last_break > line_start {
+            add_line(&mut text, prefix, words[line_start..last_break].trim_end());
+            text.push('\n');
+            line_start = last_break;
+            prefix = continuation_prefix.as_str();
+        }
+        last_break = brk;
+    }
+    add_line(&mut text, prefix, words[line_start..].trim_end());
+
+    let end = start + lines.iter().map(|line| line.text.len() + 1).sum::<usize>() - 1;
+    Reflowed { iv: Interval::new(start, end), text, old_ends, new_ends }
+}
+
+/// Builds the delta that reflows the paragraphs `selection` is on to fit in
+/// `width` columns, and returns it with the selection after it. Selections
+/// stay with the text they were next to.
+pub(crate) fn reflow(
+    text: &Rope,
+    selection: &Selection,
+    width: usize,
+    tab_size: usize,
+) -> Option<(RopeDelta, Selection)> {
+    let reflowed = selected_paragraphs(text, selection)
+        .into_iter()
+        .map(|paragraph| {
+            let start = line_bounds(text, paragraph.start).0;
+            let lines = paragraph.map(|line| Line::read(text, line)).collect::<Vec<_>>();
+            reflow_paragraph(&lines, start, width, tab_size)
+        })
+        .filter(|reflowed| {
+            text.slice_to_cow(reflowed.iv.start()..reflowed.iv.end()) != reflowed.text
+        })
+        .collect::<Vec<_>>();
+    if reflowed.is_empty() {
+        return None;
+    }
+
+    let mut builder = DeltaBuilder::new(text.len());
+    for paragraph in &reflowed {
+        builder.replace(paragraph.iv, Rope::from(&paragraph.text));
+    }
+
+    let map_offset = |offset: usize| {
+        let mut shift = 0isize;
+        for paragraph in &reflowed {
+            let (start, end) = (paragraph.iv.start(), paragraph.iv.end());
+            let new_start = (start as isize + shift) as usize;
+            if offset < start {
+                break;
+            } else if offset <= end {
+                let n_before = paragraph.old_ends.iter().take_while(|&&e| e <= offset).count();
+                return match n_before {
+                    0 => new_start + (offset - start).min(paragraph.text.len()),
+                    n => (paragraph.new_ends[n - 1] as isize + shift) as usize,
+                };
+            }
+            shift += paragraph.text.len() as isize - paragraph.iv.size() as isize;
+        }
+        (offset as isize + shift) as usize
+    };
+    let mut new_sel = Selection::new();
+    for region in selection.iter() {
+        new_sel.add_region(SelRegion::new(map_offset(region.start), map_offset(region.end)));
+    }
+    Some((builder.build(), new_sel))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reflowed(text: &str, carets: &[usize], width: usize) -> (String, Vec<usize>) {
+        let mut text = Rope::from(text);
+        let mut selection = Selection::new();
+        carets.iter().for_each(|&caret| selection.add_region(SelRegion::caret(caret)));
+        if let Some((delta, new_sel)) = reflow(&text, &selection, width, 4) {
+            text = delta.apply(&text);
+            selection = new_sel;
+        }
+        (String::from(text), selection.iter().map(|region| region.end).collect())
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(prefix_len("plain"), 0);
+        assert_eq!(prefix_len("    // text"), 7);
+        assert_eq!(prefix_len("/// text"), 4);
+        assert_eq!(prefix_len("//! text"), 4);
+        assert_eq!(prefix_len("## text"), 3);
+        assert_eq!(prefix_len("#"), 1);
+        assert_eq!(prefix_len("#[derive(Debug)]"), 0);
+        assert_eq!(prefix_len(" * text"), 3);
+        assert_eq!(prefix_len("*emphasis*"), 0);
+        assert_eq!(marker_len("- item"), Some(2));
+        assert_eq!(marker_len("12. item"), Some(4));
+        assert_eq!(marker_len("-- text"), None);
+        assert_eq!(marker_len("- "), None);
+    }
+
+    #[test]
+    fn reflow_paragraphs() {
+        let text = "one two three\nfour five\n\nsix seven\n";
+        let (text, carets) = reflowed(text, &[0], 9);
+        assert_eq!(text, "one two\nthree\nfour five\n\nsix seven\n");
+        assert_eq!(carets, vec![0]);
+        let (text, _) = reflowed(&text, &[0], 80);
+        assert_eq!(text, "one two three four five\n\nsix seven\n");
+
+        // carets stay with their text
+        let (text, carets) = reflowed("ab cd ef\n", &[4, 8], 4);
+        assert_eq!(text, "ab\ncd\nef\n");
+        assert_eq!(carets, vec![4, 8]);
+
+        // words longer than the width are left alone
+        let (text, _) = reflowed("abcdefgh ij", &[0], 4);
+        assert_eq!(text, "abcdefgh\nij");
+    }
+
+    #[test]
+    fn reflow_comments_and_lists() {
+        let text = "    // one two\n    // three four\nfn f() {}\n";
+        let (text, _) = reflowed(text, &[20], 16);
+        assert_eq!(text, "    // one two\n    // three\n    // four\nfn f() {}\n");
+
+        let text = "/// - one two three\n///   four\n/// - five\n";
+        let (text, _) = reflowed(text, &[0], 80);
+        assert_eq!(text, "/// - one two three four\n/// - five\n");
+        let (text, _) = reflowed(&text, &[0], 16);
+        assert_eq!(text, "/// - one two\n///   three four\n/// - five\n");
+
+        let text = "#[derive(Debug)]\n#[cfg(test)]\n    #[inline]\n    #[test]\n";
+        assert_eq!(reflowed(text, &[0, 35], 80).0, text);
+
+        let text = "/**\n * one two\n * three\n */\n";
+        let (text, _) = reflowed(text, &[6], 80);
+        assert_eq!(text, "/**\n * one two three\n */\n");
+    }
+}
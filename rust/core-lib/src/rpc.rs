@@ -25,6 +25,8 @@ use serde::ser::{self, Serialize, Serializer};
 use serde_json::{self, Value};
 
 use crate::config::{ConfigDomainExternal, Table};
+use crate::file::CharacterEncoding;
+use crate::line_ending::LineEnding;
 use crate::plugins::PlaceholderRpc;
 use crate::syntax::LanguageId;
 use crate::tabs::ViewId;
@@ -207,6 +209,25 @@ pub enum CoreNotification {
     SaveTrace { destination: PathBuf, frontend_samples: Value },
     /// Tells `xi-core` to set the language id for the view.
     SetLanguage { view_id: ViewId, language_id: LanguageId },
+    /// Tells `xi-core` to use the given encoding for the view's file.
+    /// If `reopen` is true the file is read from disk again and decoded
+    /// with this encoding; otherwise the buffer's contents are unchanged,
+    /// and the encoding is used the next time the buffer is saved.
+    SetEncoding {
+        view_id: ViewId,
+        encoding: CharacterEncoding,
+        #[serde(default)]
+        reopen: bool,
+    },
+    /// Tells `xi-core` whether the view's buffer is read-only. Edits to
+    /// a read-only buffer are rejected.
+    SetReadOnly { view_id: ViewId, read_only: bool },
+    /// Tells `xi-core` to discard the unsaved changes in the view's buffer,
+    /// reading its file from disk again. This can be undone.
+    RevertBuffer { view_id: ViewId },
+    /// Tells `xi-core` to save every buffer with unsaved changes that
+    /// has a file.
+    SaveAll {},
 }
 
 /// The requests which make up the base of the protocol.
@@ -243,11 +264,35 @@ pub enum CoreRequest {
     Edit(EditCommand<EditRequest>),
     /// Tells `xi-core` to create a new view. If the `file_path`
     /// argument is present, `xi-core` should attempt to open the file
-    /// at that location.
+    /// at that location. If the file is already open, the new view
+    /// shares its buffer with the existing views of that file.
     ///
     /// Returns the view identifier that should be used to interact
     /// with the newly created view.
     NewView { file_path: Option<String> },
+    /// Tells `xi-core` to create a new view of the buffer shown in
+    /// `view_id`. Each view has its own selections and scroll position.
+    ///
+    /// Returns the view identifier of the new view.
+    CloneView { view_id: ViewId },
+    /// Tells `xi-core` to close every view. Unless `force` is true, nothing
+    /// is closed if any buffer has unsaved changes.
+    ///
+    /// Returns the buffers with unsaved changes that kept the views from
+    /// being closed, as `{"view_ids": [...], "file_path": ...}` objects.
+    CloseAll {
+        #[serde(default)]
+        force: bool,
+    },
+    /// Tells `xi-core` to write the open views, with their selections,
+    /// scroll positions and any unsaved changes, and the named
+    /// recordings, to the session file in the config directory.
+    SaveSession {},
+    /// Tells `xi-core` to reopen the views saved by `save_session`.
+    ///
+    /// Returns the new views, as `{"view_id": ..., "file_path": ...}`
+    /// objects.
+    RestoreSession {},
     /// Returns the current collated config object for the given view.
     GetConfig { view_id: ViewId },
     /// Returns the contents of the buffer for a given `ViewId`.
@@ -301,6 +346,8 @@ pub enum SelectionGranularity {
     Word,
     /// Selects one line at a time
     Line,
+    /// Selects a rectangular block, with one region per line
+    Column,
 }
 
 /// An enum representing touch and mouse gestures applied to the text.
@@ -442,6 +489,11 @@ pub enum EditNotification {
     GotoLine {
         line: u64,
     },
+    /// Moves the cursor to a byte offset. In a hex view of a binary file,
+    /// this is an offset in the file.
+    GotoOffset {
+        offset: u64,
+    },
     RequestLines(LineRange),
     Yank,
     Transpose,
@@ -454,6 +506,14 @@ pub enum EditNotification {
     },
     Undo,
     Redo,
+    /// Moves to a node of the undo tree, which may be on another branch.
+    UndoTreeGoto {
+        node: usize,
+    },
+    /// Returns the buffer to the state it was in some seconds ago.
+    UndoToTime {
+        seconds_ago: u64,
+    },
     Find {
         chars: String,
         case_sensitive: bool,
@@ -515,6 +575,28 @@ pub enum EditNotification {
     },
     SelectionIntoLines,
     DuplicateLine,
+    /// Moves the selected lines past the line above or below them.
+    MoveLinesUp,
+    MoveLinesDown,
+    JoinLines,
+    /// Sorts the selected lines, or all lines if nothing is selected.
+    SortLines {
+        #[serde(default)]
+        case_insensitive: bool,
+        #[serde(default)]
+        natural: bool,
+        #[serde(default)]
+        numeric: bool,
+        #[serde(default)]
+        reverse: bool,
+    },
+    UniqueLines,
+    ReverseLines,
+    /// Rewraps the selected paragraphs, or the paragraph at each caret, to
+    /// `width` columns.
+    Reflow {
+        width: usize,
+    },
     IncreaseNumber,
     DecreaseNumber,
     ToggleRecording {
@@ -527,6 +609,28 @@ pub enum EditNotification {
         recording_name: String,
     },
     CollapseSelections,
+    /// Goes back to the previous selection in the selection history.
+    UndoSelection,
+    /// Goes forward to the next selection in the selection history.
+    RedoSelection,
+    /// Extends a column selection by a line or a character.
+    ColumnSelectUp,
+    ColumnSelectDown,
+    ColumnSelectLeft,
+    ColumnSelectRight,
+    /// Moves each caret to the bracket matching the one next to it.
+    MoveToMatchingBracket,
+    /// Selects the brackets next to each caret, their matches and the text
+    /// between them.
+    SelectToMatchingBracket,
+    /// Sets the line ending that will be used when the buffer is saved.
+    SetLineEnding {
+        line_ending: LineEnding,
+    },
+    /// Resolves conflicts touching the selection by keeping the buffer's text.
+    KeepMine,
+    /// Resolves conflicts touching the selection by taking the text on disk.
+    TakeTheirs,
 }
 
 /// The edit related requests.
@@ -540,6 +644,8 @@ pub enum EditRequest {
     /// Copies the active selection, returning their contents or
     /// or `Null` if the selection was empty.
     Copy,
+    /// Returns the undo tree of the buffer.
+    UndoTreeGet,
 }
 
 /// The plugin related notifications.
@@ -667,4 +773,19 @@ mod tests {
             assert_eq!(chars, message);
         }
     }
+
+    #[test]
+    fn test_deserialize_buffer_commands() {
+        let json = json!({"method": "save_all", "params": {}});
+        let cmd: CoreNotification = serde_json::from_value(json).unwrap();
+        assert_eq!(cmd, CoreNotification::SaveAll {});
+
+        let json = json!({"method": "close_all", "params": {}});
+        let cmd: CoreRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(cmd, CoreRequest::CloseAll { force: false });
+
+        let json = json!({"method": "restore_session", "params": {}});
+        let cmd: CoreRequest = serde_json::from_value(json).unwrap();
+        assert_eq!(cmd, CoreRequest::RestoreSession {});
+    }
 }
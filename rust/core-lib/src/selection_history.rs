@@ -0,0 +1,141 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A view's history of earlier selections, for jumping back to them.
+
+use std::collections::VecDeque;
+
+use xi_rope::RopeDelta;
+
+use crate::selection::{InsertDrift, Selection};
+
+/// The number of earlier selections kept.
+const MAX_SELECTION_HISTORY: usize = 100;
+
+/// The selections a view can go back and forward to.
+///
+/// A jump, such as going to a line or to the next find match, always gets
+/// an entry of its own, while a run of small movements, such as moving by
+/// characters or words, is coalesced into a single entry.
+#[derive(Debug, Default)]
+pub(crate) struct SelectionHistory {
+    back: VecDeque<Selection>,
+    forward: Vec<Selection>,
+    /// Set during a run of small movements, which share one entry.
+    coalescing: bool,
+}
+
+impl SelectionHistory {
+    /// Records that the selection was changed from `before`, either by
+    /// a jump or by a small movement.
+    pub(crate) fn record(&mut self, before: Selection, is_jump: bool) {
+        if is_jump || !self.coalescing {
+            self.forward.clear();
+            let is_duplicate = self.back.back().map(|last| **last == *before).unwrap_or(false);
+            if !is_duplicate {
+                self.back.push_back(before);
+                if self.back.len() > MAX_SELECTION_HISTORY {
+                    self.back.pop_front();
+                }
+            }
+        }
+        self.coalescing = !is_jump;
+    }
+
+    /// Returns the previous selection, if any. `current` becomes the next one.
+    pub(crate) fn undo(&mut self, current: Selection) -> Option<Selection> {
+        let prev = self.back.pop_back()?;
+        self.forward.push(current);
+        self.coalescing = false;
+        Some(prev)
+    }
+
+    /// Returns the next selection, if any. `current` becomes the previous one.
+    pub(crate) fn redo(&mut self, current: Selection) -> Option<Selection> {
+        let next = self.forward.pop()?;
+        self.back.push_back(current);
+        self.coalescing = false;
+        Some(next)
+    }
+
+    /// Updates the recorded selections after an edit.
+    pub(crate) fn apply_delta(&mut self, delta: &RopeDelta) {
+        for sel in self.back.iter_mut().chain(self.forward.iter_mut()) {
+            *sel = sel.apply_delta(delta, true, InsertDrift::Default);
+        }
+        self.coalescing = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::selection::SelRegion;
+    use xi_rope::{DeltaBuilder, Rope};
+
+    fn caret(offset: usize) -> Selection {
+        Selection::new_simple(SelRegion::caret(offset))
+    }
+
+    fn offset(sel: Option<Selection>) -> Option<usize> {
+        sel.map(|s| s[0].end)
+    }
+
+    #[test]
+    fn coalescing() {
+        let mut history = SelectionHistory::default();
+        history.record(caret(0), false);
+        history.record(caret(1), false);
+        history.record(caret(2), true);
+        history.record(caret(10), false);
+        history.record(caret(11), false);
+        // at 12
+        assert_eq!(offset(history.undo(caret(12))), Some(10));
+        assert_eq!(offset(history.undo(caret(10))), Some(2));
+        assert_eq!(offset(history.undo(caret(2))), Some(0));
+        assert_eq!(offset(history.undo(caret(0))), None);
+        assert_eq!(offset(history.redo(caret(0))), Some(2));
+        assert_eq!(offset(history.redo(caret(2))), Some(10));
+
+        // a new selection drops the later ones
+        history.record(caret(10), true);
+        assert_eq!(offset(history.redo(caret(5))), None);
+        assert_eq!(offset(history.undo(caret(5))), Some(10));
+        assert_eq!(offset(history.undo(caret(10))), Some(2));
+    }
+
+    #[test]
+    fn bounded() {
+        let mut history = SelectionHistory::default();
+        for i in 0..MAX_SELECTION_HISTORY + 10 {
+            history.record(caret(i), true);
+        }
+        let mut offsets = Vec::new();
+        while let Some(prev) = history.undo(caret(0)) {
+            offsets.push(prev[0].end);
+        }
+        assert_eq!(offsets.len(), MAX_SELECTION_HISTORY);
+        assert_eq!(offsets.last(), Some(&10));
+    }
+
+    #[test]
+    fn follows_edits() {
+        let mut history = SelectionHistory::default();
+        history.record(caret(3), true);
+        let mut builder = DeltaBuilder::new(5);
+        builder.replace(0..0, Rope::from("ab"));
+        history.apply_delta(&builder.build());
+        assert_eq!(offset(history.undo(caret(0))), Some(5));
+    }
+}
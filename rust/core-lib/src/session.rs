@@ -0,0 +1,110 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Saving and restoring the editing session.
+//!
+//! A session records the open views, the contents of any buffers with
+//! unsaved changes or without a file, and the named recordings, so that a
+//! restarted xi-core can pick up where the last one left off.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+
+use crate::recorder::Recording;
+use crate::syntax::LanguageId;
+use crate::vfs;
+
+/// The contents of a session file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Session {
+    #[serde(default)]
+    pub(crate) buffers: Vec<SessionBuffer>,
+    #[serde(default)]
+    pub(crate) recordings: HashMap<String, Recording>,
+}
+
+/// An open buffer, and its views.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct SessionBuffer {
+    /// The file being edited, if any.
+    pub(crate) path: Option<PathBuf>,
+    /// The buffer's contents, if it has no file or unsaved changes.
+    pub(crate) text: Option<String>,
+    /// The hash of the file's contents when the session was saved, so
+    /// that changes made to it since then can be noticed.
+    #[serde(default)]
+    pub(crate) content_hash: Option<u64>,
+    /// The language chosen by the user, if any.
+    pub(crate) language: Option<LanguageId>,
+    pub(crate) views: Vec<SessionView>,
+}
+
+/// The state of a single view.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SessionView {
+    /// The selected regions, as `(start, end)` offsets.
+    pub(crate) selection: Vec<(usize, usize)>,
+    /// The first visible line, and the line after the last.
+    pub(crate) scroll: (usize, usize),
+}
+
+impl Session {
+    /// Reads the session file at `path`. Returns `None` if there isn't one.
+    pub(crate) fn load(path: &Path) -> io::Result<Option<Session>> {
+        let f = match File::open(path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        Ok(Some(serde_json::from_reader(BufReader::new(f))?))
+    }
+
+    /// Writes the session file at `path`, replacing any previous one.
+    pub(crate) fn save(&self, path: &Path) -> io::Result<()> {
+        vfs::write_json(path, self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn save_and_load() {
+        let tmp = TempDir::new("xi-test-session").unwrap();
+        let path = tmp.path().join("session.json");
+        assert!(Session::load(&path).unwrap().is_none());
+
+        let view = SessionView { selection: vec![(2, 5), (7, 7)], scroll: (3, 40) };
+        let buffer = SessionBuffer {
+            path: None,
+            text: Some("untitled".into()),
+            content_hash: None,
+            language: Some("Rust".into()),
+            views: vec![view.clone()],
+        };
+        let session = Session { buffers: vec![buffer], recordings: HashMap::new() };
+        session.save(&path).unwrap();
+
+        let loaded = Session::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.buffers.len(), 1);
+        assert_eq!(loaded.buffers[0].path, None);
+        assert_eq!(loaded.buffers[0].text, Some("untitled".to_string()));
+        assert_eq!(loaded.buffers[0].language, Some("Rust".into()));
+        assert_eq!(loaded.buffers[0].views, vec![view]);
+    }
+}
@@ -20,12 +20,15 @@
 //! be renamed.
 
 use std::cell::{Cell, RefCell};
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::fs::File;
 use std::io;
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use serde::de::{self, Deserialize, Deserializer, Unexpected};
 use serde::ser::{Serialize, Serializer};
@@ -35,22 +38,27 @@ use xi_rope::Rope;
 use xi_rpc::{self, ReadError, RemoteError, RpcCtx, RpcPeer};
 use xi_trace::{self, trace_block};
 
+use crate::backup::BackupSettings;
 use crate::client::Client;
 use crate::config::{self, ConfigDomain, ConfigDomainExternal, ConfigManager, Table};
 use crate::editor::Editor;
 use crate::event_context::EventContext;
-use crate::file::FileManager;
+use crate::file::{self, CharacterEncoding, FileError, FileInfo, FileManager};
 use crate::line_ending::LineEnding;
+use crate::persistent_undo::UndoStore;
 use crate::plugin_rpc::{PluginNotification, PluginRequest};
 use crate::plugins::rpc::ClientPluginInfo;
 use crate::plugins::{start_plugin_process, Plugin, PluginCatalog, PluginPid};
 use crate::recorder::Recorder;
+use crate::recovery::{RecoveryManager, RECOVERY_INTERVAL};
 use crate::rpc::{
     CoreNotification, CoreRequest, EditNotification, EditRequest,
     PluginNotification as CorePluginNotification,
 };
+use crate::session::{Session, SessionBuffer, SessionView};
 use crate::styles::{ThemeStyleMap, DEFAULT_THEME};
 use crate::syntax::LanguageId;
+use crate::vfs::{self, FileSystem};
 use crate::view::View;
 use crate::whitespace::Indentation;
 use crate::width_cache::WidthCache;
@@ -87,6 +95,30 @@ const NEW_VIEW_IDLE_TOKEN: usize = 1001;
 /// xi_rpc idle Token for watcher related idle scheduling.
 pub(crate) const WATCH_IDLE_TOKEN: usize = 1002;
 
+/// Token for the timer that writes crash recovery files.
+const RECOVERY_IDLE_TOKEN: usize = 1003;
+
+/// Token for handling the client's responses to recovery offers.
+const RECOVERY_RESPONSE_IDLE_TOKEN: usize = 1004;
+
+/// Token for reporting the progress of files being loaded in the background.
+const LOAD_IDLE_TOKEN: usize = 1005;
+
+/// Files at least this large are loaded in the background, so that
+/// `new_view` can return before loading is finished.
+const BACKGROUND_LOAD_THRESHOLD: u64 = 8 * 1024 * 1024;
+
+/// The minimum interval between progress notifications for a file being
+/// loaded in the background.
+const LOAD_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// In large file mode, whitespace settings are detected from this many
+/// bytes at the start of the file.
+const WHITESPACE_SAMPLE_SIZE: usize = 1024 * 1024;
+
+/// The features disabled in large file mode, as reported to the client.
+const LARGE_FILE_DISABLED: [&str; 2] = ["autorun_plugins", "word_wrap"];
+
 #[cfg(feature = "notify")]
 const CONFIG_EVENT_TOKEN: WatchToken = WatchToken(1);
 
@@ -114,11 +146,27 @@ pub struct CoreState {
     config_manager: ConfigManager,
     /// Recorded editor actions
     recorder: RefCell<Recorder>,
+    /// Crash recovery files for unsaved changes
+    recovery: RecoveryManager,
+    /// Undo histories kept with the `persistent_undo` setting
+    undo_store: UndoStore,
     /// A weak reference to the main state container, stashed so that
     /// it can be passed to plugins.
     self_ref: Option<WeakXiCore>,
     /// Views which need to have setup finished.
     pending_views: Vec<(ViewId, Table)>,
+    /// New views of buffers that were already open, which need to have
+    /// setup finished.
+    pending_clones: Vec<ViewId>,
+    /// Views restored from a session, grouped by buffer, with the state
+    /// to give them once their setup is finished.
+    pending_restores: Vec<(Vec<ViewId>, SessionBuffer)>,
+    /// Buffers whose files are being loaded in the background, with their
+    /// paths. The setup of their views is finished once loading is done.
+    loads: HashMap<BufferId, (PathBuf, Table, Arc<Mutex<Load>>)>,
+    /// Buffers larger than the `large_file_threshold` config setting, for
+    /// which expensive features are disabled.
+    large_buffers: HashSet<BufferId>,
     peer: Client,
     id_counter: Counter,
     plugins: PluginCatalog,
@@ -126,6 +174,15 @@ pub struct CoreState {
     running_plugins: Vec<Plugin>,
 }
 
+/// The state of a file being loaded in the background, shared with the
+/// loading thread.
+#[derive(Default)]
+struct Load {
+    bytes_loaded: u64,
+    total_bytes: u64,
+    result: Option<Result<(Rope, FileInfo), FileError>>,
+}
+
 /// Initial setup and bookkeeping
 impl CoreState {
     pub(crate) fn new(
@@ -166,6 +223,9 @@ impl CoreState {
             watcher.watch_filtered(p, true, PLUGIN_EVENT_TOKEN, |p| p.is_dir() || !p.exists());
         }
 
+        let recovery = RecoveryManager::new(config_manager.get_recovery_dir());
+        let undo_store = UndoStore::new(config_manager.get_undo_dir());
+
         CoreState {
             views: BTreeMap::new(),
             editors: BTreeMap::new(),
@@ -178,8 +238,14 @@ impl CoreState {
             width_cache: RefCell::new(WidthCache::new()),
             config_manager,
             recorder: RefCell::new(Recorder::new()),
+            recovery,
+            undo_store,
             self_ref: None,
             pending_views: Vec::new(),
+            pending_clones: Vec::new(),
+            pending_restores: Vec::new(),
+            loads: HashMap::new(),
+            large_buffers: HashSet::new(),
             peer: Client::new(peer.clone()),
             id_counter: Counter::default(),
             plugins: PluginCatalog::default(),
@@ -220,6 +286,10 @@ impl CoreState {
         let theme_names = self.style_map.borrow().get_theme_names();
         self.peer.available_themes(theme_names);
 
+        if self.recovery.is_enabled() {
+            self.peer.schedule_timer(Instant::now() + RECOVERY_INTERVAL, RECOVERY_IDLE_TOKEN);
+        }
+
         // FIXME: temporary: we just launch every plugin we find at startup
         for manifest in self.plugins.iter() {
             start_plugin_process(
@@ -230,6 +300,13 @@ impl CoreState {
         }
     }
 
+    /// Makes `fs` handle files whose paths are URIs with this scheme, so
+    /// that an embedding application can provide its own file systems.
+    /// `zip://` URIs are handled by default.
+    pub fn register_file_system<S: Into<String>>(&mut self, scheme: S, fs: Arc<dyn FileSystem>) {
+        self.file_manager.register_file_system(scheme, fs);
+    }
+
     /// Attempt to load a config file.
     fn load_file_based_config(&mut self, path: &Path) {
         let _t = trace_block("CoreState::load_config_file", &["core"]);
@@ -254,14 +331,34 @@ impl CoreState {
     /// Notify editors/views/plugins of config changes.
     fn handle_config_changes(&self, changes: Vec<(BufferId, Table)>) {
         for (id, table) in changes {
-            let view_id = self
-                .views
-                .values()
-                .find(|v| v.borrow().get_buffer_id() == id)
-                .map(|v| v.borrow().get_view_id())
-                .unwrap();
+            for view_id in self.buffer_views(id) {
+                self.make_context(view_id).unwrap().config_changed(&table)
+            }
+        }
+    }
 
-            self.make_context(view_id).unwrap().config_changed(&table)
+    /// Returns the ids of all the views of a buffer, oldest first.
+    fn buffer_views(&self, buffer_id: BufferId) -> Vec<ViewId> {
+        self.views
+            .values()
+            .filter(|v| v.borrow().get_buffer_id() == buffer_id)
+            .map(|v| v.borrow().get_view_id())
+            .collect()
+    }
+
+    /// Returns the buffer of the file at `path`, if it is open or being
+    /// loaded.
+    fn open_buffer(&self, path: &Path) -> Option<BufferId> {
+        self.file_manager.get_editor(path).or_else(|| {
+            self.loads.iter().find(|(_, (p, _, _))| p == path).map(|(buffer_id, _)| *buffer_id)
+        })
+    }
+
+    /// Returns `true` if this view's file is still being loaded.
+    fn is_loading(&self, view_id: ViewId) -> bool {
+        match self.views.get(&view_id) {
+            Some(view) => self.loads.contains_key(&view.borrow().get_buffer_id()),
+            None => false,
         }
     }
 }
@@ -278,9 +375,19 @@ impl CoreState {
 
             let editor = &self.editors[&buffer_id];
             let info = self.file_manager.get_info(buffer_id);
-            let plugins = self.running_plugins.iter().collect::<Vec<_>>();
+            let plugins = self
+                .running_plugins
+                .iter()
+                .filter(|plugin| self.is_plugin_enabled(buffer_id, plugin))
+                .collect::<Vec<_>>();
             let config = self.config_manager.get_buffer_config(buffer_id);
             let language = self.config_manager.get_buffer_language(buffer_id);
+            let siblings = self
+                .views
+                .values()
+                .filter(|v| v.borrow().get_buffer_id() == buffer_id)
+                .filter(|v| v.borrow().get_view_id() != view_id)
+                .collect::<Vec<_>>();
 
             EventContext {
                 view_id,
@@ -291,7 +398,7 @@ impl CoreState {
                 recorder: &self.recorder,
                 language,
                 info,
-                siblings: Vec::new(),
+                siblings,
                 plugins,
                 client: &self.peer,
                 style_map: &self.style_map,
@@ -308,6 +415,13 @@ impl CoreState {
         Iter { views: Box::new(self.views.keys()), seen: HashSet::new(), inner: self }
     }
 
+    /// Returns `true` if `plugin` should receive events for this buffer.
+    /// Autorun plugins are not run for buffers in large file mode.
+    fn is_plugin_enabled(&self, buffer_id: BufferId, plugin: &Plugin) -> bool {
+        !self.large_buffers.contains(&buffer_id)
+            || self.plugins.get_named(&plugin.name).map(|p| !p.is_autorun()).unwrap_or(true)
+    }
+
     pub(crate) fn client_notification(&mut self, cmd: CoreNotification) {
         use self::CoreNotification::*;
         use self::CorePluginNotification as PN;
@@ -331,6 +445,12 @@ impl CoreState {
             // handled at the top level
             ClientStarted { .. } => (),
             SetLanguage { view_id, language_id } => self.do_set_language(view_id, language_id),
+            SetEncoding { view_id, encoding, reopen } => {
+                self.do_set_encoding(view_id, encoding, reopen)
+            }
+            SetReadOnly { view_id, read_only } => self.do_set_read_only(view_id, read_only),
+            RevertBuffer { view_id } => self.do_revert_buffer(view_id),
+            SaveAll {} => self.do_save_all(),
         }
     }
 
@@ -340,6 +460,10 @@ impl CoreState {
             //TODO: make file_path be an Option<PathBuf>
             //TODO: make this a notification
             NewView { file_path } => self.do_new_view(file_path.map(PathBuf::from)),
+            CloneView { view_id } => self.do_clone_view(view_id),
+            CloseAll { force } => self.do_close_all(force),
+            SaveSession {} => self.do_save_session(),
+            RestoreSession {} => self.do_restore_session(),
             Edit(crate::rpc::EditCommand { view_id, cmd }) => self.do_edit_sync(view_id, cmd),
             //TODO: why is this a request?? make a notification?
             GetConfig { view_id } => self.do_get_config(view_id).map(|c| json!(c)),
@@ -348,12 +472,37 @@ impl CoreState {
     }
 
     fn do_edit(&mut self, view_id: ViewId, cmd: EditNotification) {
-        if let Some(mut edit_ctx) = self.make_context(view_id) {
-            edit_ctx.do_edit(cmd);
+        if self.is_loading(view_id) {
+            warn!("ignoring edit to {:?}, which is still loading", view_id);
+            return;
+        }
+        let mut edit_ctx = match self.make_context(view_id) {
+            Some(ctx) => ctx,
+            None => return,
+        };
+        let line_ending = edit_ctx.editor.borrow().get_line_ending();
+        edit_ctx.do_edit(cmd);
+        if edit_ctx.editor.borrow().get_line_ending() != line_ending {
+            self.line_ending_changed(view_id);
         }
     }
 
+    /// Updates the buffer's config with the line ending it will be saved
+    /// with, after it was changed.
+    fn line_ending_changed(&mut self, view_id: ViewId) {
+        let buffer_id = self.views[&view_id].borrow().get_buffer_id();
+        let line_ending = self.editors[&buffer_id].borrow().get_line_ending();
+        let mut changes = Table::new();
+        changes.insert("line_ending".into(), line_ending.as_str().into());
+        let domain = ConfigDomain::SysOverride(buffer_id);
+        let new_config = self.config_manager.table_for_update(domain.clone(), changes);
+        self.set_config(domain, new_config);
+    }
+
     fn do_edit_sync(&mut self, view_id: ViewId, cmd: EditRequest) -> Result<Value, RemoteError> {
+        if self.is_loading(view_id) {
+            return Err(RemoteError::custom(404, format!("{:?} is still loading", view_id), None));
+        }
         if let Some(mut edit_ctx) = self.make_context(view_id) {
             edit_ctx.do_edit_sync(cmd)
         } else {
@@ -363,21 +512,53 @@ impl CoreState {
     }
 
     fn do_new_view(&mut self, path: Option<PathBuf>) -> Result<Value, RemoteError> {
+        self.open_view(path).map(|view_id| json!(view_id))
+    }
+
+    fn open_view(&mut self, path: Option<PathBuf>) -> Result<ViewId, RemoteError> {
+        let path = path.map(vfs::normalize);
+        // a file that is already open, or still loading, gets another view
+        // of its buffer
+        if let Some(buffer_id) = path.as_ref().and_then(|p| self.open_buffer(p)) {
+            return Ok(self.add_view(buffer_id));
+        }
+
         let view_id = self.next_view_id();
         let buffer_id = self.next_buffer_id();
 
+        let file_len = path
+            .as_ref()
+            .and_then(|p| self.file_manager.file_system(p).ok().and_then(|fs| fs.metadata(p).ok()));
+        let file_len = file_len.map(|m| m.len);
+        let load_in_background = file_len.unwrap_or(0) >= BACKGROUND_LOAD_THRESHOLD;
         let rope = match path.as_ref() {
-            Some(p) => self.file_manager.open(p, buffer_id)?,
-            None => Rope::from(""),
+            Some(p) if !load_in_background => self.file_manager.open(p, buffer_id)?,
+            _ => Rope::from(""),
         };
 
-        let editor = RefCell::new(Editor::with_text(rope));
+        let config = self.config_manager.add_buffer(buffer_id, path.as_ref().map(|p| p.as_path()));
+
+        // files keep their line endings; new buffers use the configured one
+        let line_ending = self.file_manager.get_info(buffer_id).and_then(|info| info.line_ending);
+        let line_ending = line_ending.unwrap_or_else(|| {
+            match self.config_manager.get_buffer_config(buffer_id).items.line_ending.as_str() {
+                "\r\n" => LineEnding::CrLf,
+                _ => LineEnding::Lf,
+            }
+        });
+        let read_only = self.file_manager.get_info(buffer_id).map(|info| info.read_only);
+        let mut editor = self.new_editor(buffer_id, rope);
+        editor.set_line_ending(line_ending);
+        editor.set_read_only(read_only.unwrap_or(false));
         let view = RefCell::new(View::new(view_id, buffer_id));
 
-        self.editors.insert(buffer_id, editor);
+        self.editors.insert(buffer_id, RefCell::new(editor));
         self.views.insert(view_id, view);
 
-        let config = self.config_manager.add_buffer(buffer_id, path.as_ref().map(|p| p.as_path()));
+        if load_in_background {
+            self.start_load(buffer_id, path.unwrap(), config);
+            return Ok(view_id);
+        }
 
         //NOTE: because this is a synchronous call, we have to return the
         //view_id before we can send any events to this view. We mark the
@@ -386,7 +567,93 @@ impl CoreState {
         self.pending_views.push((view_id, config));
         self.peer.schedule_idle(NEW_VIEW_IDLE_TOKEN);
 
-        Ok(json!(view_id))
+        Ok(view_id)
+    }
+
+    /// Creates a new view of the same buffer as `view_id`.
+    fn do_clone_view(&mut self, view_id: ViewId) -> Result<Value, RemoteError> {
+        match self.views.get(&view_id).map(|v| v.borrow().get_buffer_id()) {
+            Some(buffer_id) => Ok(json!(self.add_view(buffer_id))),
+            None => Err(RemoteError::custom(404, format!("missing view {:?}", view_id), None)),
+        }
+    }
+
+    /// Adds a view of an open buffer. As with other new views, its setup is
+    /// finished on the next runloop pass, or if the buffer's file is still
+    /// loading, once it has loaded.
+    fn add_view(&mut self, buffer_id: BufferId) -> ViewId {
+        let view_id = self.next_view_id();
+        self.views.insert(view_id, RefCell::new(View::new(view_id, buffer_id)));
+        self.pending_clones.push(view_id);
+        self.peer.schedule_idle(NEW_VIEW_IDLE_TOKEN);
+        view_id
+    }
+
+    /// Creates the editor for a newly opened buffer, restoring its file's
+    /// undo history if it was kept with the `persistent_undo` setting.
+    fn new_editor(&self, buffer_id: BufferId, text: Rope) -> Editor {
+        if !self.config_manager.get_buffer_config(buffer_id).items.persistent_undo {
+            return Editor::with_text(text);
+        }
+        let history = self.file_manager.get_info(buffer_id).and_then(|info| {
+            let hash = info.content_hash?;
+            self.undo_store.find(&info.path, hash)
+        });
+        match history {
+            Some(history) if history.get_text().len() == text.len() => {
+                Editor::with_history(history)
+            }
+            _ => Editor::with_text(text),
+        }
+    }
+
+    /// Keeps the undo history of a buffer that is being closed, if the
+    /// `persistent_undo` setting is on and it has no unsaved changes.
+    fn write_undo_history(&self, buffer_id: BufferId) {
+        let config = self.config_manager.get_buffer_config(buffer_id);
+        if !config.items.persistent_undo || self.large_buffers.contains(&buffer_id) {
+            return;
+        }
+        let (path, hash) = match self.file_manager.get_info(buffer_id) {
+            Some(info) if !info.has_changed => match info.content_hash {
+                Some(hash) => (&info.path, hash),
+                None => return,
+            },
+            _ => return,
+        };
+        let editor = self.editors[&buffer_id].borrow();
+        if !editor.is_pristine() {
+            return;
+        }
+        if let Err(e) = self.undo_store.write(path, hash, editor.get_undo_history()) {
+            warn!("failed to write undo history for {:?}: {}", path, e);
+        }
+    }
+
+    /// Loads the file for a new buffer on another thread. Progress is
+    /// reported to the client from the idle handler, and the buffer's views
+    /// are set up when loading is finished, in `handle_loads`.
+    fn start_load(&mut self, buffer_id: BufferId, path: PathBuf, config: Table) {
+        let load = Arc::new(Mutex::new(Load::default()));
+        self.loads.insert(buffer_id, (path.clone(), config, load.clone()));
+        let peer = self.peer.clone();
+        let fs = self.file_manager.file_system(&path);
+        thread::spawn(move || {
+            let mut last_update = Instant::now();
+            let result = fs.and_then(|fs| {
+                file::load_file(&*fs, &path, None, |loaded, total| {
+                    let mut load = load.lock().unwrap();
+                    load.bytes_loaded = loaded;
+                    load.total_bytes = total;
+                    if last_update.elapsed() >= LOAD_PROGRESS_INTERVAL {
+                        last_update = Instant::now();
+                        peer.schedule_idle(LOAD_IDLE_TOKEN);
+                    }
+                })
+            });
+            load.lock().unwrap().result = Some(result);
+            peer.schedule_idle(LOAD_IDLE_TOKEN);
+        });
     }
 
     fn do_save<P>(&mut self, view_id: ViewId, path: P)
@@ -394,7 +661,11 @@ impl CoreState {
         P: AsRef<Path>,
     {
         let _t = trace_block("CoreState::do_save", &["core"]);
-        let path = path.as_ref();
+        let path = &vfs::normalize(path.as_ref().to_owned());
+        if self.is_loading(view_id) {
+            self.peer.alert(format!("{:?} cannot be saved until it has finished loading", path));
+            return;
+        }
         let buffer_id = self.views.get(&view_id).map(|v| v.borrow().get_buffer_id());
         let buffer_id = match buffer_id {
             Some(id) => id,
@@ -403,36 +674,252 @@ impl CoreState {
 
         let mut save_ctx = self.make_context(view_id).unwrap();
         let fin_text = save_ctx.text_for_save();
-
-        if let Err(e) = self.file_manager.save(path, &fin_text, buffer_id) {
+        let line_ending = self.editors[&buffer_id].borrow().get_line_ending();
+        let prev_path = self.file_manager.get_info(buffer_id).map(|info| info.path.clone());
+        let backup = BackupSettings::new(
+            &self.config_manager.get_buffer_config(buffer_id).items,
+            self.config_manager.get_backup_dir(),
+        );
+
+        // the file is saved even if it can't be backed up, since not saving
+        // would lose the user's changes instead
+        if let Err(e) = file::write_backup(path, &backup) {
+            warn!("{}", e);
+            self.peer.alert(e.to_string());
+        }
+        if let Err(e) = self.file_manager.save(path, &fin_text, line_ending, buffer_id) {
             let error_message = e.to_string();
             error!("File error: {:?}", error_message);
             self.peer.alert(error_message);
             return;
         }
 
+        if let Some(prev_path) = prev_path {
+            self.recovery.remove(buffer_id, &prev_path);
+        }
+        self.recovery.remove(buffer_id, path);
+
         let changes = self.config_manager.update_buffer_path(buffer_id, path);
         let language = self.config_manager.get_buffer_language(buffer_id);
 
         self.make_context(view_id).unwrap().after_save(path);
-        self.make_context(view_id).unwrap().language_changed(&language);
+        for id in self.buffer_views(buffer_id) {
+            self.make_context(id).unwrap().language_changed(&language);
+        }
 
         // update the config _after_ sending save related events
         if let Some(changes) = changes {
-            self.make_context(view_id).unwrap().config_changed(&changes);
+            for id in self.buffer_views(buffer_id) {
+                self.make_context(id).unwrap().config_changed(&changes);
+            }
         }
     }
 
     fn do_close_view(&mut self, view_id: ViewId) {
-        let close_buffer = self.make_context(view_id).map(|ctx| ctx.close_view()).unwrap_or(true);
+        self.pending_clones.retain(|id| *id != view_id);
+        let (close_buffer, was_plugin_view) = self
+            .make_context(view_id)
+            .map(|ctx| (ctx.close_view(), ctx.plugin_view_id() == view_id))
+            .unwrap_or((true, false));
 
         let buffer_id = self.views.remove(&view_id).map(|v| v.borrow().get_buffer_id());
 
         if let Some(buffer_id) = buffer_id {
             if close_buffer {
+                // if the file is still loading, the result is discarded.
+                self.loads.remove(&buffer_id);
+                self.write_undo_history(buffer_id);
                 self.editors.remove(&buffer_id);
+                if let Some(info) = self.file_manager.get_info(buffer_id) {
+                    self.recovery.remove(buffer_id, &info.path);
+                }
                 self.file_manager.close(buffer_id);
                 self.config_manager.remove_buffer(buffer_id);
+                self.large_buffers.remove(&buffer_id);
+            } else if was_plugin_view {
+                // plugins now know the buffer by its next oldest view
+                let next = self.buffer_views(buffer_id)[0];
+                self.make_context(next).unwrap().notify_plugins_new_buffer();
+            }
+        }
+    }
+
+    /// Closes every view, unless some buffer has unsaved changes and
+    /// `force` is false. Returns the buffers with unsaved changes that
+    /// kept the views open.
+    fn do_close_all(&mut self, force: bool) -> Result<Value, RemoteError> {
+        if !force {
+            let blocking = self
+                .editors
+                .iter()
+                .filter(|(_, editor)| !editor.borrow().is_pristine())
+                .map(|(buffer_id, _)| {
+                    let path = self.file_manager.get_info(*buffer_id).map(|info| &info.path);
+                    json!({"view_ids": self.buffer_views(*buffer_id), "file_path": path})
+                })
+                .collect::<Vec<_>>();
+            if !blocking.is_empty() {
+                return Ok(json!(blocking));
+            }
+        }
+        let view_ids = self.views.keys().cloned().collect::<Vec<_>>();
+        view_ids.into_iter().for_each(|view_id| self.do_close_view(view_id));
+        Ok(json!([]))
+    }
+
+    /// Writes the open views, the contents of buffers with unsaved changes
+    /// or without a file, and the recordings to the session file.
+    fn do_save_session(&mut self) -> Result<Value, RemoteError> {
+        let path = self.config_manager.get_session_path().ok_or_else(no_config_dir)?;
+        let buffers = self
+            .editors
+            .iter()
+            .map(|(buffer_id, editor)| (*buffer_id, editor.borrow()))
+            // files still being loaded are left out
+            .filter(|(buffer_id, _)| !self.loads.contains_key(buffer_id))
+            .map(|(buffer_id, editor)| {
+                let info = self.file_manager.get_info(buffer_id);
+                let path = info.map(|info| info.path.clone());
+                let content_hash = info.and_then(|info| info.content_hash);
+                let text = match path {
+                    Some(_) if editor.is_pristine() => None,
+                    _ => Some(String::from(editor.get_buffer())),
+                };
+                let views = self
+                    .buffer_views(buffer_id)
+                    .iter()
+                    .map(|id| {
+                        let view = self.views[id].borrow();
+                        let selection = view.sel_regions().iter().map(|r| (r.start, r.end));
+                        SessionView { selection: selection.collect(), scroll: view.scroll_range() }
+                    })
+                    .collect();
+                let language = self.config_manager.get_language_override(buffer_id);
+                SessionBuffer { path, text, content_hash, language, views }
+            })
+            .collect();
+        let recordings = self.recorder.borrow().get_recordings().clone();
+
+        let session = Session { buffers, recordings };
+        session.save(&path).map_err(|e| FileError::Io(e, path))?;
+        Ok(json!(null))
+    }
+
+    /// Opens the views saved in the session file, returning each new view
+    /// with its file.
+    fn do_restore_session(&mut self) -> Result<Value, RemoteError> {
+        let path = self.config_manager.get_session_path().ok_or_else(no_config_dir)?;
+        let session = match Session::load(&path) {
+            Ok(Some(session)) => session,
+            Ok(None) => return Ok(json!([])),
+            Err(e) => return Err(FileError::Io(e, path).into()),
+        };
+        self.recorder.borrow_mut().add_recordings(session.recordings);
+
+        let mut restored = Vec::new();
+        for mut buffer in session.buffers {
+            let view_id = match self.open_view(buffer.path.clone()) {
+                Ok(view_id) => view_id,
+                // unsaved changes to a file that is gone are kept in a new buffer
+                Err(e) if buffer.text.is_some() => {
+                    warn!("failed to restore {:?}: {:?}", buffer.path, e);
+                    buffer.path = None;
+                    self.open_view(None)?
+                }
+                Err(e) => {
+                    warn!("failed to restore {:?}: {:?}", buffer.path, e);
+                    continue;
+                }
+            };
+            let buffer_id = self.views[&view_id].borrow().get_buffer_id();
+            let mut view_ids = vec![view_id];
+            view_ids.extend((1..buffer.views.len()).map(|_| self.add_view(buffer_id)));
+            let path = buffer.path.as_ref();
+            restored.extend(view_ids.iter().map(|id| json!({"view_id": id, "file_path": path})));
+            self.pending_restores.push((view_ids, buffer));
+        }
+        Ok(json!(restored))
+    }
+
+    /// Gives views restored from a session their saved state, once their
+    /// setup is finished.
+    fn finish_restores(&mut self) {
+        let (ready, loading) = self
+            .pending_restores
+            .split_off(0)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(view_ids, _)| !self.is_loading(view_ids[0]));
+        self.pending_restores = loading;
+
+        for (view_ids, buffer) in ready {
+            let view_id = view_ids[0];
+            if !self.views.contains_key(&view_id) {
+                continue;
+            }
+            if let Some(language) = buffer.language {
+                self.do_set_language(view_id, language);
+            }
+            if let Some(text) = buffer.text {
+                self.make_context(view_id).unwrap().replace_contents(Rope::from(text));
+                self.check_restored_file(view_id, buffer.content_hash);
+            }
+            for (id, state) in view_ids.iter().zip(buffer.views.iter()) {
+                if let Some(mut ctx) = self.make_context(*id) {
+                    ctx.restore_position(state);
+                }
+            }
+        }
+    }
+
+    /// Warns the user if the file of a view whose unsaved changes were
+    /// restored from a session has changed on disk since the session was
+    /// saved, since saving the changes would overwrite it.
+    fn check_restored_file(&self, view_id: ViewId, saved_hash: Option<u64>) {
+        let buffer_id = self.views[&view_id].borrow().get_buffer_id();
+        let info = match self.file_manager.get_info(buffer_id) {
+            Some(info) => info,
+            None => return,
+        };
+        if saved_hash.is_some() && info.content_hash != saved_hash {
+            self.peer.alert(format!(
+                "{:?} has changed on disk since the session was saved. Its unsaved changes \
+                 from the session have been restored, and saving them will overwrite it.",
+                info.path
+            ));
+        }
+    }
+
+    /// Saves every buffer that has a file and unsaved changes.
+    fn do_save_all(&mut self) {
+        let to_save = self
+            .editors
+            .iter()
+            .filter(|(_, editor)| !editor.borrow().is_pristine())
+            .filter_map(|(buffer_id, _)| {
+                let path = self.file_manager.get_info(*buffer_id)?.path.clone();
+                Some((self.buffer_views(*buffer_id)[0], path))
+            })
+            .collect::<Vec<_>>();
+        for (view_id, path) in to_save {
+            self.do_save(view_id, path);
+        }
+    }
+
+    /// Reads the view's file from disk again, discarding unsaved changes.
+    fn do_revert_buffer(&mut self, view_id: ViewId) {
+        if self.is_loading(view_id) {
+            return;
+        }
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return,
+        };
+        match self.file_manager.revert(buffer_id) {
+            Ok(text) => self.make_context(view_id).unwrap().revert(text),
+            Err(e) => {
+                let error_message = e.to_string();
+                error!("File error: {:?}", error_message);
+                self.peer.alert(error_message);
             }
         }
     }
@@ -456,10 +943,8 @@ impl CoreState {
         }
 
         self.iter_groups().for_each(|mut edit_ctx| {
-            edit_ctx.with_editor(|ed, view, _, _| {
-                ed.theme_changed(&self.style_map.borrow());
-                view.set_dirty(ed.get_buffer());
-            });
+            edit_ctx.with_editor(|ed, _, _, _| ed.theme_changed(&self.style_map.borrow()));
+            edit_ctx.set_views_dirty();
             edit_ctx.render_if_needed();
         });
     }
@@ -500,14 +985,53 @@ impl CoreState {
             let buffer_id = view.borrow().get_buffer_id();
             let changes = self.config_manager.override_language(buffer_id, language_id.clone());
 
-            let mut context = self.make_context(view_id).unwrap();
-            context.language_changed(&language_id);
-            if let Some(changes) = changes {
-                context.config_changed(&changes);
+            for id in self.buffer_views(buffer_id) {
+                let mut context = self.make_context(id).unwrap();
+                context.language_changed(&language_id);
+                if let Some(changes) = changes.as_ref() {
+                    context.config_changed(changes);
+                }
             }
         }
     }
 
+    fn do_set_encoding(&mut self, view_id: ViewId, encoding: CharacterEncoding, reopen: bool) {
+        if self.is_loading(view_id) {
+            return;
+        }
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return,
+        };
+
+        let result = if reopen {
+            self.file_manager
+                .reopen_with_encoding(buffer_id, encoding)
+                .map(|text| self.make_context(view_id).unwrap().reload(text))
+        } else {
+            self.file_manager.set_encoding(buffer_id, encoding)
+        };
+
+        if let Err(e) = result {
+            let error_message = e.to_string();
+            error!("File error: {:?}", error_message);
+            self.peer.alert(error_message);
+        }
+    }
+
+    /// Makes the view's buffer read-only or writable, and tells the client
+    /// about each view of that buffer.
+    fn do_set_read_only(&mut self, view_id: ViewId, read_only: bool) {
+        let buffer_id = match self.views.get(&view_id) {
+            Some(view) => view.borrow().get_buffer_id(),
+            None => return,
+        };
+        self.editors[&buffer_id].borrow_mut().set_read_only(read_only);
+        for id in self.buffer_views(buffer_id) {
+            self.peer.read_only(id, read_only);
+        }
+    }
+
     fn do_start_plugin(&mut self, _view_id: ViewId, plugin: &str) {
         if self.running_plugins.iter().any(|p| p.name == plugin) {
             info!("plugin {} already running", plugin);
@@ -541,6 +1065,10 @@ impl CoreState {
     }
 
     fn do_plugin_rpc(&self, view_id: ViewId, receiver: &str, method: &str, params: &Value) {
+        let view_id = match self.make_context(view_id) {
+            Some(ctx) => ctx.plugin_view_id(),
+            None => return,
+        };
         self.running_plugins
             .iter()
             .filter(|p| p.name == receiver)
@@ -548,7 +1076,9 @@ impl CoreState {
     }
 
     fn after_stop_plugin(&mut self, plugin: &Plugin) {
-        self.iter_groups().for_each(|mut cx| cx.plugin_stopped(plugin));
+        self.iter_groups()
+            .filter(|ctx| self.is_plugin_enabled(ctx.buffer_id, plugin))
+            .for_each(|mut cx| cx.plugin_stopped(plugin));
     }
 }
 
@@ -558,6 +1088,9 @@ impl CoreState {
         match token {
             NEW_VIEW_IDLE_TOKEN => self.finalize_new_views(),
             WATCH_IDLE_TOKEN => self.handle_fs_events(),
+            RECOVERY_IDLE_TOKEN => self.write_recovery_files(),
+            RECOVERY_RESPONSE_IDLE_TOKEN => self.handle_recovery_responses(),
+            LOAD_IDLE_TOKEN => self.handle_loads(),
             other if (other & RENDER_VIEW_IDLE_MASK) != 0 => {
                 self.handle_render_timer(other ^ RENDER_VIEW_IDLE_MASK)
             }
@@ -574,33 +1107,195 @@ impl CoreState {
     fn finalize_new_views(&mut self) {
         let to_start = mem::replace(&mut self.pending_views, Vec::new());
         to_start.iter().for_each(|(id, config)| {
-            let modified = self.detect_whitespace(*id, config);
+            let large_file = self.check_large_file(*id);
+            let mut changes = self.detect_whitespace(*id, large_file);
+            if large_file {
+                changes.insert("word_wrap".into(), false.into());
+                changes.insert("wrap_width".into(), 0.into());
+            }
+            let modified = self.set_sys_overrides(*id, config, changes);
             let config = modified.as_ref().unwrap_or(config);
             let mut edit_ctx = self.make_context(*id).unwrap();
             edit_ctx.finish_init(&config);
+            if large_file {
+                self.peer.large_file_mode(*id, &LARGE_FILE_DISABLED);
+            }
+            self.offer_recovery(*id);
         });
+
+        // the buffers of cloned views have already been set up, unless
+        // they are still loading
+        let (ready, loading) = self
+            .pending_clones
+            .split_off(0)
+            .into_iter()
+            .partition::<Vec<_>, _>(|id| !self.is_loading(*id));
+        self.pending_clones = loading;
+        for id in ready {
+            let buffer_id = self.views[&id].borrow().get_buffer_id();
+            let config = self.config_manager.get_buffer_config(buffer_id).to_table();
+            self.make_context(id).unwrap().finish_init(&config);
+            if self.large_buffers.contains(&buffer_id) {
+                self.peer.large_file_mode(id, &LARGE_FILE_DISABLED);
+            }
+        }
+        self.finish_restores();
+    }
+
+    /// Reports the progress of files being loaded in the background, and
+    /// sets up the views of any that have finished.
+    fn handle_loads(&mut self) {
+        let mut finished = Vec::new();
+        for (buffer_id, (_, _, load)) in self.loads.iter() {
+            let mut load = load.lock().unwrap();
+            for view_id in self.buffer_views(*buffer_id) {
+                self.peer.load_progress(view_id, load.bytes_loaded, load.total_bytes);
+            }
+            if let Some(result) = load.result.take() {
+                finished.push((*buffer_id, result));
+            }
+        }
+
+        for (buffer_id, result) in finished {
+            let (_, config, _) = self.loads.remove(&buffer_id).unwrap();
+            // the oldest view sets up the buffer, and the others wait for
+            // it in `pending_clones`
+            let view_id = self.buffer_views(buffer_id)[0];
+            self.pending_clones.retain(|id| *id != view_id);
+            match result {
+                Ok((rope, info)) => {
+                    let line_ending = info.line_ending;
+                    let read_only = info.read_only;
+                    self.file_manager.finish_open(buffer_id, info);
+                    let new_editor = self.new_editor(buffer_id, rope);
+                    let mut editor = self.editors[&buffer_id].borrow_mut();
+                    let line_ending = line_ending.unwrap_or_else(|| editor.get_line_ending());
+                    *editor = new_editor;
+                    editor.set_line_ending(line_ending);
+                    editor.set_read_only(read_only);
+                }
+                Err(e) => {
+                    let error_message = e.to_string();
+                    error!("File error: {:?}", error_message);
+                    self.peer.alert(error_message);
+                    // the empty buffer must not be saved over the file
+                    for view_id in self.buffer_views(buffer_id) {
+                        self.peer.close_view(view_id);
+                        self.do_close_view(view_id);
+                    }
+                    continue;
+                }
+            }
+            self.pending_views.push((view_id, config));
+        }
+        self.finalize_new_views();
     }
 
-    // Detects whitespace settings from the file and merges them with the config
-    fn detect_whitespace(&mut self, id: ViewId, config: &Table) -> Option<Table> {
-        let buffer_id = self.views.get(&id).map(|v| v.borrow().get_buffer_id())?;
+    /// Writes a recovery file for each file-backed buffer with changes
+    /// made since it was last saved or recovery file was written.
+    fn write_recovery_files(&mut self) {
+        let _t = trace_block("CoreState::write_recovery_files", &["core"]);
+        for (buffer_id, editor) in self.editors.iter() {
+            let path = match self.file_manager.get_info(*buffer_id) {
+                Some(info) => &info.path,
+                None => continue,
+            };
+            let editor = editor.borrow();
+            let rev = editor.get_head_rev_token();
+            if editor.is_pristine() {
+                // changes may have been undone
+                if self.recovery.has_written(*buffer_id) {
+                    self.recovery.remove(*buffer_id, path);
+                }
+            } else if !self.recovery.is_current(*buffer_id, rev) {
+                if let Err(e) = self.recovery.write(*buffer_id, rev, path, editor.get_buffer()) {
+                    warn!("failed to write recovery file for {:?}: {}", path, e);
+                }
+            }
+        }
+        self.peer.schedule_timer(Instant::now() + RECOVERY_INTERVAL, RECOVERY_IDLE_TOKEN);
+    }
+
+    /// If there is a recovery file for this view's file that is newer than
+    /// the file itself, asks the client whether it should be restored.
+    fn offer_recovery(&self, view_id: ViewId) {
+        let buffer_id = self.views[&view_id].borrow().get_buffer_id();
+        let info = match self.file_manager.get_info(buffer_id) {
+            Some(info) => info,
+            None => return,
+        };
+        if self.recovery.find(&info.path, info.mod_time).is_none() {
+            return;
+        }
+
+        // The response arrives on the rpc thread, so we stash it and
+        // handle it from the runloop.
+        let responses = self.recovery.responses();
+        let peer = self.peer.clone();
+        self.peer.offer_recovery(view_id, &info.path, move |response| {
+            let restore = response.ok().and_then(|v| v.as_bool()).unwrap_or(false);
+            responses.lock().unwrap().push((view_id, restore));
+            peer.schedule_idle(RECOVERY_RESPONSE_IDLE_TOKEN);
+        });
+    }
+
+    fn handle_recovery_responses(&mut self) {
+        for (view_id, restore) in self.recovery.take_responses() {
+            let buffer_id = match self.views.get(&view_id) {
+                Some(view) => view.borrow().get_buffer_id(),
+                None => continue,
+            };
+            let path = match self.file_manager.get_info(buffer_id) {
+                Some(info) => info.path.clone(),
+                None => continue,
+            };
+            let text = if restore { self.recovery.find(&path, None) } else { None };
+            match text {
+                Some(text) => self.make_context(view_id).unwrap().replace_contents(text),
+                None => self.recovery.remove(buffer_id, &path),
+            }
+        }
+    }
+
+    /// Puts this view's buffer in large file mode if it is larger than the
+    /// configured threshold. Returns `true` if the buffer is in large file mode.
+    fn check_large_file(&mut self, id: ViewId) -> bool {
+        let buffer_id = self.views[&id].borrow().get_buffer_id();
+        let threshold = self.config_manager.get_buffer_config(buffer_id).items.large_file_threshold;
+        let len = self.editors[&buffer_id].borrow().get_buffer().len() as u64;
+        if len < threshold {
+            return false;
+        }
+        info!("{:?} is {} bytes, entering large file mode", buffer_id, len);
+        self.large_buffers.insert(buffer_id);
+        true
+    }
+
+    /// Detects whitespace settings from the file. In large file mode, only
+    /// the start of the file is examined.
+    fn detect_whitespace(&self, id: ViewId, large_file: bool) -> Table {
+        let mut changes = Table::new();
+        let buffer_id = self.views[&id].borrow().get_buffer_id();
         let editor = self
             .editors
             .get(&buffer_id)
             .expect("existing buffer_id must have corresponding editor");
 
         if editor.borrow().get_buffer().is_empty() {
-            return None;
+            return changes;
         }
 
         let autodetect_whitespace =
             self.config_manager.get_buffer_config(buffer_id).items.autodetect_whitespace;
         if !autodetect_whitespace {
-            return None;
+            return changes;
         }
 
-        let mut changes = Table::new();
-        let indentation = Indentation::parse(editor.borrow().get_buffer());
+        let indentation = if large_file {
+            Indentation::parse_head(editor.borrow().get_buffer(), WHITESPACE_SAMPLE_SIZE)
+        } else {
+            Indentation::parse(editor.borrow().get_buffer())
+        };
         match indentation {
             Ok(Some(Indentation::Tabs)) => {
                 changes.insert("translate_tabs_to_spaces".into(), false.into());
@@ -613,18 +1308,24 @@ impl CoreState {
             Ok(None) => info!("file contains no indentation"),
         }
 
-        let line_ending = LineEnding::parse(editor.borrow().get_buffer());
-        match line_ending {
-            Ok(Some(LineEnding::CrLf)) => {
-                changes.insert("line_ending".into(), "\r\n".into());
+        // buffers are normalized to '\n' on load, so we use what was on disk
+        match self.file_manager.get_info(buffer_id).and_then(|info| info.line_ending) {
+            Some(line_ending) => {
+                changes.insert("line_ending".into(), line_ending.as_str().into());
             }
-            Ok(Some(LineEnding::Lf)) => {
-                changes.insert("line_ending".into(), "\n".into());
-            }
-            Err(_) => info!("detected mixed line endings"),
-            Ok(None) => info!("file contains no supported line endings"),
+            None => info!("file contains no supported line endings"),
         }
+        changes
+    }
 
+    /// Applies settings chosen by core, such as detected whitespace, to this
+    /// view's buffer, and merges them with the config.
+    fn set_sys_overrides(&mut self, id: ViewId, config: &Table, changes: Table) -> Option<Table> {
+        if changes.is_empty() {
+            return None;
+        }
+
+        let buffer_id = self.views[&id].borrow().get_buffer_id();
         let config_delta =
             self.config_manager.table_for_update(ConfigDomain::SysOverride(buffer_id), changes);
         match self
@@ -634,7 +1335,7 @@ impl CoreState {
             Ok(ref mut items) if !items.is_empty() => {
                 assert!(
                     items.len() == 1,
-                    "whitespace overrides can only update a single buffer's config\n{:?}",
+                    "system overrides can only update a single buffer's config\n{:?}",
                     items
                 );
                 let table = items.remove(0).1;
@@ -647,7 +1348,7 @@ impl CoreState {
                 None
             }
             Err(err) => {
-                warn!("detect_whitespace failed to update config: {:?}", err);
+                warn!("failed to set system overrides: {:?}", err);
                 None
             }
         }
@@ -714,21 +1415,18 @@ impl CoreState {
 
         let has_changes = self.file_manager.check_file(path, buffer_id);
         let is_pristine = self.editors.get(&buffer_id).map(|ed| ed.borrow().is_pristine()).unwrap();
-        //TODO: currently we only use the file's modification time when
-        // determining if a file has been changed by another process.
-        // A more robust solution would also hash the file's contents.
-
-        if has_changes && is_pristine {
-            if let Ok(text) = self.file_manager.open(path, buffer_id) {
-                // this is ugly; we don't map buffer_id -> view_id anywhere
-                // but we know we must have a view.
-                let view_id = self
-                    .views
-                    .values()
-                    .find(|v| v.borrow().get_buffer_id() == buffer_id)
-                    .map(|v| v.borrow().get_view_id())
-                    .unwrap();
-                self.make_context(view_id).unwrap().reload(text);
+
+        if has_changes {
+            // the file is read with the encoding the user chose for it
+            if let Ok(text) = self.file_manager.revert(buffer_id) {
+                // we know we must have a view.
+                let view_id = self.buffer_views(buffer_id)[0];
+                let mut ctx = self.make_context(view_id).unwrap();
+                if is_pristine {
+                    ctx.reload(text);
+                } else {
+                    ctx.merge(text);
+                }
             }
         }
     }
@@ -902,10 +1600,15 @@ impl CoreState {
     pub(crate) fn plugin_connect(&mut self, plugin: Result<Plugin, io::Error>) {
         match plugin {
             Ok(plugin) => {
-                let init_info =
-                    self.iter_groups().map(|mut ctx| ctx.plugin_info()).collect::<Vec<_>>();
+                let init_info = self
+                    .iter_groups()
+                    .filter(|ctx| self.is_plugin_enabled(ctx.buffer_id, &plugin))
+                    .map(|mut ctx| ctx.plugin_info())
+                    .collect::<Vec<_>>();
                 plugin.initialize(init_info);
-                self.iter_groups().for_each(|mut cx| cx.plugin_started(&plugin));
+                self.iter_groups()
+                    .filter(|ctx| self.is_plugin_enabled(ctx.buffer_id, &plugin))
+                    .for_each(|mut cx| cx.plugin_started(&plugin));
                 self.running_plugins.push(plugin);
             }
             Err(e) => error!("failed to start plugin {:?}", e),
@@ -1014,6 +1717,12 @@ where
     }
 }
 
+/// The error returned by session requests when there is nowhere to
+/// keep the session file.
+fn no_config_dir() -> RemoteError {
+    RemoteError::custom(404, "sessions need a config directory", None)
+}
+
 #[derive(Debug, Default)]
 pub(crate) struct Counter(Cell<usize>);
 
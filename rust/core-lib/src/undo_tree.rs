@@ -0,0 +1,294 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! The shape of a buffer's undo history.
+//!
+//! Every undo group is a node, whose parent is the group that was current
+//! when it was created. Making an edit after an undo starts a new branch
+//! instead of discarding the undone groups, so any earlier state of the
+//! buffer can be returned to. The `Editor` decides which groups are undone;
+//! this only keeps track of the tree, and of when each node was current.
+
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use xi_rope::delta::DeltaElement;
+use xi_rope::RopeDelta;
+
+/// The maximum number of chars of inserted text kept for a node's summary.
+const MAX_SUMMARY_CHARS: usize = 40;
+
+/// The maximum number of visits remembered, for `undo_to_time`.
+const MAX_VISITS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Node {
+    /// `None` for the root.
+    parent: Option<usize>,
+    created: SystemTime,
+    /// The child that was most recently current, which redo moves to.
+    last_child: Option<usize>,
+    /// The start of the text inserted by the group.
+    inserted: String,
+    /// The number of bytes deleted by the group.
+    deleted: usize,
+}
+
+/// The undo groups of a buffer, arranged as a tree.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct UndoTree {
+    root: usize,
+    nodes: BTreeMap<usize, Node>,
+    /// The times at which nodes became current, oldest first.
+    visits: Vec<(SystemTime, usize)>,
+}
+
+/// A description of the tree, as sent to the client.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct UndoTreeInfo {
+    pub(crate) root: usize,
+    pub(crate) current: usize,
+    pub(crate) nodes: Vec<UndoNodeInfo>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct UndoNodeInfo {
+    pub(crate) id: usize,
+    pub(crate) parent: Option<usize>,
+    /// When the node was created, in milliseconds since the Unix epoch.
+    pub(crate) time: u64,
+    pub(crate) summary: String,
+}
+
+impl UndoTree {
+    /// Creates a tree containing only `root`, which is current.
+    pub(crate) fn new(root: usize) -> UndoTree {
+        let now = SystemTime::now();
+        let node = Node {
+            parent: None,
+            created: now,
+            last_child: None,
+            inserted: String::new(),
+            deleted: 0,
+        };
+        let mut nodes = BTreeMap::new();
+        nodes.insert(root, node);
+        UndoTree { root, nodes, visits: vec![(now, root)] }
+    }
+
+    /// The oldest group in the tree, which can't be undone.
+    pub(crate) fn root(&self) -> usize {
+        self.root
+    }
+
+    /// Iterates over the groups in the tree.
+    pub(crate) fn groups<'a>(&'a self) -> impl Iterator<Item = usize> + 'a {
+        self.nodes.keys().cloned()
+    }
+
+    /// Adds `group` as a child of `parent`, and makes it current.
+    pub(crate) fn add(&mut self, group: usize, parent: usize) {
+        let node = Node {
+            parent: Some(parent),
+            created: SystemTime::now(),
+            last_child: None,
+            inserted: String::new(),
+            deleted: 0,
+        };
+        self.nodes.insert(group, node);
+        self.visit(group);
+    }
+
+    /// Updates the summary of `group` with an edit made in it.
+    pub(crate) fn record_edit(&mut self, group: usize, delta: &RopeDelta) {
+        let node = match self.nodes.get_mut(&group) {
+            Some(node) => node,
+            None => return,
+        };
+        let mut copied = 0;
+        for el in &delta.els {
+            match el {
+                DeltaElement::Copy(start, end) => copied += end - start,
+                DeltaElement::Insert(rope) => {
+                    let room = MAX_SUMMARY_CHARS - node.inserted.chars().count();
+                    let text = rope.slice_to_cow(..);
+                    node.inserted.extend(text.chars().take(room));
+                }
+            }
+        }
+        node.deleted += delta.base_len.saturating_sub(copied);
+    }
+
+    /// Records that `group` is now current. Redo from each of its
+    /// ancestors will move towards it.
+    pub(crate) fn visit(&mut self, group: usize) {
+        let mut child = group;
+        while let Some(parent) = self.nodes.get(&child).and_then(|n| n.parent) {
+            self.nodes.get_mut(&parent).unwrap().last_child = Some(child);
+            child = parent;
+        }
+        if self.visits.last().map(|&(_, g)| g) != Some(group) {
+            self.visits.push((SystemTime::now(), group));
+        }
+        if self.visits.len() > MAX_VISITS {
+            let excess = self.visits.len() - MAX_VISITS;
+            self.visits.drain(..excess);
+        }
+    }
+
+    /// Returns the groups from the root to `group` inclusive, or `None`
+    /// if `group` isn't in the tree.
+    pub(crate) fn path_to(&self, group: usize) -> Option<Vec<usize>> {
+        let mut node = self.nodes.get(&group)?;
+        let mut path = vec![group];
+        while let Some(parent) = node.parent {
+            path.push(parent);
+            node = &self.nodes[&parent];
+        }
+        path.reverse();
+        Some(path)
+    }
+
+    /// Returns the groups that redo would move through from `group`.
+    pub(crate) fn redo_path(&self, group: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut next = self.nodes.get(&group).and_then(|n| n.last_child);
+        while let Some(group) = next {
+            path.push(group);
+            next = self.nodes[&group].last_child;
+        }
+        path
+    }
+
+    /// Returns the group that was current at `time`, or the root if
+    /// that was before any remaining history.
+    pub(crate) fn group_at(&self, time: SystemTime) -> usize {
+        self.visits.iter().rev().find(|&&(t, _)| t <= time).map(|&(_, g)| g).unwrap_or(self.root)
+    }
+
+    /// Makes `new_root`, a child of the root, the new root. Returns the old
+    /// root and every group that is no longer reachable from the new one.
+    pub(crate) fn set_root(&mut self, new_root: usize) -> Vec<usize> {
+        let mut removed = vec![self.root];
+        let mut stack = vec![self.root];
+        while let Some(group) = stack.pop() {
+            for (&child, node) in &self.nodes {
+                if node.parent == Some(group) && child != new_root {
+                    removed.push(child);
+                    stack.push(child);
+                }
+            }
+        }
+        for group in &removed {
+            self.nodes.remove(group);
+        }
+        self.nodes.get_mut(&new_root).unwrap().parent = None;
+        self.root = new_root;
+        let nodes = &self.nodes;
+        self.visits.retain(|&(_, g)| nodes.contains_key(&g));
+        removed
+    }
+
+    /// Describes the tree, for the client.
+    pub(crate) fn info(&self, current: usize) -> UndoTreeInfo {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|(&id, node)| UndoNodeInfo {
+                id,
+                parent: node.parent,
+                time: node
+                    .created
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() * 1000 + u64::from(d.subsec_millis()))
+                    .unwrap_or(0),
+                summary: node.summary(),
+            })
+            .collect();
+        UndoTreeInfo { root: self.root, current, nodes }
+    }
+}
+
+impl Node {
+    fn summary(&self) -> String {
+        match (self.inserted.is_empty(), self.deleted) {
+            (true, 0) => String::new(),
+            (true, n) => format!("delete {}", n),
+            (false, 0) => format!("insert {:?}", self.inserted),
+            (false, n) => format!("replace {} with {:?}", n, self.inserted),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use xi_rope::{DeltaBuilder, Rope};
+
+    #[test]
+    fn branches() {
+        let mut tree = UndoTree::new(0);
+        tree.add(1, 0);
+        tree.add(2, 1);
+        tree.visit(1);
+        tree.add(3, 1);
+        assert_eq!(tree.path_to(3), Some(vec![0, 1, 3]));
+        assert_eq!(tree.path_to(2), Some(vec![0, 1, 2]));
+        assert_eq!(tree.path_to(4), None);
+        assert_eq!(tree.redo_path(0), vec![1, 3]);
+        tree.visit(2);
+        assert_eq!(tree.redo_path(0), vec![1, 2]);
+
+        assert_eq!(tree.set_root(1), vec![0]);
+        assert_eq!(tree.path_to(2), Some(vec![1, 2]));
+        assert_eq!(tree.set_root(3), vec![1, 2]);
+        assert_eq!(tree.groups().collect::<Vec<_>>(), vec![3]);
+    }
+
+    #[test]
+    fn group_at() {
+        let mut tree = UndoTree::new(0);
+        let before = SystemTime::now() - Duration::from_secs(60);
+        tree.add(1, 0);
+        tree.add(2, 1);
+        tree.visit(1);
+        assert_eq!(tree.group_at(SystemTime::now()), 1);
+        assert_eq!(tree.group_at(before), 0);
+
+        for _ in 0..MAX_VISITS {
+            tree.visit(2);
+            tree.visit(1);
+        }
+        assert_eq!(tree.visits.len(), MAX_VISITS);
+    }
+
+    #[test]
+    fn summaries() {
+        let mut tree = UndoTree::new(0);
+        tree.add(1, 0);
+        let mut builder = DeltaBuilder::new(5);
+        builder.replace(0..2, Rope::from("abc"));
+        tree.record_edit(1, &builder.build());
+        tree.add(2, 1);
+        let mut builder = DeltaBuilder::new(6);
+        builder.delete(0..3);
+        tree.record_edit(2, &builder.build());
+
+        let info = tree.info(2);
+        assert_eq!(info.current, 2);
+        let summaries = info.nodes.iter().map(|n| n.summary.as_str()).collect::<Vec<_>>();
+        assert_eq!(summaries, vec!["", "replace 2 with \"abc\"", "delete 3"]);
+    }
+}
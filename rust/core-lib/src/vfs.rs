@@ -0,0 +1,549 @@
+// Copyright 2019 The xi-editor Authors.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Pluggable file systems.
+//!
+//! The `FileManager` reads and writes files through the `FileSystem` trait.
+//! Plain paths and `file://` URIs refer to the local disk, and `zip://`
+//! URIs to entries in zip archives. Other file systems are registered by
+//! URI scheme, with `CoreState::register_file_system`. A file system is
+//! given the whole URI, and is responsible for interpreting it.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions, Permissions};
+use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use crate::archive::{ZipFileSystem, ZIP_SCHEME};
+#[cfg(feature = "notify")]
+use crate::watcher::{FileWatcher, WatchToken};
+
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+#[cfg(target_family = "unix")]
+use std::os::unix::io::AsRawFd;
+#[cfg(target_family = "unix")]
+use xattr::FileExt;
+
+/// The scheme of URIs that refer to the local disk.
+const FILE_SCHEME: &str = "file";
+
+/// What is known about a file without reading it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    /// The size of the file in bytes.
+    pub len: u64,
+    pub mod_time: Option<SystemTime>,
+    /// Whether the file can't be written to.
+    pub read_only: bool,
+    /// The file's permissions on UNIXy systems, if it has any.
+    pub permissions: Option<u32>,
+}
+
+/// A place files can be loaded from and saved to.
+pub trait FileSystem: Send + Sync {
+    /// Opens the file at `path` for reading.
+    fn read(&self, path: &Path) -> io::Result<Box<dyn Read + Send>>;
+
+    /// Replaces the contents of the file at `path`, creating it if it
+    /// doesn't exist, with whatever `contents` writes. If `contents` fails,
+    /// the file should be left as it was.
+    fn write(
+        &self,
+        path: &Path,
+        contents: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> io::Result<()>;
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// Starts reporting changes to the file at `path` to `watcher`. File
+    /// systems that can't be watched do nothing; changes made to their
+    /// files are noticed when the file is next saved.
+    #[cfg(feature = "notify")]
+    fn watch(&self, _path: &Path, _watcher: &mut FileWatcher, _token: WatchToken) {}
+
+    #[cfg(feature = "notify")]
+    fn unwatch(&self, _path: &Path, _watcher: &mut FileWatcher, _token: WatchToken) {}
+}
+
+/// The file systems available, by URI scheme.
+#[derive(Clone)]
+pub struct FileSystems {
+    local: Arc<dyn FileSystem>,
+    schemes: HashMap<String, Arc<dyn FileSystem>>,
+}
+
+impl FileSystems {
+    /// Makes `fs` handle URIs with this scheme, replacing any file system
+    /// previously registered for it.
+    pub fn register<S: Into<String>>(&mut self, scheme: S, fs: Arc<dyn FileSystem>) {
+        let scheme = scheme.into();
+        if scheme == FILE_SCHEME {
+            self.local = fs;
+        } else {
+            self.schemes.insert(scheme, fs);
+        }
+    }
+
+    /// Returns the file system that `path` belongs to, or `None` if it is
+    /// a URI with a scheme that hasn't been registered.
+    pub fn get(&self, path: &Path) -> Option<Arc<dyn FileSystem>> {
+        match scheme(path) {
+            None | Some(FILE_SCHEME) => Some(self.local.clone()),
+            Some(scheme) => self.schemes.get(scheme).cloned(),
+        }
+    }
+}
+
+impl Default for FileSystems {
+    fn default() -> Self {
+        let mut schemes: HashMap<String, Arc<dyn FileSystem>> = HashMap::new();
+        schemes.insert(ZIP_SCHEME.into(), Arc::new(ZipFileSystem));
+        FileSystems { local: Arc::new(LocalFileSystem), schemes }
+    }
+}
+
+/// Returns the scheme of `path`, if it is a URI such as
+/// `zip://archive.zip/path`.
+pub fn scheme(path: &Path) -> Option<&str> {
+    let path = path.to_str()?;
+    let scheme = &path[..path.find("://")?];
+    // a single letter is more likely to be a Windows drive
+    let is_valid = scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    if is_valid {
+        Some(scheme)
+    } else {
+        None
+    }
+}
+
+/// Returns the location on the local disk of `path`, if it has one.
+pub fn local_path(path: &Path) -> Option<&Path> {
+    match scheme(path) {
+        None => Some(path),
+        Some(FILE_SCHEME) => path.to_str().map(|s| Path::new(&s[FILE_SCHEME.len() + 3..])),
+        Some(_) => None,
+    }
+}
+
+/// Converts `file://` URIs to plain paths, so that each local file is only
+/// known by one name. Other paths are unchanged.
+pub fn normalize(path: PathBuf) -> PathBuf {
+    match scheme(&path) {
+        Some(FILE_SCHEME) => local_path(&path).map(Path::to_owned).unwrap_or(path),
+        _ => path,
+    }
+}
+
+/// The local disk.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalFileSystem;
+
+impl LocalFileSystem {
+    fn local_path(path: &Path) -> io::Result<&Path> {
+        local_path(path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} is not a local path", path))
+        })
+    }
+}
+
+impl FileSystem for LocalFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        let f = File::open(LocalFileSystem::local_path(path)?)?;
+        Ok(Box::new(f))
+    }
+
+    /// If `path` is a symlink, the file it points to is written. The new
+    /// contents are written to a temporary file next to it, which is synced
+    /// to disk and then renamed over the original, so that a crash never
+    /// leaves a partly written file; the original's permissions, owner and
+    /// extended attributes are kept where possible. If the temporary file
+    /// can't be created, for instance because the directory isn't writable,
+    /// the file is overwritten in place.
+    fn write(
+        &self,
+        path: &Path,
+        contents: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let path = &resolve_symlinks(LocalFileSystem::local_path(path)?);
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".swp");
+        let tmp_path = &path.with_file_name(tmp_name);
+
+        let f = match File::create(tmp_path) {
+            Ok(f) => f,
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied && path.exists() => {
+                info!("saving {:?} in place: {}", path, e);
+                return write_in_place(path, contents);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let result = (|| {
+            let mut writer = BufWriter::new(&f);
+            contents(&mut writer)?;
+            writer.flush()?;
+            copy_metadata(path, &f)?;
+            f.sync_all()
+        })();
+        if let Err(e) = result.and_then(|()| fs::rename(tmp_path, path)) {
+            let _ = fs::remove_file(tmp_path);
+            return Err(e);
+        }
+        sync_parent_dir(path);
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = LocalFileSystem::local_path(path)?;
+        let metadata = fs::metadata(path)?;
+        #[cfg(target_family = "unix")]
+        let permissions = Some(metadata.permissions().mode());
+        #[cfg(not(target_family = "unix"))]
+        let permissions = None;
+        Ok(Metadata {
+            len: metadata.len(),
+            mod_time: metadata.modified().ok(),
+            read_only: metadata.permissions().readonly() || !can_write(path),
+            permissions,
+        })
+    }
+
+    #[cfg(feature = "notify")]
+    fn watch(&self, path: &Path, watcher: &mut FileWatcher, token: WatchToken) {
+        if let Some(path) = local_path(path) {
+            watcher.watch(path, false, token);
+        }
+    }
+
+    #[cfg(feature = "notify")]
+    fn unwatch(&self, path: &Path, watcher: &mut FileWatcher, token: WatchToken) {
+        if let Some(path) = local_path(path) {
+            watcher.unwatch(path, token);
+        }
+    }
+}
+
+/// Overwrites the file at `path`, for when a new file can't be created
+/// alongside it. The new contents are collected before anything is
+/// written, so that an error doesn't leave the file truncated.
+fn write_in_place(
+    path: &Path,
+    contents: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut buf = Vec::new();
+    contents(&mut buf)?;
+    let mut f = OpenOptions::new().write(true).truncate(true).open(path)?;
+    f.write_all(&buf)?;
+    f.sync_all()
+}
+
+/// Writes `value` as JSON to a file of xi's own, such as the session or a
+/// recovery file. As when saving, the JSON goes to a temporary file, which
+/// is synced to disk and then renamed over `path`; the temporary file is
+/// removed if anything fails.
+pub(crate) fn write_json<T: Serialize>(path: &Path, value: &T) -> io::Result<()> {
+    let tmp_path = &path.with_extension("tmp");
+    let result = (|| {
+        let mut writer = BufWriter::new(File::create(tmp_path)?);
+        serde_json::to_writer(&mut writer, value)?;
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(tmp_path, path)
+    })();
+    if let Err(e) = result {
+        let _ = fs::remove_file(tmp_path);
+        return Err(e);
+    }
+    sync_parent_dir(path);
+    Ok(())
+}
+
+/// Returns the file that `path` refers to, following any symlinks, so that
+/// saving replaces that file rather than the link. The file need not exist.
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    // the limit on the length of a chain of links on Linux, which also
+    // stops us going around a cycle forever.
+    const MAX_LINKS: usize = 40;
+    let mut path = path.to_owned();
+    for _ in 0..MAX_LINKS {
+        match fs::read_link(&path) {
+            Ok(target) => path = path.parent().map(|p| p.join(&target)).unwrap_or(target),
+            Err(_) => break,
+        }
+    }
+    path
+}
+
+/// Copies the permissions, owner and extended attributes of the file at
+/// `original`, if it exists, to `file`, which will replace it. Failing to
+/// copy the owner or attributes is not an error, since a user can't always
+/// set them.
+#[cfg(target_family = "unix")]
+fn copy_metadata(original: &Path, file: &File) -> io::Result<()> {
+    let metadata = match fs::metadata(original) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    let new_metadata = file.metadata()?;
+    if (metadata.uid(), metadata.gid()) != (new_metadata.uid(), new_metadata.gid()) {
+        let result = unsafe { libc::fchown(file.as_raw_fd(), metadata.uid(), metadata.gid()) };
+        if result != 0 {
+            let e = io::Error::last_os_error();
+            info!("could not preserve the owner of {:?}: {}", original, e);
+        }
+    }
+    let names = xattr::list(original).map(|names| names.collect::<Vec<_>>());
+    for name in names.unwrap_or_default() {
+        let result = xattr::get(original, &name).and_then(|value| match value {
+            Some(value) => file.set_xattr(&name, &value),
+            None => Ok(()),
+        });
+        if let Err(e) = result {
+            info!("could not preserve attribute {:?} of {:?}: {}", name, original, e);
+        }
+    }
+
+    // this comes last, since changing the owner can clear setuid bits.
+    file.set_permissions(Permissions::from_mode(metadata.permissions().mode()))
+}
+
+#[cfg(not(target_family = "unix"))]
+fn copy_metadata(original: &Path, file: &File) -> io::Result<()> {
+    match fs::metadata(original) {
+        Ok(metadata) => file.set_permissions(metadata.permissions()),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Syncs the directory containing `path`, so that a file renamed into it
+/// is durable.
+#[cfg(target_family = "unix")]
+fn sync_parent_dir(path: &Path) {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Err(e) = File::open(dir).and_then(|dir| dir.sync_all()) {
+        warn!("failed to sync directory {:?}: {}", dir, e);
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+fn sync_parent_dir(_path: &Path) {}
+
+/// Returns `true` if this process has permission to write to `path`, which
+/// depends on the file's owner as well as its permissions.
+#[cfg(target_family = "unix")]
+fn can_write(path: &Path) -> bool {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    match CString::new(path.as_os_str().as_bytes()) {
+        Ok(path) => unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 },
+        Err(_) => true,
+    }
+}
+
+#[cfg(not(target_family = "unix"))]
+fn can_write(_path: &Path) -> bool {
+    true
+}
+
+/// A file system that only exists in memory, for tests.
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem {
+    files: Mutex<HashMap<PathBuf, (Vec<u8>, SystemTime)>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        MemoryFileSystem::default()
+    }
+
+    /// Creates or replaces the file at `path`.
+    pub fn insert<P: Into<PathBuf>, B: Into<Vec<u8>>>(&self, path: P, bytes: B) {
+        self.files.lock().unwrap().insert(path.into(), (bytes.into(), SystemTime::now()));
+    }
+
+    /// Returns the contents of the file at `path`, if it exists.
+    pub fn get(&self, path: &Path) -> Option<Vec<u8>> {
+        self.files.lock().unwrap().get(path).map(|(bytes, _)| bytes.clone())
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Box<dyn Read + Send>> {
+        match self.get(path) {
+            Some(bytes) => Ok(Box::new(Cursor::new(bytes))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(
+        &self,
+        path: &Path,
+        contents: &mut dyn FnMut(&mut dyn Write) -> io::Result<()>,
+    ) -> io::Result<()> {
+        let mut buf = Vec::new();
+        contents(&mut buf)?;
+        self.insert(path, buf);
+        Ok(())
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        match self.files.lock().unwrap().get(path) {
+            Some((bytes, mod_time)) => Ok(Metadata {
+                len: bytes.len() as u64,
+                mod_time: Some(*mod_time),
+                read_only: false,
+                permissions: None,
+            }),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("no such file: {:?}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write_str(fs: &dyn FileSystem, path: &Path, s: &str) -> io::Result<()> {
+        fs.write(path, &mut |w| w.write_all(s.as_bytes()))
+    }
+
+    #[test]
+    fn uri_schemes() {
+        assert_eq!(scheme(Path::new("zip://archive.zip/path")), Some("zip"));
+        assert_eq!(scheme(Path::new("/home/user/file.txt")), None);
+        assert_eq!(scheme(Path::new("C://file.txt")), None);
+        assert_eq!(scheme(Path::new("dir/not a scheme://x")), None);
+        assert_eq!(local_path(Path::new("file:///tmp/a")), Some(Path::new("/tmp/a")));
+        assert_eq!(local_path(Path::new("zip://archive.zip/path")), None);
+        assert_eq!(normalize(PathBuf::from("file:///tmp/a")), PathBuf::from("/tmp/a"));
+
+        let mut file_systems = FileSystems::default();
+        assert!(file_systems.get(Path::new("mem://a")).is_none());
+        assert!(file_systems.get(Path::new("zip://archive.zip/path")).is_some());
+        file_systems.register("mem", Arc::new(MemoryFileSystem::new()));
+        assert!(file_systems.get(Path::new("mem://a")).is_some());
+    }
+
+    #[test]
+    fn memory_file_system() {
+        let fs = MemoryFileSystem::new();
+        let path = Path::new("mem://a.txt");
+        assert_eq!(fs.metadata(path).unwrap_err().kind(), io::ErrorKind::NotFound);
+        write_str(&fs, path, "text").unwrap();
+        assert_eq!(fs.metadata(path).unwrap().len, 4);
+        let mut s = String::new();
+        fs.read(path).unwrap().read_to_string(&mut s).unwrap();
+        assert_eq!(s, "text");
+
+        let failed = fs.write(path, &mut |_| Err(io::Error::new(io::ErrorKind::InvalidData, "no")));
+        assert!(failed.is_err());
+        assert_eq!(fs.get(path).unwrap(), b"text");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn local_write_through_symlink() {
+        use std::os::unix::fs::symlink;
+        let tmp = TempDir::new("xi-test-symlink").unwrap();
+        let target = tmp.path().join("target.txt");
+        let link = tmp.path().join("link.txt");
+        fs::write(&target, "old\n").unwrap();
+        fs::set_permissions(&target, Permissions::from_mode(0o640)).unwrap();
+        symlink("target.txt", &link).unwrap();
+
+        write_str(&LocalFileSystem, &link, "new\n").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new\n");
+        assert_eq!(fs::metadata(&target).unwrap().permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 2);
+
+        // a dangling link creates the file it points to
+        let dangling = tmp.path().join("dangling.txt");
+        symlink("created.txt", &dangling).unwrap();
+        write_str(&LocalFileSystem, &dangling, "new\n").unwrap();
+        assert!(fs::symlink_metadata(&dangling).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(tmp.path().join("created.txt")).unwrap(), "new\n");
+    }
+
+    #[test]
+    fn local_write_failed_rename() {
+        let tmp = TempDir::new("xi-test-failed-rename").unwrap();
+        // a file can't be renamed over a directory
+        let path = tmp.path().join("dir.txt");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("file.txt"), "text").unwrap();
+        assert!(write_str(&LocalFileSystem, &path, "new\n").is_err());
+        assert!(path.is_dir());
+        assert!(!tmp.path().join("dir.txt.swp").exists());
+    }
+
+    #[test]
+    fn json_write() {
+        let tmp = TempDir::new("xi-test-json").unwrap();
+        let path = tmp.path().join("file.json");
+        write_json(&path, &vec![1, 2]).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[1,2]");
+
+        let path = tmp.path().join("dir.json");
+        fs::create_dir(&path).unwrap();
+        fs::write(path.join("file.txt"), "text").unwrap();
+        assert!(write_json(&path, &vec![1, 2]).is_err());
+        assert!(!tmp.path().join("dir.tmp").exists());
+        assert_eq!(fs::read_dir(tmp.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn local_write_in_place() {
+        let tmp = TempDir::new("xi-test-in-place").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "a longer original text\n").unwrap();
+        write_in_place(&path, &mut |w| w.write_all(b"short\n")).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "short\n");
+
+        // nothing is written if the contents can't be produced
+        let failed = write_in_place(&path, &mut |w| {
+            w.write_all(b"partial")?;
+            Err(io::Error::new(io::ErrorKind::InvalidData, "unencodable"))
+        });
+        assert!(failed.is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "short\n");
+    }
+
+    #[cfg(target_family = "unix")]
+    #[test]
+    fn local_read_only() {
+        let tmp = TempDir::new("xi-test-read-only").unwrap();
+        let path = tmp.path().join("file.txt");
+        fs::write(&path, "text").unwrap();
+        assert!(!LocalFileSystem.metadata(&path).unwrap().read_only);
+        fs::set_permissions(&path, Permissions::from_mode(0o444)).unwrap();
+        assert!(LocalFileSystem.metadata(&path).unwrap().read_only);
+        let uri = PathBuf::from(format!("file://{}", path.display()));
+        assert!(LocalFileSystem.metadata(&uri).unwrap().read_only);
+    }
+}
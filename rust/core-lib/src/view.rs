@@ -15,20 +15,27 @@
 
 use std::cell::RefCell;
 use std::cmp::{max, min};
+use std::collections::BTreeMap;
 use std::iter;
 use std::ops::Range;
 
 use serde_json::Value;
 
 use crate::annotations::{AnnotationStore, ToAnnotation};
+use crate::brackets::{self, BracketMatches};
 use crate::client::{Client, Update, UpdateOp};
+use crate::column_selection::{ColumnBlock, ColumnMeasure};
 use crate::edit_types::ViewEvent;
+use crate::editor::UndoSelection;
 use crate::find::{Find, FindStatus};
+use crate::layers::Layers;
 use crate::line_cache_shadow::{self, LineCacheShadow, RenderPlan, RenderTactic};
 use crate::linewrap::{InvalLines, Lines, VisualLine, WrapWidth};
+use crate::merge::Conflicts;
 use crate::movement::{region_movement, selection_movement, Movement};
 use crate::rpc::{FindQuery, GestureType, MouseAction, SelectionGranularity, SelectionModifier};
 use crate::selection::{Affinity, InsertDrift, SelRegion, Selection};
+use crate::selection_history::SelectionHistory;
 use crate::styles::{Style, ThemeStyleMap};
 use crate::tabs::{BufferId, Counter, ViewId};
 use crate::width_cache::WidthCache;
@@ -59,6 +66,9 @@ pub struct View {
 
     drag_state: Option<DragState>,
 
+    /// The column selection being made, if any.
+    column_state: Option<ColumnState>,
+
     /// vertical scroll position
     first_line: usize,
     /// height of visible portion
@@ -98,6 +108,16 @@ pub struct View {
 
     /// Annotations provided by plugins.
     annotations: AnnotationStore,
+
+    /// The selections before and after the edits of each live undo group,
+    /// restored by undo and redo.
+    undo_selections: BTreeMap<usize, (Selection, Selection)>,
+
+    /// Earlier selections, for `undo_selection` and `redo_selection`.
+    selection_history: SelectionHistory,
+
+    /// The brackets matching those next to the visible carets.
+    bracket_matches: BracketMatches,
 }
 
 /// Indicates what changed in the find state.
@@ -156,6 +176,18 @@ struct DragState {
     granularity: SelectionGranularity,
 }
 
+/// State required to extend a column selection.
+struct ColumnState {
+    /// The selection regions other than those of the block.
+    base_sel: Selection,
+
+    block: ColumnBlock,
+
+    /// The selection made from the block. If the selection is changed in
+    /// another way, the block can no longer be extended.
+    selection: Selection,
+}
+
 impl View {
     pub fn new(view_id: ViewId, buffer_id: BufferId) -> View {
         View {
@@ -166,6 +198,7 @@ impl View {
             scroll_to: Some(0),
             size: Size::default(),
             drag_state: None,
+            column_state: None,
             first_line: 0,
             height: 10,
             lines: Lines::default(),
@@ -178,6 +211,9 @@ impl View {
             replace: None,
             replace_changed: false,
             annotations: AnnotationStore::new(),
+            undo_selections: BTreeMap::new(),
+            selection_history: SelectionHistory::default(),
+            bracket_matches: BracketMatches::default(),
         }
     }
 
@@ -233,6 +269,8 @@ impl View {
 
     pub(crate) fn do_edit(&mut self, text: &Rope, cmd: ViewEvent) {
         use self::ViewEvent::*;
+        let before = self.selection.clone();
+        let is_jump = cmd.is_jump();
         match cmd {
             Move(movement) => self.do_move(text, movement, false),
             ModifySelection(movement) => self.do_move(text, movement, true),
@@ -288,6 +326,39 @@ impl View {
             Replace { chars, preserve_case } => self.do_set_replace(chars, preserve_case),
             SelectionForReplace => self.do_selection_for_replace(text),
             SelectionIntoLines => self.do_split_selection_into_lines(text),
+            UndoSelection => {
+                self.undo_selection(text);
+                return;
+            }
+            RedoSelection => {
+                self.redo_selection(text);
+                return;
+            }
+            ColumnSelect(_) => warn!("column selections are made with do_column_edit"),
+            MoveToMatchingBracket | SelectToMatchingBracket => {
+                warn!("bracket movements are made with do_bracket_edit")
+            }
+        }
+        self.record_selection_change(before, is_jump);
+    }
+
+    /// Adds `before` to the selection history, if the selection has changed
+    /// since. A jump always gets an entry of its own.
+    pub(crate) fn record_selection_change(&mut self, before: Selection, is_jump: bool) {
+        if *before != *self.selection {
+            self.selection_history.record(before, is_jump);
+        }
+    }
+
+    fn undo_selection(&mut self, text: &Rope) {
+        if let Some(sel) = self.selection_history.undo(self.selection.clone()) {
+            self.set_selection(text, sel);
+        }
+    }
+
+    fn redo_selection(&mut self, text: &Rope) {
+        if let Some(sel) = self.selection_history.redo(self.selection.clone()) {
+            self.set_selection(text, sel);
         }
     }
 
@@ -330,6 +401,28 @@ impl View {
         self.height
     }
 
+    /// Returns the first visible line, and the line after the last.
+    pub(crate) fn scroll_range(&self) -> (usize, usize) {
+        (self.first_line, self.first_line + self.height)
+    }
+
+    /// Restores a selection and scroll position saved in a session.
+    pub(crate) fn restore_position(
+        &mut self,
+        text: &Rope,
+        sel: Selection,
+        first: usize,
+        last: usize,
+    ) {
+        self.set_selection_raw(text, sel);
+        self.set_scroll(first as i64, last as i64);
+        // The client is only told which line to show; asking for the last
+        // visible line puts the first one at the top of a freshly opened view.
+        let last_line = self.line_of_offset(text, text.len());
+        let line = last.saturating_sub(1).min(last_line);
+        self.scroll_to = Some(self.offset_of_line(text, line));
+    }
+
     fn scroll_to_cursor(&mut self, text: &Rope) {
         let end = self.sel_regions().last().unwrap().end;
         let line = self.line_of_offset(text, end);
@@ -437,7 +530,9 @@ impl View {
     /// Finds the unit of text containing the given offset.
     fn unit(&self, text: &Rope, offset: usize, granularity: SelectionGranularity) -> Interval {
         match granularity {
-            SelectionGranularity::Point => Interval::new(offset, offset),
+            SelectionGranularity::Point | SelectionGranularity::Column => {
+                Interval::new(offset, offset)
+            }
             SelectionGranularity::Word => {
                 let mut word_cursor = WordCursor::new(text, offset);
                 let (start, end) = word_cursor.select_word();
@@ -469,6 +564,7 @@ impl View {
 
         let region = self.unit(text, offset, granularity).into();
 
+        self.column_state = None;
         let base_sel = match multi {
             true => self.selection.clone(),
             false => Selection::new(),
@@ -502,6 +598,7 @@ impl View {
         };
 
         let mut sel = base_sel.clone();
+        self.column_state = None;
         self.drag_state =
             Some(DragState { base_sel, min: last.start, max: last.start, granularity });
 
@@ -515,6 +612,184 @@ impl View {
         self.set_selection(text, sel);
     }
 
+    /// Returns `true` if `cmd` makes or extends a column selection, and should
+    /// be handled by `do_column_edit`.
+    pub(crate) fn is_column_event(&self, cmd: &ViewEvent) -> bool {
+        use self::GestureType::*;
+        match cmd {
+            ViewEvent::ColumnSelect(_) => true,
+            ViewEvent::Gesture { ty: Select { granularity, .. }, .. }
+            | ViewEvent::Gesture { ty: SelectExtend { granularity }, .. } => {
+                *granularity == SelectionGranularity::Column
+            }
+            ViewEvent::Gesture { ty: Drag, .. } => self.column_state_is_current(),
+            _ => false,
+        }
+    }
+
+    fn column_state_is_current(&self) -> bool {
+        match self.column_state {
+            Some(ref state) => *state.selection == *self.selection,
+            None => false,
+        }
+    }
+
+    /// Makes or extends a column selection. `measure` gives the positions
+    /// of text in the lines.
+    pub(crate) fn do_column_edit(
+        &mut self,
+        text: &Rope,
+        cmd: ViewEvent,
+        measure: &mut ColumnMeasure,
+    ) {
+        let before = self.selection.clone();
+        let is_jump = cmd.is_jump();
+        let current = match self.column_state_is_current() {
+            true => self.column_state.take().map(|state| (state.base_sel, state.block)),
+            false => None,
+        };
+        // a selection that wasn't made as a block is extended from the
+        // corners of its last region
+        let current = current.unwrap_or_else(|| {
+            let (last, rest) = self.selection.split_last().unwrap();
+            let mut base_sel = Selection::new();
+            rest.iter().for_each(|&region| base_sel.add_region(region));
+            let anchor = self.column_position(text, measure, last.start);
+            let active = self.column_position(text, measure, last.end);
+            (base_sel, ColumnBlock { anchor, active })
+        });
+
+        let (base_sel, block) = match cmd {
+            ViewEvent::Gesture { line, col, ty: GestureType::Select { multi, .. } } => {
+                let point = self.column_point(text, measure, line as usize, col as usize);
+                let base_sel = if multi { before.clone() } else { Selection::new() };
+                (base_sel, ColumnBlock { anchor: point, active: point })
+            }
+            ViewEvent::Gesture { line, col, .. } => {
+                let (base_sel, block) = current;
+                let active = self.column_point(text, measure, line as usize, col as usize);
+                (base_sel, ColumnBlock { active, ..block })
+            }
+            ViewEvent::ColumnSelect(movement) => {
+                let (base_sel, block) = current;
+                let (line, x) = block.active;
+                let last_line = self.line_of_offset(text, text.len());
+                let active = match movement {
+                    Movement::Up => (line.saturating_sub(1), x),
+                    Movement::Down => (min(line + 1, last_line), x),
+                    Movement::Left | Movement::Right => {
+                        let (start, end) = self.line_range(text, line);
+                        let positions = measure.line_positions(text, start, end);
+                        match movement {
+                            Movement::Left => (line, positions.prev_x(x)),
+                            _ => (line, positions.next_x(x)),
+                        }
+                    }
+                    _ => {
+                        warn!("{:?} can't extend a column selection", movement);
+                        return;
+                    }
+                };
+                (base_sel, ColumnBlock { active, ..block })
+            }
+            cmd => {
+                warn!("{:?} is not a column selection event", cmd);
+                return;
+            }
+        };
+
+        let mut selection = base_sel.clone();
+        let regions = block.regions(text, measure, |line| self.line_range(text, line));
+        regions.iter().for_each(|&region| selection.add_region(region));
+        self.set_selection(text, selection.clone());
+        self.drag_state = None;
+        self.column_state = Some(ColumnState { base_sel, block, selection });
+        self.record_selection_change(before, is_jump);
+    }
+
+    /// Moves each caret to the bracket matching the one next to it, keeping
+    /// it on the same side of the bracket, or selects both brackets and the
+    /// text between them. `layers` give the scopes of strings and comments.
+    pub(crate) fn do_bracket_edit(&mut self, text: &Rope, layers: &Layers, cmd: ViewEvent) {
+        let before = self.selection.clone();
+        let select = match cmd {
+            ViewEvent::MoveToMatchingBracket => false,
+            ViewEvent::SelectToMatchingBracket => true,
+            cmd => {
+                warn!("{:?} is not a bracket movement", cmd);
+                return;
+            }
+        };
+        let mut selection = Selection::new();
+        for &region in self.sel_regions() {
+            let region = match brackets::match_at_caret(text, layers, region.end) {
+                Some(m) if select => {
+                    let outer = m.outer();
+                    match m.matching < m.bracket {
+                        true => SelRegion::new(outer.end(), outer.start()),
+                        false => SelRegion::new(outer.start(), outer.end()),
+                    }
+                }
+                Some(m) if m.bracket == region.end => SelRegion::caret(m.matching),
+                Some(m) => SelRegion::caret(m.matching + 1),
+                None => region,
+            };
+            selection.add_region(region);
+        }
+        self.set_selection(text, selection);
+        self.record_selection_change(before, true);
+    }
+
+    /// Finds the brackets matching those next to the visible carets, which
+    /// are highlighted.
+    pub(crate) fn update_bracket_matches(&mut self, text: &Rope, layers: &Layers) {
+        let visible_range = self.visible_range(text);
+        let carets = self
+            .selection
+            .regions_in_range(visible_range.start(), visible_range.end())
+            .iter()
+            .filter(|region| region.is_caret())
+            .map(|region| region.end);
+        self.bracket_matches = BracketMatches::find(text, layers, carets);
+    }
+
+    /// Returns the start and end of the visual line `line`, without its newline.
+    fn line_range(&self, text: &Rope, line: usize) -> (usize, usize) {
+        let start = self.offset_of_line(text, line);
+        let end = self.offset_of_line(text, line + 1);
+        if end > start && text.byte_at(end - 1) == b'\n' {
+            (start, end - 1)
+        } else {
+            (start, end)
+        }
+    }
+
+    /// Returns the line and horizontal position of `offset`.
+    fn column_position(
+        &self,
+        text: &Rope,
+        measure: &mut ColumnMeasure,
+        offset: usize,
+    ) -> (usize, f64) {
+        let line = self.line_of_offset(text, offset);
+        let (start, end) = self.line_range(text, line);
+        (line, measure.line_positions(text, start, end).x_of_offset(offset))
+    }
+
+    /// Returns the line and horizontal position of a gesture at `line` and
+    /// `col`, which may be past the end of the line.
+    fn column_point(
+        &self,
+        text: &Rope,
+        measure: &mut ColumnMeasure,
+        line: usize,
+        col: usize,
+    ) -> (usize, f64) {
+        let line = min(line, self.line_of_offset(text, text.len()));
+        let (start, end) = self.line_range(text, line);
+        (line, measure.line_positions(text, start, end).x_of_col(col))
+    }
+
     /// Splits current selections into lines.
     fn do_split_selection_into_lines(&mut self, text: &Rope) {
         let mut selection = Selection::new();
@@ -581,6 +856,10 @@ impl View {
         &self.selection
     }
 
+    pub(crate) fn get_selection(&self) -> &Selection {
+        &self.selection
+    }
+
     /// Collapse all selections in this view into a single caret
     pub fn collapse_selections(&mut self, text: &Rope) {
         let mut sel = self.selection.clone();
@@ -720,6 +999,7 @@ impl View {
         client: &Client,
         styles: &StyleMap,
         style_spans: &Spans<Style>,
+        conflicts: &Conflicts,
         plan: &RenderPlan,
         pristine: bool,
     ) {
@@ -812,17 +1092,23 @@ impl View {
             find.set_hls_dirty(false)
         }
 
-        let start_off = self.offset_of_line(text, self.first_line);
-        let end_off = self.offset_of_line(text, self.first_line + self.height + 2);
-        let visible_range = Interval::new(start_off, end_off);
+        let visible_range = self.visible_range(text);
         let selection_annotations =
             self.selection.get_annotations(visible_range, &self, text).to_json();
         let find_annotations =
             self.find.iter().map(|ref f| f.get_annotations(visible_range, &self, text).to_json());
         let plugin_annotations = self.annotations.iter_range(visible_range).map(|a| a.to_json());
+        let conflict_annotations = Some(conflicts)
+            .filter(|c| !c.is_empty())
+            .map(|c| c.get_annotations(visible_range, self, text).to_json());
+        let bracket_annotations = Some(&self.bracket_matches)
+            .filter(|m| !m.is_empty())
+            .map(|m| m.get_annotations(visible_range, self, text).to_json());
 
         let annotations = iter::once(selection_annotations)
             .chain(find_annotations)
+            .chain(conflict_annotations)
+            .chain(bracket_annotations)
             .chain(plugin_annotations)
             .collect::<Vec<_>>();
 
@@ -830,6 +1116,13 @@ impl View {
         client.update_view(self.view_id, &update);
     }
 
+    /// Returns the range of the text in the visible lines, and a couple more.
+    fn visible_range(&self, text: &Rope) -> Interval {
+        let start = self.offset_of_line(text, self.first_line);
+        let end = self.offset_of_line(text, self.first_line + self.height + 2);
+        Interval::new(start, end)
+    }
+
     /// Determines the current number of find results and search parameters to send them to
     /// the frontend.
     pub fn find_status(&self, text: &Rope, matches_only: bool) -> Vec<FindStatus> {
@@ -848,11 +1141,12 @@ impl View {
         client: &Client,
         styles: &StyleMap,
         style_spans: &Spans<Style>,
+        conflicts: &Conflicts,
         pristine: bool,
     ) {
         let height = self.line_of_offset(text, text.len()) + 1;
         let plan = RenderPlan::create(height, self.first_line, self.height);
-        self.send_update_for_plan(text, client, styles, style_spans, &plan, pristine);
+        self.send_update_for_plan(text, client, styles, style_spans, conflicts, &plan, pristine);
         if let Some(new_scroll_pos) = self.scroll_to.take() {
             let (line, col) = self.offset_to_line_col(text, new_scroll_pos);
             client.scroll_to(self.view_id, line, col);
@@ -866,6 +1160,7 @@ impl View {
         client: &Client,
         styles: &StyleMap,
         style_spans: &Spans<Style>,
+        conflicts: &Conflicts,
         first_line: usize,
         last_line: usize,
         pristine: bool,
@@ -873,7 +1168,7 @@ impl View {
         let height = self.line_of_offset(text, text.len()) + 1;
         let mut plan = RenderPlan::create(height, self.first_line, self.height);
         plan.request_lines(first_line, last_line);
-        self.send_update_for_plan(text, client, styles, style_spans, &plan, pristine);
+        self.send_update_for_plan(text, client, styles, style_spans, conflicts, &plan, pristine);
     }
 
     /// Invalidates front-end's entire line cache, forcing a full render at the next
@@ -963,6 +1258,9 @@ impl View {
     /// Updates the view after the text has been modified by the given `delta`.
     /// This method is responsible for updating the cursors, and also for
     /// recomputing line wraps.
+    ///
+    /// `is_active` is `true` for the view the edit was made in. Other views
+    /// of the same buffer keep their scroll position and any drag in progress.
     pub fn after_edit(
         &mut self,
         text: &Rope,
@@ -971,6 +1269,7 @@ impl View {
         client: &Client,
         width_cache: &mut WidthCache,
         drift: InsertDrift,
+        is_active: bool,
     ) {
         let visible = self.first_line..self.first_line + self.height;
         match self.lines.after_edit(text, last_text, delta, width_cache, client, visible) {
@@ -982,7 +1281,9 @@ impl View {
 
         // Any edit cancels a drag. This is good behavior for edits initiated through
         // the front-end, but perhaps not for async edits.
-        self.drag_state = None;
+        if is_active {
+            self.drag_state = None;
+        }
 
         // update only find highlights affected by change
         for find in &mut self.find {
@@ -992,8 +1293,56 @@ impl View {
 
         // Note: for committing plugin edits, we probably want to know the priority
         // of the delta so we can set the cursor before or after the edit, as needed.
+        self.selection_history.apply_delta(delta);
         let new_sel = self.selection.apply_delta(delta, true, drift);
-        self.set_selection_for_edit(text, new_sel);
+        if is_active {
+            self.set_selection_for_edit(text, new_sel);
+        } else {
+            self.selection = new_sel;
+        }
+    }
+
+    /// Records the selection after an edit in `undo_group`, where `before`
+    /// was the selection before it. Groups older than `oldest_group` can no
+    /// longer be undone, and are forgotten.
+    pub(crate) fn record_undo_selection(
+        &mut self,
+        undo_group: usize,
+        before: Selection,
+        oldest_group: usize,
+    ) {
+        let after = self.selection.clone();
+        self.undo_selections.entry(undo_group).or_insert_with(|| (before, Selection::new())).1 =
+            after;
+        self.undo_selections = self.undo_selections.split_off(&oldest_group);
+    }
+
+    /// Restores the selection recorded for an undo group, if there is one.
+    pub(crate) fn restore_undo_selection(
+        &mut self,
+        text: &Rope,
+        which: UndoSelection,
+        is_active: bool,
+    ) {
+        let recorded = match which {
+            UndoSelection::Before(group) => self.undo_selections.get(&group).map(|s| &s.0),
+            UndoSelection::After(group) => self.undo_selections.get(&group).map(|s| &s.1),
+        };
+        let recorded = match recorded {
+            Some(recorded) if !recorded.is_empty() => recorded,
+            _ => return,
+        };
+        // other edits may have happened since, so keep the regions in bounds
+        let clamp =
+            |offset: usize| text.at_or_prev_codepoint_boundary(offset.min(text.len())).unwrap_or(0);
+        let mut sel = Selection::new();
+        for region in recorded.iter() {
+            sel.add_region(SelRegion::new(clamp(region.start), clamp(region.end)));
+        }
+        self.set_selection_raw(text, sel);
+        if is_active {
+            self.scroll_to_cursor(text);
+        }
     }
 
     fn do_selection_for_find(&mut self, text: &Rope, case_sensitive: bool) {
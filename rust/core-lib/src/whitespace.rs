@@ -64,6 +64,16 @@ impl Indentation {
         }
     }
 
+    /// Parses the whole lines within the first `max_bytes` of a rope for
+    /// indentation settings. Used where scanning the whole rope is too slow.
+    pub fn parse_head(rope: &Rope, max_bytes: usize) -> Result<Option<Self>, MixedIndentError> {
+        if rope.len() <= max_bytes {
+            return Indentation::parse(rope);
+        }
+        let end = rope.offset_of_line(rope.line_of_offset(max_bytes));
+        Indentation::parse(&rope.slice(..end))
+    }
+
     /// Detects the indentation on a specific line.
     /// Parses whitespace until first occurrence of something else
     pub fn parse_line(line: &str) -> Result<Option<Self>, MixedIndentError> {
@@ -204,4 +214,15 @@ But the majority is still 0.
 
         assert_eq!(result.unwrap(), None);
     }
+
+    #[test]
+    fn rope_head_ignores_tail() {
+        let rope = Rope::from("fn main() {\n    one();\n    two();\n}\n\tmixed\n");
+        assert!(Indentation::parse(&rope).is_err());
+        let result = Indentation::parse_head(&rope, 30);
+        assert_eq!(result.unwrap(), Some(Indentation::Spaces(4)));
+        // a partial line at the end of the sample is not included
+        let result = Indentation::parse_head(&rope, 20);
+        assert_eq!(result.unwrap(), None);
+    }
 }
@@ -15,16 +15,33 @@
 #[macro_use]
 extern crate serde_json;
 
+extern crate tempdir;
 extern crate xi_core_lib;
 extern crate xi_rpc;
 
-use std::io;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
 
+use serde_json::Value;
+
+use xi_core_lib::archive::ZipFileSystem;
 use xi_core_lib::test_helpers;
+use xi_core_lib::vfs::{FileSystem, MemoryFileSystem};
 use xi_core_lib::XiCore;
-use xi_rpc::test_utils::{make_reader, test_channel};
+use xi_rpc::test_utils::{make_reader, test_channel, DummyReader};
 use xi_rpc::{ReadError, RpcLoop};
 
+/// Returns the messages sent to the client so far.
+fn received(rx: &mut DummyReader) -> Vec<Value> {
+    let mut messages = Vec::new();
+    while let Some(message) = rx.next_timeout(Duration::from_millis(100)) {
+        messages.push(message.unwrap().0);
+    }
+    messages
+}
+
 #[test]
 /// Tests that the handler responds to a standard startup sequence as expected.
 fn test_startup() {
@@ -92,6 +109,495 @@ fn test_state() {
     }
 }
 
+#[test]
+/// Tests that edits in one view of a buffer are shown in its other views.
+fn test_clone_view() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(
+        r#"{"method":"client_started","params":{}}
+{"method":"set_theme","params":{"theme_name":"InspiredGitHub"}}
+{"id":0,"method":"new_view","params":{}}"#,
+    );
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    rx.expect_rpc("available_languages");
+    rx.expect_rpc("available_themes");
+    rx.expect_rpc("theme_changed");
+    assert_eq!(rx.expect_response(), Ok(json!("view-id-1")));
+    rx.expect_rpc("available_plugins");
+    rx.expect_rpc("config_changed");
+    rx.expect_rpc("language_changed");
+    rx.expect_rpc("update");
+    rx.expect_rpc("scroll_to");
+
+    let json = make_reader(r#"{"id":1,"method":"clone_view","params":{"view_id":"view-id-1"}}"#);
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    assert_eq!(rx.expect_response(), Ok(json!("view-id-3")));
+    rx.expect_rpc("available_plugins");
+    rx.expect_rpc("config_changed");
+    rx.expect_rpc("language_changed");
+    assert_eq!(rx.expect_rpc("update").0["params"]["view_id"], json!("view-id-3"));
+    rx.expect_rpc("scroll_to");
+    {
+        let state = state.inner();
+        assert_eq!(state._test_open_editors(), vec![test_helpers::new_buffer_id(2)]);
+    }
+
+    let json = make_reader(
+        r#"{"method":"edit","params":{"view_id":"view-id-3","method":"insert","params":{"chars":"a"}}}
+{"id":2,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#,
+    );
+    assert!(rpc_looper.mainloop(|| json, &mut state).is_ok());
+    assert_eq!(rx.expect_rpc("update").0["params"]["view_id"], json!("view-id-3"));
+    assert_eq!(rx.expect_rpc("scroll_to").0["params"]["view_id"], json!("view-id-3"));
+    assert_eq!(rx.expect_rpc("update").0["params"]["view_id"], json!("view-id-1"));
+    assert_eq!(rx.expect_response(), Ok(json!("a")));
+
+    let json = make_reader(r#"{"method":"close_view","params":{"view_id":"view-id-1"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    {
+        let state = state.inner();
+        assert_eq!(state._test_open_views(), vec![test_helpers::new_view_id(3)]);
+        assert_eq!(state._test_open_editors(), vec![test_helpers::new_buffer_id(2)]);
+    }
+
+    let json = make_reader(r#"{"method":"close_view","params":{"view_id":"view-id-3"}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    assert_eq!(state.inner()._test_open_editors(), Vec::new());
+}
+
+#[test]
+/// Tests that opening a file that is already open adds a view of its buffer.
+fn test_open_file_twice() {
+    let tmp = tempdir::TempDir::new("xi-test-open-twice").unwrap();
+    let path = tmp.path().join("file.txt");
+    std::fs::write(&path, "text").unwrap();
+    let new_view = json!({"id": 0, "method": "new_view", "params": {"file_path": path}});
+
+    let mut state = XiCore::new();
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    let json = make_reader(format!(
+        "{}\n{}\n{}",
+        r#"{"method":"client_started","params":{}}"#, new_view, new_view
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let state = state.inner();
+    assert_eq!(state._test_open_views().len(), 2);
+    assert_eq!(state._test_open_editors().len(), 1);
+}
+
+#[test]
+/// Tests that opening a file that is still loading in the background adds
+/// a view of its buffer, which is set up once it has loaded.
+fn test_open_loading_file_twice() {
+    let tmp = tempdir::TempDir::new("xi-test-open-loading-twice").unwrap();
+    let path = tmp.path().join("large.txt");
+    // large enough to be loaded in the background
+    let line = format!("{}\n", "a".repeat(1023));
+    std::fs::write(&path, line.repeat(8 * 1024)).unwrap();
+    let new_view = json!({"id": 0, "method": "new_view", "params": {"file_path": path}});
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(format!(
+        "{}\n{}\n{}",
+        r#"{"method":"client_started","params":{}}"#, new_view, new_view
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    {
+        let state = state.inner();
+        assert_eq!(state._test_open_views().len(), 2);
+        assert_eq!(state._test_open_editors().len(), 1);
+    }
+
+    // the views are set up from the idle handler once loading is done
+    let mut updated = Vec::new();
+    for _ in 0..100 {
+        rpc_looper.mainloop(|| make_reader(""), &mut state).unwrap();
+        let messages = received(&mut rx);
+        let updates = messages.iter().filter(|m| m["method"] == json!("update"));
+        updated.extend(updates.map(|m| m["params"]["view_id"].clone()));
+        if updated.len() >= 2 {
+            break;
+        }
+    }
+    updated.sort_by_key(|id| id.to_string());
+    assert_eq!(updated, vec![json!("view-id-1"), json!("view-id-3")]);
+}
+
+#[test]
+fn test_buffer_commands() {
+    let tmp = tempdir::TempDir::new("xi-test-buffer-commands").unwrap();
+    let saved = tmp.path().join("saved.txt");
+    let reverted = tmp.path().join("reverted.txt");
+    std::fs::write(&saved, "text\n").unwrap();
+    std::fs::write(&reverted, "text\n").unwrap();
+    let new_view =
+        |id, path| json!({"id": id, "method": "new_view", "params": {"file_path": path}});
+    let insert = |view_id| {
+        json!({"method": "edit", "params": {"view_id": view_id, "method": "insert",
+            "params": {"chars": "new "}}})
+    };
+
+    let edit = |view_id, method| json!({"method": "edit", "params": {"view_id": view_id, "method": method, "params": []}});
+    let get_contents =
+        |id| json!({"id": id, "method": "debug_get_contents", "params": {"view_id": "view-id-3"}});
+    let response = |messages: &[Value], id| {
+        messages.iter().find(|m| m["id"] == json!(id)).unwrap()["result"].clone()
+    };
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(format!(
+        "{}\n{}\n{}",
+        r#"{"method":"client_started","params":{}}"#,
+        new_view(0, &saved),
+        new_view(1, &reverted)
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    received(&mut rx);
+
+    let json = make_reader(format!(
+        "{}\n{}",
+        insert("view-id-1"),
+        r#"{"id":2,"method":"close_all","params":{}}"#
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    // unsaved changes keep close_all from closing anything
+    assert_eq!(state.inner()._test_open_views().len(), 2);
+    let blocking = response(&received(&mut rx), 2);
+    assert_eq!(blocking, json!([{"view_ids": ["view-id-1"], "file_path": saved}]));
+
+    let json = make_reader(format!(
+        "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
+        r#"{"method":"save_all","params":{}}"#,
+        insert("view-id-3"),
+        r#"{"method":"revert_buffer","params":{"view_id":"view-id-3"}}"#,
+        get_contents(3),
+        edit("view-id-3", "undo"),
+        get_contents(4),
+        edit("view-id-3", "redo"),
+        r#"{"id":5,"method":"close_all","params":{}}"#
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let messages = received(&mut rx);
+    assert_eq!(response(&messages, 3), json!("text\n"));
+    // reverting can be undone
+    assert_eq!(response(&messages, 4), json!("new text\n"));
+    assert_eq!(response(&messages, 5), json!([]));
+    assert_eq!(state.inner()._test_open_views().len(), 0);
+    assert_eq!(std::fs::read_to_string(&saved).unwrap(), "new text\n");
+    assert_eq!(std::fs::read_to_string(&reverted).unwrap(), "text\n");
+}
+
+#[test]
+/// Tests that reverting a buffer keeps the encoding chosen for its file.
+fn test_revert_keeps_encoding() {
+    let tmp = tempdir::TempDir::new("xi-test-revert-encoding").unwrap();
+    let path = tmp.path().join("file.txt");
+    // guessed to be Windows-1252, in which 0x80 is a euro sign
+    std::fs::write(&path, b"price: 5\x80\n").unwrap();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(format!(
+        "{}\n{}",
+        r#"{"method":"client_started","params":{}}"#,
+        json!({"id": 0, "method": "new_view", "params": {"file_path": path}})
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    received(&mut rx);
+
+    let json = make_reader(
+        r#"{"method":"set_encoding","params":{"view_id":"view-id-1","encoding":"iso-8859-1","reopen":true}}
+{"method":"revert_buffer","params":{"view_id":"view-id-1"}}
+{"id":1,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#,
+    );
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let messages = received(&mut rx);
+    let contents = messages.iter().find(|m| m["id"] == json!(1)).unwrap();
+    assert_eq!(contents["result"], json!("price: 5\u{80}\n"));
+}
+
+#[test]
+/// Tests that a file changed on disk is reloaded with the encoding chosen
+/// for it.
+fn test_reload_keeps_encoding() {
+    let tmp = tempdir::TempDir::new("xi-test-reload-encoding").unwrap();
+    let path = tmp.path().join("file.txt");
+    // guessed to be Windows-1252, in which 0x80 is a euro sign
+    std::fs::write(&path, b"price: 5\x80\n").unwrap();
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(format!(
+        "{}\n{}",
+        r#"{"method":"client_started","params":{}}"#,
+        json!({"id": 0, "method": "new_view", "params": {"file_path": path}})
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let json = make_reader(
+        r#"{"method":"set_encoding","params":{"view_id":"view-id-1","encoding":"iso-8859-1","reopen":true}}"#,
+    );
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    received(&mut rx);
+
+    std::fs::write(&path, b"price: 6\x80\n").unwrap();
+    // the change is noticed once the watcher reports it
+    let mut contents = Value::Null;
+    for _ in 0..50 {
+        rpc_looper.mainloop(|| make_reader(""), &mut state).unwrap();
+        let json =
+            make_reader(r#"{"id":1,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#);
+        rpc_looper.mainloop(|| json, &mut state).unwrap();
+        let messages = received(&mut rx);
+        contents = messages.iter().find(|m| m["id"] == json!(1)).unwrap()["result"].clone();
+        if contents != json!("price: 5\u{80}\n") {
+            break;
+        }
+    }
+    assert_eq!(contents, json!("price: 6\u{80}\n"));
+}
+
+#[test]
+fn test_save_without_backup() {
+    let tmp = tempdir::TempDir::new("xi-test-save-without-backup").unwrap();
+    let config_dir = tmp.path().join("config");
+    std::fs::create_dir(&config_dir).unwrap();
+    std::fs::write(config_dir.join("preferences.xiconfig"), "backup_on_save = \"beside\"\n")
+        .unwrap();
+    let path = tmp.path().join("file.txt");
+    std::fs::write(&path, "text\n").unwrap();
+    // the backup can't be written over a directory
+    std::fs::create_dir(tmp.path().join("file.txt~")).unwrap();
+
+    let mut state = XiCore::new();
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    let json = make_reader(format!(
+        "{}\n{}",
+        json!({"method": "client_started", "params": {"config_dir": config_dir}}),
+        json!({"id": 0, "method": "new_view", "params": {"file_path": path}})
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let json = make_reader(format!(
+        "{}\n{}",
+        r#"{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"new "}}}"#,
+        json!({"method": "save", "params": {"view_id": "view-id-1", "file_path": path}})
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "new text\n");
+}
+
+#[test]
+fn test_file_systems() {
+    let tmp = tempdir::TempDir::new("xi-test-file-systems").unwrap();
+    let archive = format!("zip://{}", tmp.path().join("archive.zip").display());
+    let entry = format!("{}/a.txt", archive);
+    let read = |fs: &dyn FileSystem, path: &str| {
+        let mut s = String::new();
+        fs.read(Path::new(path)).unwrap().read_to_string(&mut s).unwrap();
+        s
+    };
+    let zip_fs = ZipFileSystem;
+    for (name, text) in &[("a.txt", "first\n"), ("b.txt", "second\n")] {
+        let path = format!("{}/{}", archive, name);
+        zip_fs.write(Path::new(&path), &mut |w| w.write_all(text.as_bytes())).unwrap();
+    }
+    let mem_fs = Arc::new(MemoryFileSystem::new());
+    mem_fs.insert("mem://c.txt", "third\n");
+
+    let mut state = XiCore::new();
+    let mut rpc_looper = RpcLoop::new(io::sink());
+    let json = make_reader(r#"{"method":"client_started","params":{}}"#);
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    state.inner().register_file_system("mem", mem_fs.clone());
+
+    let json = make_reader(format!(
+        "{}\n{}",
+        json!({"id": 0, "method": "new_view", "params": {"file_path": entry}}),
+        json!({"id": 1, "method": "new_view", "params": {"file_path": "mem://c.txt"}})
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let insert = json!({"chars": "new "});
+    let json = make_reader(format!(
+        "{}\n{}\n{}\n{}",
+        json!({"method": "edit", "params": {"view_id": "view-id-1", "method": "insert", "params": insert}}),
+        json!({"method": "save", "params": {"view_id": "view-id-1", "file_path": entry}}),
+        json!({"method": "edit", "params": {"view_id": "view-id-3", "method": "insert", "params": insert}}),
+        json!({"method": "save", "params": {"view_id": "view-id-3", "file_path": "mem://c.txt"}})
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    assert_eq!(read(&zip_fs, &entry), "new first\n");
+    assert_eq!(read(&zip_fs, &format!("{}/b.txt", archive)), "second\n");
+    assert_eq!(read(&*mem_fs, "mem://c.txt"), "new third\n");
+}
+
+#[test]
+fn test_read_only() {
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(
+        r#"{"method":"client_started","params":{}}
+{"id":0,"method":"new_view","params":{}}"#,
+    );
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    received(&mut rx);
+
+    let json = make_reader(
+        r#"{"method":"set_read_only","params":{"view_id":"view-id-1","read_only":true}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"insert","params":{"chars":"a"}}}
+{"id":1,"method":"debug_get_contents","params":{"view_id":"view-id-1"}}"#,
+    );
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let messages = received(&mut rx);
+    // the rejected edit is reported to the user
+    assert!(messages.iter().any(|m| m["method"] == json!("alert")));
+    let contents = messages.iter().find(|m| m["id"] == json!(1)).unwrap();
+    assert_eq!(contents["result"], json!(""));
+}
+
+#[test]
+fn test_session() {
+    let tmp = tempdir::TempDir::new("xi-test-session").unwrap();
+    let path = tmp.path().join("file.txt");
+    let large_path = tmp.path().join("large.txt");
+    std::fs::write(&path, "text\n").unwrap();
+    // large enough to be loaded in the background
+    let line = format!("{}\n", "a".repeat(1023));
+    std::fs::write(&large_path, line.repeat(8 * 1024)).unwrap();
+    let session_path = tmp.path().join("config/session.json");
+    let read_session = || -> Value {
+        serde_json::from_str(&std::fs::read_to_string(&session_path).unwrap()).unwrap()
+    };
+    let client_started =
+        json!({"method": "client_started", "params": {"config_dir": tmp.path().join("config")}});
+    let edit = |view_id, method, params| json!({"method": "edit", "params": {"view_id": view_id, "method": method, "params": params}});
+    let get_contents = |id, view_id| json!({"id": id, "method": "debug_get_contents", "params": {"view_id": view_id}});
+    let response = |messages: &[Value], id| {
+        messages.iter().find(|m| m["id"] == json!(id)).unwrap()["result"].clone()
+    };
+    let view_of = |messages: &[Value], file_path: Value| {
+        let restored = response(messages, 0);
+        let view = restored.as_array().unwrap().iter().find(|v| v["file_path"] == file_path);
+        view.unwrap()["view_id"].clone()
+    };
+    // runs the loop until the view of the large file is set up
+    let wait_for_load =
+        |rpc_looper: &mut RpcLoop<_>, state: &mut XiCore, rx: &mut DummyReader, view_id| {
+            let mut messages = Vec::new();
+            for _ in 0..100 {
+                rpc_looper.mainloop(|| make_reader(""), state).unwrap();
+                messages.extend(received(rx));
+                let is_update = |m: &&Value| {
+                    m["method"] == json!("update") && m["params"]["view_id"] == view_id
+                };
+                if messages.iter().any(|m| is_update(&m)) {
+                    break;
+                }
+            }
+            messages
+        };
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(format!(
+        "{}\n{}\n{}\n{}",
+        client_started,
+        json!({"id": 0, "method": "new_view", "params": {"file_path": path}}),
+        r#"{"id":1,"method":"new_view","params":{}}"#,
+        json!({"id": 2, "method": "new_view", "params": {"file_path": large_path}})
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    wait_for_load(&mut rpc_looper, &mut state, &mut rx, json!("view-id-5"));
+    let json = make_reader(format!(
+        "{}\n{}\n{}\n{}",
+        edit("view-id-3", "insert", json!({"chars": "unsaved"})),
+        edit("view-id-1", "insert", json!({"chars": "new "})),
+        r#"{"id":3,"method":"clone_view","params":{"view_id":"view-id-1"}}"#,
+        r#"{"id":4,"method":"clone_view","params":{"view_id":"view-id-5"}}"#
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let json = make_reader(format!(
+        "{}\n{}\n{}",
+        edit("view-id-7", "move_to_end_of_document", json!([])),
+        edit("view-id-8", "move_down", json!([])),
+        r#"{"id":5,"method":"save_session","params":{}}"#
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    received(&mut rx);
+    let saved = read_session();
+    let buffers = saved["buffers"].as_array().unwrap().iter();
+    let views = buffers.flat_map(|b| b["views"].as_array().unwrap().iter());
+    let saved_selections = views.map(|v| v["selection"].clone()).collect::<Vec<_>>();
+    assert!(saved_selections.contains(&json!([[4, 4]])));
+    assert!(saved_selections.contains(&json!([[9, 9]])));
+    assert!(saved_selections.contains(&json!([[1024, 1024]])));
+
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(format!(
+        "{}\n{}",
+        client_started, r#"{"id":0,"method":"restore_session","params":{}}"#
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let mut messages = received(&mut rx);
+    let large_view = view_of(&messages, json!(large_path));
+    messages.extend(wait_for_load(&mut rpc_looper, &mut state, &mut rx, large_view));
+    assert_eq!(state.inner()._test_open_views().len(), 5);
+    assert_eq!(state.inner()._test_open_editors().len(), 3);
+    // every view of each file is restored
+    let restored = response(&messages, 0);
+    let views_of = |file_path: Value| {
+        restored.as_array().unwrap().iter().filter(|v| v["file_path"] == file_path).count()
+    };
+    assert_eq!(views_of(json!(path)), 2);
+    assert_eq!(views_of(json!(large_path)), 2);
+    assert_eq!(views_of(Value::Null), 1);
+    assert!(!messages.iter().any(|m| m["method"] == json!("alert")));
+
+    let json = make_reader(format!(
+        "{}\n{}\n{}",
+        get_contents(1, view_of(&messages, json!(path))),
+        get_contents(2, view_of(&messages, Value::Null)),
+        r#"{"id":3,"method":"save_session","params":{}}"#
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    messages.extend(received(&mut rx));
+    assert_eq!(response(&messages, 1), json!("new text\n"));
+    assert_eq!(response(&messages, 2), json!("unsaved"));
+    // the restored session is saved just as it was restored
+    assert_eq!(read_session(), saved);
+
+    // unsaved changes to a file that has changed since are still restored,
+    // with a warning
+    std::fs::write(&path, "changed\n").unwrap();
+    let mut state = XiCore::new();
+    let (tx, mut rx) = test_channel();
+    let mut rpc_looper = RpcLoop::new(tx);
+    let json = make_reader(format!(
+        "{}\n{}",
+        client_started, r#"{"id":0,"method":"restore_session","params":{}}"#
+    ));
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    let mut messages = received(&mut rx);
+    let large_view = view_of(&messages, json!(large_path));
+    messages.extend(wait_for_load(&mut rpc_looper, &mut state, &mut rx, large_view));
+    let alerts = messages.iter().filter(|m| m["method"] == json!("alert")).collect::<Vec<_>>();
+    assert_eq!(alerts.len(), 1);
+    assert!(alerts[0]["params"]["msg"].as_str().unwrap().contains("file.txt"));
+    let json = make_reader(get_contents(1, view_of(&messages, json!(path))).to_string());
+    rpc_looper.mainloop(|| json, &mut state).unwrap();
+    assert_eq!(response(&received(&mut rx), 1), json!("new text\n"));
+}
+
 #[test]
 /// Tests that the runloop exits with the correct error when receiving
 /// malformed json.
@@ -252,6 +758,8 @@ const MOVEMENT_RPCS: &str = r#"{"method":"edit","params":{"view_id":"view-id-1",
 {"method":"edit","params":{"view_id":"view-id-1","method":"scroll_page_down","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"page_up_and_modify_selection","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"page_down_and_modify_selection","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"move_to_matching_bracket","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"select_to_matching_bracket","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"select_all","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"add_selection_above","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"add_selection_below","params":[]}}
@@ -275,12 +783,17 @@ const TEXT_EDIT_RPCS: &str =
 {"method":"edit","params":{"view_id":"view-id-1","method":"indent","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"outdent","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"duplicate_line","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"reflow","params":{"width":80}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"replace_next","params":[]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"replace_all","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"set_line_ending","params":{"line_ending":"crlf"}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"keep_mine","params":[]}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"take_theirs","params":[]}}
 {"id":2,"method":"edit","params":{"view_id":"view-id-1","method":"cut","params":[]}}"#;
 
 const OTHER_EDIT_RPCS: &str = r#"{"method":"edit","params":{"view_id":"view-id-1","method":"scroll","params":[0,1]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"goto_line","params":{"line":1}}}
+{"method":"edit","params":{"view_id":"view-id-1","method":"goto_offset","params":{"offset":3}}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"request_lines","params":[0,1]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"drag","params":[17,15,0]}}
 {"method":"edit","params":{"view_id":"view-id-1","method":"gesture","params":{"line": 1, "col": 2, "ty": "toggle_sel"}}}
@@ -698,6 +698,21 @@ impl Engine {
         );
         self.session = session;
     }
+
+    /// Moves the revision counter past the revisions made in this session.
+    /// The counter isn't serialized, so this must be called before editing
+    /// a deserialized `Engine`, or new revisions may collide with old ones.
+    pub fn resume_revisions(&mut self) {
+        let session = self.session;
+        let last = self
+            .revs
+            .iter()
+            .map(|rev| rev.rev_id)
+            .filter(|id| (id.session1, id.session2) == session)
+            .map(|id| id.num)
+            .max();
+        self.rev_id_counter = last.map_or(initial_revision_counter(), |num| num + 1);
+    }
 }
 
 // ======== Generic helpers
@@ -1144,6 +1159,25 @@ mod tests {
         assert_eq!("a0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz", String::from(engine.get_head()));
     }
 
+    #[test]
+    fn resume_revisions() {
+        let mut engine = Engine::new(Rope::from(TEST_STR));
+        let d1 = Delta::simple_edit(Interval::new(0,0), Rope::from("a"), TEST_STR.len());
+        let first_rev = engine.get_head_rev_id().token();
+        engine.edit_rev(1, 1, first_rev, d1);
+        let head = engine.get_head_rev_id();
+
+        let json = serde_json::to_string(&engine).unwrap();
+        let mut engine: Engine = serde_json::from_str(&json).unwrap();
+        engine.resume_revisions();
+        let d2 = Delta::simple_edit(Interval::new(0,0), Rope::from("b"), TEST_STR.len()+1);
+        engine.edit_rev(1, 2, head.token(), d2);
+        assert_ne!(head, engine.get_head_rev_id());
+        assert_eq!("ba0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz", String::from(engine.get_head()));
+        engine.undo([2].iter().cloned().collect());
+        assert_eq!("a0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz", String::from(engine.get_head()));
+    }
+
     #[test]
     fn undo_5() {
         let mut engine = Engine::new(Rope::from(TEST_STR));